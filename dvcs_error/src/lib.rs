@@ -0,0 +1,212 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Error returned by the fallible operations of every DVCS crate
+#[derive(Debug)]
+pub enum DvcsError {
+    /// No repository (`.dvcs_hidden` folder) was found for the path
+    NotARepository(PathBuf),
+    /// An operation needs a working directory but the repository at the path is bare
+    BareRepository(PathBuf),
+    /// A path that was asked for doesn't exist
+    PathNotFound(PathBuf),
+    /// A path is not inside the working directory of the repository
+    PathOutsideRepository(PathBuf),
+    /// Reading or writing a file or folder failed
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// The staging index can't be read back
+    IndexCorrupt(String),
+    /// The repository file can't be read back
+    RepositoryCorrupt(String),
+    /// An object is not in the object database
+    ObjectMissing(String),
+    /// An object doesn't match its id or can't be parsed
+    ObjectCorrupt {
+        id: String,
+        reason: String,
+    },
+    /// A revision doesn't name a commit
+    RevisionNotFound(String),
+    /// An abbreviated commit id is the start of more than one commit id
+    AmbiguousRevision {
+        revision: String,
+        candidates: Vec<String>,
+    },
+    BranchNotFound(String),
+    BranchExists(String),
+    /// A branch can't be deleted without force because HEAD doesn't contain its commits
+    BranchNotMerged(String),
+    /// An operation would write over local changes to the paths, or a commit would conclude a merge with the paths still unmerged
+    Conflict {
+        operation: String,
+        paths: Vec<String>,
+    },
+    NothingToCommit,
+    /// A remote refused to move one of its branches, `reason` says why
+    PushRejected {
+        branch: String,
+        reason: String,
+    },
+    /// A merge stopped because both sides changed the paths in ways that couldn't be merged
+    MergeConflict(Vec<String>),
+    /// The other end of a network connection reported an error or didn't follow the protocol, the message says what happened
+    Remote(String),
+    /// Another process holds the lock file of the repository, `pid` is None if the lock file doesn't say which one
+    RepositoryLocked {
+        pid: Option<u32>,
+        path: PathBuf,
+    },
+    /// An argument can't be used, the message says why
+    InvalidArgument(String),
+}
+
+/// Result of the fallible operations of every DVCS crate
+pub type Result<T> = std::result::Result<T, DvcsError>;
+
+impl DvcsError {
+    /// Creates an Io error for a path
+    pub fn io(path: impl AsRef<Path>, source: io::Error) -> DvcsError {
+        DvcsError::Io {
+            path: path.as_ref().to_path_buf(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for DvcsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DvcsError::NotARepository(path) => write!(
+                f,
+                "Not a dvcs repository (or any of the parent directories): {}",
+                path.display()
+            ),
+            DvcsError::BareRepository(path) => write!(
+                f,
+                "{} is a bare repository, this needs a working directory",
+                path.display()
+            ),
+            DvcsError::PathNotFound(path) => write!(f, "Path {} does not exist", path.display()),
+            DvcsError::PathOutsideRepository(path) => write!(
+                f,
+                "Path {} is outside of the working directory",
+                path.display()
+            ),
+            DvcsError::Io { path, source } => {
+                write!(f, "Could not access {}: {}", path.display(), source)
+            }
+            DvcsError::IndexCorrupt(reason) => write!(f, "Staging index is corrupt: {}", reason),
+            DvcsError::RepositoryCorrupt(reason) => {
+                write!(f, "Repository file is corrupt: {}", reason)
+            }
+            DvcsError::ObjectMissing(id) => {
+                write!(f, "Object {} is missing from the object database", id)
+            }
+            DvcsError::ObjectCorrupt { id, reason } => {
+                write!(f, "Object {} is corrupt: {}", id, reason)
+            }
+            DvcsError::RevisionNotFound(revision) => write!(f, "Revision {} not found", revision),
+            DvcsError::AmbiguousRevision {
+                revision,
+                candidates,
+            } => write!(
+                f,
+                "Revision {} is ambiguous, it could be:\n\t{}",
+                revision,
+                candidates.join("\n\t")
+            ),
+            DvcsError::BranchNotFound(name) => write!(f, "Branch '{}' not found", name),
+            DvcsError::BranchExists(name) => {
+                write!(f, "A branch named '{}' already exists", name)
+            }
+            DvcsError::BranchNotMerged(name) => write!(
+                f,
+                "The branch '{}' is not fully merged, use -D to delete it anyway",
+                name
+            ),
+            // * Committing is refused while files a merge couldn't merge aren't fixed yet
+            DvcsError::Conflict { operation, paths } if operation == "commit" => write!(
+                f,
+                "Committing is not possible because you have unmerged files:\n\t{}\nFix them and add them first",
+                paths.join("\n\t")
+            ),
+            DvcsError::Conflict { operation, paths } => write!(
+                f,
+                "Your local changes to the following files would be overwritten by {}:\n\t{}\nCommit or remove them first{}",
+                operation,
+                paths.join("\n\t"),
+                // * Only checking out can throw the local changes away
+                if operation == "checkout" { ", or use --force" } else { "" }
+            ),
+            DvcsError::NothingToCommit => {
+                write!(f, "Nothing to commit, stage changes with add first")
+            }
+            DvcsError::PushRejected { branch, reason } => {
+                write!(f, "Updating the remote branch '{}' was rejected, {}", branch, reason)
+            }
+            DvcsError::MergeConflict(paths) => write!(
+                f,
+                "Automatic merge failed, fix the conflicts in:\n\t{}\nthen add them and commit the result",
+                paths.join("\n\t")
+            ),
+            DvcsError::Remote(message) => write!(f, "Remote error: {}", message),
+            DvcsError::RepositoryLocked { pid, path } => {
+                match pid {
+                    Some(pid) => write!(f, "repository is locked by PID {}", pid)?,
+                    None => write!(f, "repository is locked by another process")?,
+                }
+                write!(
+                    f,
+                    ", remove {} if that process is no longer running",
+                    path.display()
+                )
+            }
+            DvcsError::InvalidArgument(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for DvcsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DvcsError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            DvcsError::BranchNotFound("feature".to_string()).to_string(),
+            "Branch 'feature' not found"
+        );
+        let conflict = DvcsError::Conflict {
+            operation: "checkout".to_string(),
+            paths: vec!["a.txt".to_string(), "b/c.txt".to_string()],
+        };
+        assert!(conflict
+            .to_string()
+            .contains("overwritten by checkout:\n\ta.txt\n\tb/c.txt\n"));
+    }
+
+    #[test]
+    fn test_io_source() {
+        let error = DvcsError::io(
+            "/missing",
+            io::Error::new(io::ErrorKind::NotFound, "not found"),
+        );
+        assert!(error.to_string().starts_with("Could not access /missing"));
+        assert!(error.source().is_some());
+        assert!(DvcsError::NothingToCommit.source().is_none());
+    }
+}
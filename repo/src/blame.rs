@@ -0,0 +1,223 @@
+use crate::RepositoryController;
+use dvcs_error::DvcsError;
+use repo_directory_hiding::line_diff::{diff, split_lines, Edit};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use storage_hiding::commit_objects::{Commit, Signature};
+use storage_hiding::repository_storage::RepositoryStorage;
+use storage_hiding::tree_objects::{EntryMode, Tree};
+
+/// Line of a file with the commit that last introduced it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlameLine {
+    /// Id of the commit that added the line or last changed it
+    pub commit_id: String,
+    pub author: Signature,
+    /// 1 based number of the line in the blamed version of the file
+    pub line_number: usize,
+    /// Contents of the line without its line ending
+    pub line: String,
+}
+
+impl RepositoryController {
+    /// Attributes every line of a file to the commit that last introduced it, walking the parents back from a commit or branch
+    ///
+    /// Lines a parent has too (according to the line diff) are passed on to that parent, the others were introduced by the commit. A line of a merge commit goes to the first parent that has it
+    ///
+    /// #### Arguments
+    ///
+    /// * `path` - `/` separated path of the file from the working directory (index key)
+    /// * `revision` - Revision to blame the file at (see `resolve_revision`), None for HEAD
+    pub fn blame(&self, path: &str, revision: Option<&str>) -> Result<Vec<BlameLine>, DvcsError> {
+        let start = self.resolve_revision(revision.unwrap_or("HEAD"))?;
+        let storage = self.storage()?;
+        let mut commits: HashMap<String, Commit> = HashMap::new();
+        commits.insert(start.clone(), Commit::read(&storage, &start)?);
+        let contents = match file_contents(&storage, &commits[&start], path)? {
+            Some(contents) => contents,
+            None => return Err(DvcsError::PathNotFound(PathBuf::from(path))),
+        };
+        let lines = split_lines(&contents);
+
+        // * Commits that still have lines to explain, as (line in their version, line in the blamed version)
+        let mut pending: HashMap<String, Vec<(usize, usize)>> = HashMap::new();
+        pending.insert(start, (0..lines.len()).map(|line| (line, line)).collect());
+        let mut blamed: Vec<Option<(String, Signature)>> = vec![None; lines.len()];
+        while !pending.is_empty() {
+            // * Newest commit first, so a commit is usually looked at once, after its children passed their lines on
+            let commit_id = pending
+                .keys()
+                .max_by_key(|commit_id| (commits[*commit_id].committer.timestamp, *commit_id))
+                .cloned()
+                .unwrap_or_default();
+            let mut remaining = pending.remove(&commit_id).unwrap_or_default();
+            let commit = commits[&commit_id].clone();
+            let current = file_contents(&storage, &commit, path)?.unwrap_or_default();
+            let current_lines = split_lines(&current);
+
+            for parent_id in &commit.parents {
+                if remaining.is_empty() {
+                    break;
+                }
+                if !commits.contains_key(parent_id) {
+                    commits.insert(parent_id.clone(), Commit::read(&storage, parent_id)?);
+                }
+                let parent = match file_contents(&storage, &commits[parent_id], path)? {
+                    Some(parent) => parent,
+                    None => continue,
+                };
+                let unchanged: HashMap<usize, usize> = diff(&split_lines(&parent), &current_lines)
+                    .into_iter()
+                    .filter_map(|edit| match edit {
+                        Edit::Equal { old, new } => Some((new, old)),
+                        _ => None,
+                    })
+                    .collect();
+                let (passed, kept) = remaining
+                    .into_iter()
+                    .partition::<Vec<(usize, usize)>, _>(|(line, _)| unchanged.contains_key(line));
+                remaining = kept;
+                pending.entry(parent_id.clone()).or_default().extend(
+                    passed
+                        .into_iter()
+                        .map(|(line, origin)| (unchanged[&line], origin)),
+                );
+            }
+            for (_, origin) in remaining {
+                blamed[origin] = Some((commit_id.clone(), commit.author.clone()));
+            }
+        }
+
+        // * Every line stops at some commit, so every line is blamed
+        Ok(blamed
+            .into_iter()
+            .zip(lines)
+            .enumerate()
+            .filter_map(|(position, (blame, line))| {
+                blame.map(|(commit_id, author)| BlameLine {
+                    commit_id,
+                    author,
+                    line_number: position + 1,
+                    line: line
+                        .trim_end_matches('\n')
+                        .trim_end_matches('\r')
+                        .to_string(),
+                })
+            })
+            .collect())
+    }
+}
+
+/// Private helper function reading a file at a commit, None if the commit doesn't have it
+fn file_contents(
+    storage: &RepositoryStorage,
+    commit: &Commit,
+    path: &str,
+) -> Result<Option<String>, DvcsError> {
+    match Tree::find(storage, &commit.tree, path)? {
+        Some(entry) if entry.mode == EntryMode::Directory => Err(DvcsError::InvalidArgument(
+            format!("{} is a folder, only files can be blamed", path),
+        )),
+        Some(entry) => Ok(Some(
+            String::from_utf8_lossy(&storage.get(&entry.id)?).to_string(),
+        )),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{commit_files, staging, test_repository};
+
+    fn blamed_ids(lines: &[BlameLine]) -> Vec<&str> {
+        lines.iter().map(|line| line.commit_id.as_str()).collect()
+    }
+
+    #[test]
+    // * Every line belongs to the commit that last changed it, even across commits that didn't touch the file
+    fn test_blame_lines() {
+        let mut repo = test_repository("blame_lines");
+        let mut staging = staging(&repo);
+        let first = commit_files(
+            &mut repo,
+            &mut staging,
+            &[("src/a.txt", "one\ntwo\nthree\n")],
+            "First",
+        );
+        let second = commit_files(
+            &mut repo,
+            &mut staging,
+            &[("src/a.txt", "one\n2\nthree\nfour\n")],
+            "Second",
+        );
+        commit_files(&mut repo, &mut staging, &[("b.txt", "b")], "Other file");
+
+        let lines = repo.blame("src/a.txt", None).unwrap();
+        assert_eq!(blamed_ids(&lines), vec![&first, &second, &first, &second]);
+        assert_eq!(lines[1].line, "2");
+        assert_eq!(lines[3].line_number, 4);
+        assert_eq!(
+            blamed_ids(&repo.blame("src/a.txt", Some(&first)).unwrap()),
+            vec![&first; 3]
+        );
+
+        assert!(matches!(
+            repo.blame("missing.txt", None),
+            Err(DvcsError::PathNotFound(_))
+        ));
+        assert!(matches!(
+            repo.blame("src", Some("master")),
+            Err(DvcsError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            repo.blame("src/a.txt", Some("unknown")),
+            Err(DvcsError::RevisionNotFound(_))
+        ));
+    }
+
+    #[test]
+    // * A merge only introduces the lines none of its parents had
+    fn test_blame_merge() {
+        let mut repo = test_repository("blame_merge");
+        let mut staging = staging(&repo);
+        let base = commit_files(&mut repo, &mut staging, &[("a.txt", "a\nb\n")], "Base");
+        let ours = commit_files(
+            &mut repo,
+            &mut staging,
+            &[("a.txt", "a\nb\nours\n")],
+            "Ours",
+        );
+        repo.create_branch("topic", Some(&base)).unwrap();
+        repo.switch_branch(&mut staging, "topic", false).unwrap();
+        let theirs = commit_files(
+            &mut repo,
+            &mut staging,
+            &[("a.txt", "theirs\na\nb\n")],
+            "Theirs",
+        );
+        let storage = repo.storage().unwrap();
+        let tree = Tree::write_from_paths(
+            &storage,
+            &[(
+                "a.txt".to_string(),
+                EntryMode::File,
+                storage.put(b"theirs\na\nb\nours\nmerge\n").unwrap(),
+            )],
+        )
+        .unwrap();
+        let merge = repo
+            .write_commit(
+                vec![ours.clone(), theirs.clone()],
+                tree,
+                "Merge".to_string(),
+            )
+            .unwrap();
+
+        let lines = repo.blame("a.txt", Some(&merge)).unwrap();
+        assert_eq!(
+            blamed_ids(&lines),
+            vec![&theirs, &base, &base, &ours, &merge]
+        );
+    }
+}
@@ -1,361 +1,1130 @@
+pub mod blame;
+pub mod log;
+pub mod merge;
+pub mod network;
+pub mod remote;
+pub mod revision;
+#[cfg(test)]
+pub(crate) mod test_support;
+
+use dvcs_error::DvcsError;
 use serde::{Deserialize, Serialize};
-use serde_json;
-use std::borrow::Borrow;
-use std::collections::HashMap;
-use std::fs::{self, File};
-use std::io::Read;
-use std::path::Path;
+use staging::staging_storage::{set_executable, StagedData, Staging};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use storage_hiding::commit_objects::{Commit, Signature};
+use storage_hiding::repository_lock::RepositoryLock;
+use storage_hiding::repository_storage::{write_atomic, RepositoryStorage};
+use storage_hiding::tree_objects::{EntryMode, Tree};
+
+/// Branch HEAD points to when a repository is created
+pub const DEFAULT_BRANCH: &str = "master";
+
+/// What HEAD points to, the current branch (which might not have any commits yet) or a commit checked out on its own
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Head {
+    Branch(String),
+    Detached(String),
+}
+
+/// Checks if a folder is a bare repository, one with a repo file marked bare in the folder itself
+pub fn is_bare_repository(folder: &Path) -> bool {
+    #[derive(Deserialize)]
+    struct RepoFile {
+        #[serde(default)]
+        bare: bool,
+    }
+    fs::read_to_string(folder.join("repo.json"))
+        .ok()
+        .and_then(|contents| serde_json::from_str::<RepoFile>(&contents).ok())
+        .is_some_and(|repo_file| repo_file.bare)
+}
+
+impl Default for Head {
+    fn default() -> Head {
+        Head::Branch(DEFAULT_BRANCH.to_string())
+    }
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct RepositoryController {
-    // vector to store the commit history for the repository (could be a hashSet as well)
+    // vector to store the ids of every commit in the repository in the order they were made
     commit_history: Vec<String>,
 
-    // map to store the head commits for each branch in the repository
+    // map to store the id of the head commit for each branch in the repository
     branch_heads: HashMap<String, String>,
 
-    // map to store the commit history for each file in the repository
-    file_history: HashMap<String, Vec<String>>,
+    // map to store the commit each tag points to, repo files saved before tags existed have none
+    #[serde(default)]
+    tags: HashMap<String, String>,
+
+    // map to store the url of each remote repository by its name
+    #[serde(default)]
+    remotes: HashMap<String, String>,
+
+    // map to store the last known tip of each branch of the remotes, keyed `<remote>/<branch>`
+    #[serde(default)]
+    remote_branches: HashMap<String, String>,
+
+    // current branch or detached commit, repo files saved before branches existed are on the default branch
+    #[serde(default)]
+    head: Head,
+
+    // bare repositories keep the repo file and objects in their folder and have no working directory or staging index
+    #[serde(default)]
+    bare: bool,
+
+    // commit being merged into HEAD while a merge waits for its conflicts to be fixed, it becomes the second parent of the next commit
+    #[serde(default)]
+    merge_head: Option<String>,
 
     // dvcs_hidden path
     dvcs_hidden: String,
+
+    // folder holding the repo file and objects when it isn't the default one (see `open_hidden`), never saved as the repository can be moved
+    #[serde(skip)]
+    hidden_folder: Option<String>,
 }
 
 impl RepositoryController {
-    /// Creates dvcs hidden folder and repo
-    pub fn new(dvcs_hidden: &str) -> Result<RepositoryController, String> {
-        let dvcs_hidden = dvcs_hidden;
+    /// Creates dvcs hidden folder and repo, or opens the bare repository of the folder
+    pub fn new(dvcs_hidden: &str) -> Result<RepositoryController, DvcsError> {
         match Path::new(&dvcs_hidden).try_exists() {
             // * Repository cannot be found with given dvcs hidden path
-            Err(_) => Err("Could not find dvcs directory files".to_string()),
-            Ok(false) => Err("DVCS path doesn't exist".to_string()),
+            Err(e) => Err(DvcsError::io(dvcs_hidden, e)),
+            Ok(false) => Err(DvcsError::NotARepository(PathBuf::from(dvcs_hidden))),
+            // * A bare repository is read from the folder itself, it is never created here
+            Ok(true) if is_bare_repository(Path::new(dvcs_hidden)) => {
+                Self::read_from_repo_from_file(dvcs_hidden, dvcs_hidden)
+            }
             // * Path exists create repo directory
             Ok(true) => {
-                let repo_file_path = dvcs_hidden.to_owned() + "/.dvcs_hidden/repo.json";
+                let hidden_folder = dvcs_hidden.to_owned() + "/.dvcs_hidden";
+                let repo_file_path = hidden_folder.clone() + "/repo.json";
                 match Path::new(&repo_file_path).try_exists() {
-                    Ok(_) => Self::read_from_repo_from_file(dvcs_hidden),
-                    Err(_) => {
-                        match std::fs::create_dir(dvcs_hidden.to_owned() + "/.dvcs_hidden") {
-                            Err(_) => Err("Error with creating repo".to_string()),
-                            Ok(_) => {
-                                let repo_path =
-                                    &(dvcs_hidden.to_owned() + "/.dvcs_hidden/repo.json");
-                                if File::create(repo_path).is_err() {
-                                    return Err("Could not create repo file".to_string());
-                                };
-                                return Ok(RepositoryController {
-                                    // vector to store the commit history for the repository (could be a hashSet as well)
-                                    commit_history: vec![],
-                                    // map to store the head commits for each branch in the repository
-                                    branch_heads: HashMap::new(),
-
-                                    // map to store the commit history for each file in the repository
-                                    file_history: HashMap::new(),
-
-                                    dvcs_hidden: dvcs_hidden.to_string(),
-                                });
-                            }
-                        }
+                    Ok(true) => Self::read_from_repo_from_file(dvcs_hidden, &hidden_folder),
+                    Err(e) => Err(DvcsError::io(&repo_file_path, e)),
+                    // * No repo file yet, create it (and the hidden folder if `init` didn't already)
+                    Ok(false) => {
+                        fs::create_dir_all(&hidden_folder)
+                            .map_err(|e| DvcsError::io(&hidden_folder, e))?;
+                        let repository = Self::empty(dvcs_hidden);
+                        repository.save_locally()?;
+                        Ok(repository)
                     }
                 }
             }
         }
     }
 
+    /// Opens the repository of a dvcs hidden folder with any name, like the one `DVCS_DIR` points to, nothing is created
+    ///
+    /// A hidden folder without a repo file yet (nothing was committed) gives an empty repository that is only saved once something changes
+    ///
+    /// #### Arguments
+    ///
+    /// * `hidden_folder` - Folder with the repo file and the objects
+    /// * `working_directory` - Working directory of the repository, None for a bare repository
+    pub fn open_hidden(
+        hidden_folder: &str,
+        working_directory: Option<&str>,
+    ) -> Result<RepositoryController, DvcsError> {
+        if !Path::new(hidden_folder).is_dir() {
+            return Err(DvcsError::NotARepository(PathBuf::from(hidden_folder)));
+        }
+        let dvcs_hidden = working_directory.unwrap_or(hidden_folder);
+        let mut repository = match Path::new(hidden_folder).join("repo.json").exists() {
+            true => Self::read_from_repo_from_file(dvcs_hidden, hidden_folder)?,
+            false => Self::empty(dvcs_hidden),
+        };
+        repository.bare = working_directory.is_none();
+        repository.hidden_folder = Some(hidden_folder.to_string());
+        Ok(repository)
+    }
+
+    /// Creates a bare repository in a folder, with the repo file and the objects in the folder itself and no working directory
+    ///
+    /// The folder has to exist, an existing bare repository is opened again and a repository with a working directory is refused
+    pub fn init_bare(folder: &str) -> Result<RepositoryController, DvcsError> {
+        if !Path::new(folder).is_dir() {
+            return Err(DvcsError::PathNotFound(PathBuf::from(folder)));
+        }
+        if is_bare_repository(Path::new(folder)) {
+            return Self::new(folder);
+        }
+        if Path::new(folder).join(".dvcs_hidden").exists() {
+            return Err(DvcsError::InvalidArgument(format!(
+                "{} already has a repository with a working directory",
+                folder
+            )));
+        }
+        let mut repository = Self::empty(folder);
+        repository.bare = true;
+        repository.storage()?;
+        repository.save_locally()?;
+        Ok(repository)
+    }
+
+    /// Private helper function creating a repository without any commits or branches
+    fn empty(dvcs_hidden: &str) -> RepositoryController {
+        RepositoryController {
+            // vector to store the commit history for the repository (could be a hashSet as well)
+            commit_history: vec![],
+            // map to store the head commits for each branch in the repository
+            branch_heads: HashMap::new(),
+            tags: HashMap::new(),
+            remotes: HashMap::new(),
+            remote_branches: HashMap::new(),
+
+            head: Head::default(),
+            bare: false,
+            merge_head: None,
+
+            dvcs_hidden: dvcs_hidden.to_string(),
+            hidden_folder: None,
+        }
+    }
+
+    /// Gets the folder holding the repo file and the objects, the bare repository folder itself or the dvcs hidden folder of the working directory
+    pub fn hidden_folder(&self) -> String {
+        match (&self.hidden_folder, self.bare) {
+            (Some(hidden_folder), _) => hidden_folder.clone(),
+            (None, true) => self.dvcs_hidden.clone(),
+            (None, false) => self.dvcs_hidden.to_owned() + "/.dvcs_hidden",
+        }
+    }
+
+    /// True for a repository without a working directory (see `init_bare`)
+    pub fn is_bare(&self) -> bool {
+        self.bare
+    }
+
+    /// Private helper function refusing operations that need a working directory in a bare repository
+    fn require_working_directory(&self) -> Result<(), DvcsError> {
+        match self.bare {
+            true => Err(DvcsError::BareRepository(PathBuf::from(&self.dvcs_hidden))),
+            false => Ok(()),
+        }
+    }
+
     /// Saves current repo structure to file
-    fn save_locally(&self) -> Result<(), String> {
-        match fs::File::create(self.dvcs_hidden.to_owned() + "/.dvcs_hidden/repo.json") {
-            Ok(file) => {
-                return match serde_json::to_writer(file, &self) {
-                    Ok(_) => Ok(()), // * Successfully written to file
-                    Err(_) => Err("Could not write to repo file".to_string()),
-                };
+    fn save_locally(&self) -> Result<(), DvcsError> {
+        let hidden_folder = self.hidden_folder();
+        let repo_file_path = hidden_folder.clone() + "/repo.json";
+        let contents =
+            serde_json::to_vec(&self).map_err(|e| DvcsError::io(&repo_file_path, e.into()))?;
+        // * Another process can't save at the same time, and a crash leaves the previous repo file
+        let _lock = RepositoryLock::acquire(&hidden_folder)?;
+        write_atomic(&repo_file_path, &contents)
+    }
+
+    /// Private helper function to read index struct from the repo file in a hidden folder
+    fn read_from_repo_from_file(
+        dvcs_hidden: &str,
+        hidden_folder: &str,
+    ) -> Result<RepositoryController, DvcsError> {
+        let repo_file_path = hidden_folder.to_owned() + "/repo.json";
+        let contents =
+            fs::read_to_string(&repo_file_path).map_err(|e| DvcsError::io(&repo_file_path, e))?;
+        // * Older versions created an empty file before the first save, it is an empty repository
+        if contents.is_empty() {
+            return Ok(Self::empty(dvcs_hidden));
+        }
+        // * Otherwise, return structure created from file
+        let mut deserialized = serde_json::from_str::<RepositoryController>(&contents)
+            .map_err(|e| DvcsError::RepositoryCorrupt(e.to_string()))?;
+        // * The repository is wherever it was loaded from, even if it was moved since it was saved
+        deserialized.dvcs_hidden = dvcs_hidden.to_string();
+        Ok(deserialized)
+    }
+
+    /// Opens the object database inside the dvcs hidden folder
+    fn storage(&self) -> Result<RepositoryStorage, DvcsError> {
+        RepositoryStorage::new(&self.hidden_folder())
+    }
+
+    /// Reads the commit stored under an id
+    pub fn get_commit(&self, commit_id: &str) -> Result<Commit, DvcsError> {
+        if !self.commit_history.iter().any(|id| id == commit_id) {
+            return Err(DvcsError::RevisionNotFound(commit_id.to_string()));
+        }
+        Commit::read(&self.storage()?, commit_id)
+    }
+
+    /// Private helper function writing a commit of a tree with the given parents and adding it to the commit history
+    fn write_commit(
+        &mut self,
+        parents: Vec<String>,
+        tree_id: String,
+        commit_message: String,
+    ) -> Result<String, DvcsError> {
+        let signature = Signature::from_environment();
+        let commit = Commit {
+            tree: tree_id,
+            parents,
+            author: signature.clone(),
+            committer: signature,
+            message: commit_message,
+        };
+        let commit_id = commit.write(&self.storage()?)?;
+
+        // adds the commit to the commit history
+        self.commit_history.push(commit_id.clone());
+        Ok(commit_id)
+    }
+
+    /// Commits everything staged on top of HEAD, moving the current branch (or a detached HEAD) to the new commit, and returns its id
+    ///
+    /// The staged files become the repository version in the index, so nothing is left to commit afterwards. While a merge is waiting for its conflicts to be fixed the commit being merged is the second parent, and committing is refused until every unmerged file was staged or removed again
+    ///
+    /// #### Arguments
+    ///
+    /// * `staging` - Staging index holding the files to commit
+    /// * `commit_message` - Why the changes were made, can't be empty
+    pub fn commit_staged(
+        &mut self,
+        staging: &mut Staging,
+        commit_message: &str,
+    ) -> Result<String, DvcsError> {
+        self.require_working_directory()?;
+        if commit_message.trim().is_empty() {
+            return Err(DvcsError::InvalidArgument(
+                "Aborting commit due to empty commit message".to_string(),
+            ));
+        }
+        // * Files a merge couldn't merge would be committed with ours' contents or their conflict markers
+        let unmerged = staging.unmerged_paths();
+        if !unmerged.is_empty() {
+            return Err(DvcsError::Conflict {
+                operation: "commit".to_string(),
+                paths: unmerged,
+            });
+        }
+        let storage = self.storage()?;
+        let tree_id = staging.write_tree(&storage)?;
+        let parent = self.head_commit();
+        // * Committing the same snapshot as HEAD (or an empty first snapshot) wouldn't record anything, unless it concludes a merge
+        let parent_tree = match &parent {
+            Some(parent) => Some(self.get_commit(parent)?.tree),
+            None => Some(Tree::default().write(&storage)?),
+        };
+        if parent_tree == Some(tree_id.clone()) && self.merge_head.is_none() {
+            return Err(DvcsError::NothingToCommit);
+        }
+
+        let parents = parent.into_iter().chain(self.merge_head.take()).collect();
+        let commit_id = self.write_commit(parents, tree_id, commit_message.to_string())?;
+        match &self.head {
+            Head::Branch(name) => {
+                self.branch_heads.insert(name.clone(), commit_id.clone());
             }
-            Err(_) => return Err("Could not recreate repo file".to_string()),
-        }
-    }
-
-    /// Private helper function to read index struct from file
-    fn read_from_repo_from_file(dvcs_hidden: &str) -> Result<RepositoryController, String> {
-        // * Open the json file
-        match File::open(dvcs_hidden.to_owned() + "/.dvcs_hidden/repo.json") {
-            Ok(repo_file) => {
-                let mut contents = String::new();
-                // * Creating string from contents in index file
-                repo_file
-                    .borrow()
-                    .read_to_string(&mut contents)
-                    .or_else(|_| {
-                        return Err("No content found in repo file".to_string());
-                    })
-                    .unwrap();
-                // * Check if file is empty, create empty hashmap
-                if contents.to_string().len() == 0 {
-                    return Ok(RepositoryController {
-                        // vector to store the commit history for the repository (could be a hashSet as well)
-                        commit_history: vec![],
-                        // map to store the head commits for each branch in the repository
-                        branch_heads: HashMap::new(),
-
-                        // map to store the commit history for each file in the repository
-                        file_history: HashMap::new(),
-
-                        dvcs_hidden: dvcs_hidden.to_string(),
-                    });
-                }
-                // * Otherwise, return structure created from file, TODO: safe way to check if this works?
-                if let Ok(deserialized) = serde_json::from_str(&contents.to_string()) {
-                    return Ok(deserialized);
-                } else {
-                    return Err("Could not deserialize repo file".to_string());
+            Head::Detached(_) => self.head = Head::Detached(commit_id.clone()),
+        }
+        self.save_locally()?;
+        staging.promote_staged_files()?;
+        Ok(commit_id)
+    }
+
+    /// Gets what HEAD points to
+    pub fn head(&self) -> &Head {
+        &self.head
+    }
+
+    /// Gets the name of the current branch, None if HEAD is detached
+    pub fn current_branch(&self) -> Option<&str> {
+        match &self.head {
+            Head::Branch(name) => Some(name),
+            Head::Detached(_) => None,
+        }
+    }
+
+    /// Gets the id of the commit HEAD points to, None if the current branch has no commits yet
+    pub fn head_commit(&self) -> Option<String> {
+        match &self.head {
+            Head::Branch(name) => self.branch_heads.get(name).cloned(),
+            Head::Detached(commit_id) => Some(commit_id.clone()),
+        }
+    }
+
+    /// Lists every branch with the id of its tip commit, sorted by name
+    pub fn list_branches(&self) -> Vec<(String, String)> {
+        let mut branches: Vec<(String, String)> = self
+            .branch_heads
+            .iter()
+            .map(|(name, tip)| (name.clone(), tip.clone()))
+            .collect();
+        branches.sort();
+        branches
+    }
+
+    /// Private helper function to check a branch or tag name, names can't be empty, be HEAD, start with `-`, contain whitespace or characters used in revisions
+    fn check_ref_name(name: &str, kind: &str) -> Result<(), DvcsError> {
+        if name.is_empty()
+            || name == "HEAD"
+            || name.starts_with('-')
+            || name.starts_with('/')
+            || name.ends_with('/')
+            || name.contains("..")
+            || name.contains("//")
+            || name
+                .chars()
+                .any(|c| c.is_whitespace() || c.is_control() || "~^:?*[\\".contains(c))
+        {
+            return Err(DvcsError::InvalidArgument(format!(
+                "'{}' is not a valid {} name",
+                name, kind
+            )));
+        }
+        Ok(())
+    }
+
+    /// Creates a branch at a commit, or at the commit HEAD points to, and returns the id of its tip
+    ///
+    /// #### Arguments
+    ///
+    /// * `name` - Name of the new branch
+    /// * `start` - Revision of the commit the branch starts at (see `resolve_revision`), None for HEAD
+    pub fn create_branch(&mut self, name: &str, start: Option<&str>) -> Result<String, DvcsError> {
+        Self::check_ref_name(name, "branch")?;
+        if self.branch_heads.contains_key(name) {
+            return Err(DvcsError::BranchExists(name.to_string()));
+        }
+        let tip = match start {
+            Some(revision) => self.resolve_revision(revision)?,
+            None => match self.head_commit() {
+                Some(commit_id) => commit_id,
+                None => {
+                    return Err(DvcsError::InvalidArgument(
+                        "Cannot create a branch before the first commit".to_string(),
+                    ))
                 }
+            },
+        };
+        self.branch_heads.insert(name.to_string(), tip.clone());
+        self.save_locally()?;
+        Ok(tip)
+    }
+
+    /// Checks if a commit is reachable from another one by following parents, a commit is its own ancestor
+    pub fn is_ancestor(&self, ancestor: &str, descendant: &str) -> Result<bool, DvcsError> {
+        let storage = self.storage()?;
+        let mut visited = BTreeSet::new();
+        let mut pending = vec![descendant.to_string()];
+        while let Some(commit_id) = pending.pop() {
+            if commit_id == ancestor {
+                return Ok(true);
             }
-            Err(_) => {
-                return Err("Could not open repo file".to_string());
+            if visited.insert(commit_id.clone()) {
+                pending.extend(Commit::read(&storage, &commit_id)?.parents);
             }
         }
+        Ok(false)
     }
 
-    // commits the current state of the repository to storage
-    fn commit(&mut self, branch: &str, commit_message: String, files: Vec<(String, String)>) {
-        // updates the head commit for the specified branch
-        self.branch_heads
-            .insert(branch.to_string(), commit_message.clone());
+    /// Deletes a branch, only if its commits are all reachable from HEAD unless `force` is set
+    pub fn delete_branch(&mut self, name: &str, force: bool) -> Result<(), DvcsError> {
+        let tip = match self.branch_heads.get(name) {
+            Some(tip) => tip.clone(),
+            None => return Err(DvcsError::BranchNotFound(name.to_string())),
+        };
+        if self.current_branch() == Some(name) {
+            return Err(DvcsError::InvalidArgument(format!(
+                "Cannot delete branch '{}' checked out",
+                name
+            )));
+        }
+        if !force {
+            let merged = match self.head_commit() {
+                Some(head) => self.is_ancestor(&tip, &head)?,
+                None => false,
+            };
+            if !merged {
+                return Err(DvcsError::BranchNotMerged(name.to_string()));
+            }
+        }
+        self.branch_heads.remove(name);
+        self.save_locally()
+    }
 
-        // adds the commit message to the commit history
-        self.commit_history.push(commit_message.clone());
+    /// Renames a branch, HEAD follows the branch if it is the current one
+    pub fn rename_branch(&mut self, old_name: &str, new_name: &str) -> Result<(), DvcsError> {
+        Self::check_ref_name(new_name, "branch")?;
+        if self.branch_heads.contains_key(new_name) {
+            return Err(DvcsError::BranchExists(new_name.to_string()));
+        }
+        match self.branch_heads.remove(old_name) {
+            Some(tip) => {
+                self.branch_heads.insert(new_name.to_string(), tip);
+            }
+            // * The current branch can be renamed before its first commit
+            None if self.current_branch() == Some(old_name) => {}
+            None => return Err(DvcsError::BranchNotFound(old_name.to_string())),
+        }
+        if self.current_branch() == Some(old_name) {
+            self.head = Head::Branch(new_name.to_string());
+        }
+        self.save_locally()
+    }
+
+    /// Lists every tag with the id of its commit, sorted by name
+    pub fn list_tags(&self) -> Vec<(String, String)> {
+        let mut tags: Vec<(String, String)> = self
+            .tags
+            .iter()
+            .map(|(name, commit_id)| (name.clone(), commit_id.clone()))
+            .collect();
+        tags.sort();
+        tags
+    }
 
-        // updates the file history with the new commit
-        for (file_path, file_content) in files {
-            self.file_history
-                .entry(file_path)
-                .or_insert_with(|| vec![])
-                .push(format!("{}:{}", commit_message, file_content));
+    /// Creates a tag at the commit of a revision (see `resolve_revision`), or at HEAD, and returns the id of the commit
+    pub fn create_tag(&mut self, name: &str, revision: Option<&str>) -> Result<String, DvcsError> {
+        Self::check_ref_name(name, "tag")?;
+        if self.tags.contains_key(name) {
+            return Err(DvcsError::InvalidArgument(format!(
+                "A tag named '{}' already exists",
+                name
+            )));
         }
-        self.save_locally();
+        let commit_id = self.resolve_revision(revision.unwrap_or("HEAD"))?;
+        self.tags.insert(name.to_string(), commit_id.clone());
+        self.save_locally()?;
+        Ok(commit_id)
     }
 
-    // return the commit log for the repository
-    fn log(&self) -> Vec<String> {
-        self.commit_history.clone()
+    /// Deletes a tag, the commit it pointed to is kept
+    pub fn delete_tag(&mut self, name: &str) -> Result<(), DvcsError> {
+        if self.tags.remove(name).is_none() {
+            return Err(DvcsError::RevisionNotFound(name.to_string()));
+        }
+        self.save_locally()
     }
 
-    // return the head commits for all branches in the repository
-    fn heads(&self) -> Vec<String> {
-        self.branch_heads.values().map(|s| s.clone()).collect()
+    /// Switches to a branch, checking out its tip and making it the current branch
+    ///
+    /// #### Arguments
+    ///
+    /// * `staging` - Staging index of the working directory being checked out into
+    /// * `name` - Name of the branch to switch to
+    /// * `force` - Overwrite local modifications instead of refusing to switch
+    pub fn switch_branch(
+        &mut self,
+        staging: &mut Staging,
+        name: &str,
+        force: bool,
+    ) -> Result<(), DvcsError> {
+        let tip = match self.branch_heads.get(name) {
+            Some(tip) => tip.clone(),
+            None => return Err(DvcsError::BranchNotFound(name.to_string())),
+        };
+        self.checkout(staging, &tip, force)?;
+        // * A merge waiting for its conflicts to be fixed belongs to the branch that was left
+        self.merge_head = None;
+        self.head = Head::Branch(name.to_string());
+        self.save_locally()
     }
 
-    // retrieves the full commit history for the repository
-    fn retrieve_all_history(&self) -> Vec<String> {
-        self.commit_history.clone()
+    /// Checks out the commit of a revision (see `resolve_revision`) without a branch and returns its id, HEAD is detached until a branch is switched to
+    pub fn detach_head(
+        &mut self,
+        staging: &mut Staging,
+        revision: &str,
+        force: bool,
+    ) -> Result<String, DvcsError> {
+        let commit_id = self.resolve_revision(revision)?;
+        self.checkout(staging, &commit_id, force)?;
+        self.merge_head = None;
+        self.head = Head::Detached(commit_id.clone());
+        self.save_locally()?;
+        Ok(commit_id)
     }
 
-    // retrieves the commit history for a specific file in the repository
-    fn retrieve_commit_history(&self, path: &str) -> Vec<String> {
-        self.file_history
-            .get(path)
-            .map(|s| s.clone())
-            .unwrap_or_else(|| vec![])
+    /// Checks out a commit, the working directory and the staging index are set to the snapshot of the commit
+    ///
+    /// Files that differ between the current snapshot and the commit are written, updated or deleted. Checking out refuses to overwrite files with local modifications (changes not staged, or staged but not committed) unless `force` is set, files that are the same in both snapshots keep their local modifications
+    ///
+    /// #### Arguments
+    ///
+    /// * `staging` - Staging index of the working directory being checked out into
+    /// * `commit_id` - Id of the commit to check out
+    /// * `force` - Overwrite local modifications instead of refusing to check out
+    ///
+    /// HEAD is left where it is, see `switch_branch` and `detach_head`
+    pub fn checkout(
+        &mut self,
+        staging: &mut Staging,
+        commit_id: &str,
+        force: bool,
+    ) -> Result<(), DvcsError> {
+        self.require_working_directory()?;
+        let storage = self.storage()?;
+        let target = Tree::flatten(&storage, &self.get_commit(commit_id)?.tree)?;
+        let staged_file = |data: &Option<StagedData>| {
+            data.as_ref()
+                .filter(|data| data.is_file)
+                .map(|data| data.sha1.clone())
+        };
+
+        // * Every file in the commit or tracked by the index might have to change
+        let mut paths: BTreeSet<String> = target.keys().cloned().collect();
+        for (key, comparison) in staging.get_index() {
+            if staged_file(&comparison.staging).is_some()
+                || staged_file(&comparison.repository_version).is_some()
+            {
+                paths.insert(key.clone());
+            }
+        }
+
+        let mut changes = vec![];
+        let mut conflicts = vec![];
+        for path in paths {
+            let comparison = staging.get_index().get(&path).cloned().unwrap_or_default();
+            let repository_version = staged_file(&comparison.repository_version);
+            let staged = staged_file(&comparison.staging);
+            let working = staging.get_working_file_hash(&path);
+            let wanted = target.get(&path).map(|entry| entry.id.clone());
+            // * A file whose executable bit is all that changed is checked out again too
+            let mode_changed = target.get(&path).is_some_and(|entry| {
+                comparison
+                    .repository_version
+                    .as_ref()
+                    .is_some_and(|data| data.executable != (entry.mode == EntryMode::Executable))
+            });
+
+            // * Tracked files are modified if they changed since they were staged or committed, untracked files if the commit has something else at their path
+            let modified = if staged.is_some() || repository_version.is_some() {
+                working != staged || staged != repository_version
+            } else {
+                working.is_some() && working != wanted
+            };
+            if wanted == repository_version && !mode_changed && !(force && modified) {
+                continue;
+            }
+            if modified && !force {
+                conflicts.push(path);
+            } else {
+                let entry = target.get(&path).cloned();
+                changes.push((path, entry));
+            }
+        }
+        if !conflicts.is_empty() {
+            return Err(DvcsError::Conflict {
+                operation: "checkout".to_string(),
+                paths: conflicts,
+            });
+        }
+
+        for (path, _) in &changes {
+            Self::check_tree_path(path)?;
+        }
+        // * Write the new snapshot to the working directory
+        for (path, wanted) in &changes {
+            let file_path = PathBuf::from(staging.working_path(path));
+            match wanted {
+                Some(entry) => {
+                    if let Some(parent) = file_path.parent() {
+                        fs::create_dir_all(parent).map_err(|e| DvcsError::io(parent, e))?;
+                    }
+                    fs::write(&file_path, storage.get(&entry.id)?)
+                        .map_err(|e| DvcsError::io(&file_path, e))?;
+                    set_executable(
+                        &file_path.display().to_string(),
+                        entry.mode == EntryMode::Executable,
+                    )?;
+                }
+                None => {
+                    if file_path.is_file() {
+                        fs::remove_file(&file_path).map_err(|e| DvcsError::io(&file_path, e))?;
+                    }
+                    Self::remove_empty_folders(&file_path, staging.get_working_directory());
+                }
+            }
+        }
+        let changed_paths: Vec<String> = changes.into_iter().map(|(path, _)| path).collect();
+        staging.set_checked_out_files(&changed_paths)
     }
 
-    // concatenates two or more commits together
-    fn concatenate(&mut self, branch: &str, commit_hashes: Vec<String>) {
-        let mut new_commit_message = String::new();
-        for commit_hash in commit_hashes {
-            let commit_message = self
-                .commit_history
-                .iter()
-                .find(|m| **m == commit_hash)
-                .map(|s| s.clone())
-                .unwrap_or_else(|| String::new());
-            new_commit_message.push_str(&commit_message);
-            new_commit_message.push('\n');
+    /// Private helper function refusing a path of a commit that isn't a plain relative path, writing it could leave the working directory or reach the dvcs hidden folder
+    fn check_tree_path(path: &str) -> Result<(), DvcsError> {
+        let plain = !path.is_empty()
+            && Path::new(path).components().all(
+                |component| matches!(component, Component::Normal(name) if name != ".dvcs_hidden"),
+            );
+        match plain {
+            true => Ok(()),
+            false => Err(DvcsError::PathOutsideRepository(PathBuf::from(path))),
         }
+    }
 
-        // create a new commit with the concatenated messages
-        self.commit(branch, new_commit_message, vec![])
+    /// Private helper function to delete the folders left empty by a deleted file, stopping at the working directory
+    fn remove_empty_folders(file_path: &Path, working_directory: &str) {
+        let working_directory = fs::canonicalize(working_directory).unwrap_or_default();
+        let mut folder = file_path.parent();
+        while let Some(path) = folder {
+            match fs::canonicalize(path) {
+                Ok(canonical) if canonical != working_directory => {
+                    // * Only succeeds if the folder is empty
+                    if fs::remove_dir(path).is_err() {
+                        return;
+                    }
+                }
+                _ => return,
+            }
+            folder = path.parent();
+        }
     }
-    // checks out a specific commit from the repository
-    fn checkout(&mut self, branch: &str, commit_hash: &str) {
-        let commit_message = self
-            .commit_history
-            .iter()
-            .find(|m| **m == commit_hash)
-            .map(|s| s.clone())
-            .unwrap_or_else(|| String::new());
-
-        // check out the commit by creating a new commit with the same message
-        self.commit(branch, commit_message, vec![]);
-    }
-    // PS. This function takes the name of the branch to checkout as an argument
-    // and retrieves the head commit for that branch from the branch_heads map.
-    // It then creates a new commit with the same message as the head commit,
-    // checking out that branch in the repository. Please let me know if there is
-    // anything I should've done differently in this function.
 }
 
 // tests for the repository controller
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::{commit_file, commit_files, staging, test_repository};
 
-    #[test]
-    fn temp_test_fn() {
-        let mut repo = RepositoryController::new("./");
-        println!("{:?}", &repo);
-
-        //repo.create_repo();
-        repo.as_mut().unwrap().commit(
-            "master3",
-            "Initial commit3".to_string(),
-            vec![("README.md3".to_string(), "Hello, world!3".to_string())],
-        );
+    // lists the ids of the commits reachable from HEAD
+    fn log_ids(repo: &RepositoryController) -> Vec<String> {
+        repo.log(Default::default())
+            .unwrap()
+            .map(|entry| entry.unwrap().id)
+            .collect()
     }
 
-    #[test]
-    fn test_commit() {
-        let mut repo = RepositoryController {
-            commit_history: Vec::new(),
-            branch_heads: HashMap::new(),
-            file_history: HashMap::new(),
-            dvcs_hidden: String::from("./"),
-        };
-        repo.commit("master", "Initial commit".to_string(), vec![]);
-        assert_eq!(repo.log(), vec!["Initial commit"]);
+    // reads the message of a commit
+    fn message(repo: &RepositoryController, commit_id: &str) -> String {
+        repo.get_commit(commit_id).unwrap().message
     }
 
     #[test]
-    fn test_log() {
-        let mut repo = RepositoryController {
-            commit_history: Vec::new(),
-            branch_heads: HashMap::new(),
-            file_history: HashMap::new(),
-            dvcs_hidden: String::from("./"),
-        };
-        repo.commit("master", "Initial commit".to_string(), vec![]);
-        repo.commit("master", "Second commit".to_string(), vec![]);
-        assert_eq!(repo.log(), vec!["Initial commit", "Second commit"]);
+    fn test_commit() {
+        let mut repo = test_repository("commit");
+        let commit_id = commit_file(&mut repo, "README.md", "Hello, world!");
+        assert_eq!(log_ids(&repo), vec![commit_id.clone()]);
+        assert_eq!(message(&repo, &commit_id), "README.md");
+        assert!(repo.get_commit(&commit_id).unwrap().parents.is_empty());
     }
 
     #[test]
-    fn test_heads() {
-        let mut repo = RepositoryController {
-            commit_history: Vec::new(),
-            branch_heads: HashMap::new(),
-            file_history: HashMap::new(),
-            dvcs_hidden: String::from("./"),
-        };
-        repo.commit("master", "Initial commit".to_string(), vec![]);
-        repo.commit("master", "Second commit".to_string(), vec![]);
-        assert_eq!(repo.heads(), vec!["Second commit"]);
+    fn test_commit_same_message() {
+        let mut repo = test_repository("commit_same_message");
+        let mut staging = staging(&repo);
+        let first = commit_files(&mut repo, &mut staging, &[("a.txt", "a")], "Fix");
+        let second = commit_files(&mut repo, &mut staging, &[("a.txt", "b")], "Fix");
+
+        // commits with the same message are still different commits
+        assert_ne!(first, second);
+        assert_eq!(repo.get_commit(&second).unwrap().parents, vec![first]);
     }
 
     #[test]
-    fn test_retrieve_all_history() {
-        let mut repo = RepositoryController {
-            commit_history: Vec::new(),
-            branch_heads: HashMap::new(),
-            file_history: HashMap::new(),
-            dvcs_hidden: String::from("./"),
-        };
-        repo.commit("master", "Initial commit".to_string(), vec![]);
-        repo.commit("master", "Second commit".to_string(), vec![]);
+    fn test_commit_snapshots_tree() {
+        let mut repo = test_repository("commit_snapshots_tree");
+        let mut staging = staging(&repo);
+        let first = commit_files(
+            &mut repo,
+            &mut staging,
+            &[
+                ("README.md", "Hello, world!"),
+                ("src/main.rs", "fn main() {}"),
+            ],
+            "Initial commit",
+        );
+        let second = commit_files(
+            &mut repo,
+            &mut staging,
+            &[("README.md", "Another change")],
+            "Second commit",
+        );
+
+        // the second commit snapshots the whole repository, not only the file it changed
+        let storage = repo.storage().unwrap();
+        let first = repo.get_commit(&first).unwrap().tree;
+        let second = repo.get_commit(&second).unwrap().tree;
+        let files = Tree::flatten(&storage, &second).unwrap();
+        assert_eq!(
+            files.keys().collect::<Vec<&String>>(),
+            vec!["README.md", "src/main.rs"]
+        );
         assert_eq!(
-            repo.retrieve_all_history(),
-            vec!["Initial commit", "Second commit"]
+            storage.get(&files.get("README.md").unwrap().id).unwrap(),
+            b"Another change".to_vec()
+        );
+
+        // the untouched directory keeps its subtree id
+        assert_eq!(
+            Tree::find(&storage, &first, "src").unwrap(),
+            Tree::find(&storage, &second, "src").unwrap()
         );
     }
 
     #[test]
-    fn test_retrieve_commit_history() {
-        let mut repo = RepositoryController {
-            commit_history: Vec::new(),
-            branch_heads: HashMap::new(),
-            file_history: HashMap::new(),
-            dvcs_hidden: String::from("./"),
-        };
+    fn test_log() {
+        let mut repo = test_repository("log");
+        let first = commit_file(&mut repo, "a.txt", "a");
+        let second = commit_file(&mut repo, "b.txt", "b");
+        // newest first
+        assert_eq!(log_ids(&repo), vec![second, first]);
+    }
+
+    #[test]
+    // * Repo files of older versions with a per file history (replaced by blame) still load, and the history isn't saved again
+    fn test_file_history_not_saved() {
+        let mut repo = test_repository("file_history_not_saved");
+        let hidden_folder = repo.hidden_folder();
+        fs::write(
+            hidden_folder.clone() + "/repo.json",
+            r#"{"commit_history":[],"branch_heads":{},"file_history":{"README.md":["abc:Hello"]},"dvcs_hidden":""}"#,
+        )
+        .unwrap();
+        repo = RepositoryController::new(&repo.dvcs_hidden).unwrap();
+        assert!(repo.list_branches().is_empty());
 
-        // create a new commit
-        repo.commit(
-            "master",
-            "Initial commit".to_string(),
-            vec![("README.md".to_string(), "Hello, world!".to_string())],
+        commit_file(&mut repo, "README.md", "Hello, world!");
+        let saved = fs::read_to_string(hidden_folder + "/repo.json").unwrap();
+        assert!(!saved.contains("\"file_history\""));
+    }
+
+    // reads a file of the working directory
+    fn read(staging: &Staging, path: &str) -> String {
+        fs::read_to_string(staging.working_path(path)).unwrap()
+    }
+
+    #[test]
+    fn test_checkout() {
+        let mut repo = test_repository("checkout");
+        let mut staging = staging(&repo);
+
+        // first commit has a.txt and folder/b.txt
+        let first = commit_files(
+            &mut repo,
+            &mut staging,
+            &[("a.txt", "a1"), ("folder/b.txt", "b1")],
+            "first",
         );
 
-        // check if the function returns the correct commit history for the file
-        assert_eq!(
-            repo.retrieve_commit_history("README.md"),
-            vec!["Initial commit:Hello, world!"]
+        // second commit changes a.txt, deletes folder/b.txt and adds c.txt
+        fs::remove_file(staging.working_path("folder/b.txt")).unwrap();
+        staging
+            .remove_file_from_staging(&staging.working_path("folder/b.txt"))
+            .unwrap();
+        let second = commit_files(
+            &mut repo,
+            &mut staging,
+            &[("a.txt", "a2"), ("c.txt", "c")],
+            "second",
         );
+        // checking out the second commit from the first deletes the folder left empty
+        repo.checkout(&mut staging, &first, true).unwrap();
+        assert_eq!(read(&staging, "folder/b.txt"), "b1");
+        repo.checkout(&mut staging, &second, true).unwrap();
+        assert!(!Path::new(&staging.working_path("folder")).exists());
+
+        // going back to the first commit restores its files on disk
+        repo.checkout(&mut staging, &first, false).unwrap();
+        assert_eq!(read(&staging, "a.txt"), "a1");
+        assert_eq!(read(&staging, "folder/b.txt"), "b1");
+        assert!(!Path::new(&staging.working_path("c.txt")).exists());
+
+        // and the index matches the checked out snapshot
+        let index = staging.get_index();
+        assert!(index.get("c.txt").is_none());
+        let a = index.get("a.txt").unwrap();
+        let first_a = Tree::find(
+            &repo.storage().unwrap(),
+            &repo.get_commit(&first).unwrap().tree,
+            "a.txt",
+        )
+        .unwrap()
+        .unwrap()
+        .id;
+        for data in [&a.working_directory, &a.staging, &a.repository_version] {
+            assert_eq!(data.as_ref().unwrap().sha1, first_a);
+        }
+
+        // the branch is not moved and no commit is made by checking out
+        assert_eq!(repo.branch_heads.get("master"), Some(&second));
+        assert_eq!(repo.commit_history, vec![first, second]);
     }
 
     #[test]
-    fn test_concatenate_empty_commit_hashes() {
-        let mut repo = RepositoryController {
-            commit_history: Vec::new(),
-            branch_heads: HashMap::new(),
-            file_history: HashMap::new(),
-            dvcs_hidden: String::from("./"),
-        };
+    // * A commit from another repository naming a path outside of the working directory is refused before anything is written
+    fn test_checkout_refuses_crafted_paths() {
+        let mut repo = test_repository("checkout_refuses_crafted_paths");
+        let mut staging = staging(&repo);
+        let storage = repo.storage().unwrap();
+        let blob = storage.put(b"escaped").unwrap();
+        let escape = Path::new(&repo.dvcs_hidden).join("../escape.txt");
+        let _ = fs::remove_file(&escape);
+        for name in ["../escape.txt", ".dvcs_hidden"] {
+            let tree = storage
+                .put(format!("100644 {}\t{}\n", blob, name).as_bytes())
+                .unwrap();
+            let commit = repo
+                .write_commit(vec![], tree, "crafted".to_string())
+                .unwrap();
+            assert!(matches!(
+                repo.checkout(&mut staging, &commit, true),
+                Err(DvcsError::ObjectCorrupt { .. })
+            ));
+        }
+        assert!(!escape.exists());
 
-        // create new commits
-        repo.commit(
-            "master",
-            "Initial commit".to_string(),
-            vec![("README.md".to_string(), "Hello, world!".to_string())],
+        for path in [
+            "../escape.txt",
+            "/etc/x",
+            ".dvcs_hidden/repo.json",
+            "a/../b",
+            "",
+        ] {
+            assert!(matches!(
+                RepositoryController::check_tree_path(path),
+                Err(DvcsError::PathOutsideRepository(_))
+            ));
+        }
+        assert!(RepositoryController::check_tree_path("src/a.txt").is_ok());
+    }
+
+    #[test]
+    fn test_checkout_refuses_to_overwrite_local_changes() {
+        let mut repo = test_repository("checkout_refuses_to_overwrite_local_changes");
+        let mut staging = staging(&repo);
+        let first = commit_files(
+            &mut repo,
+            &mut staging,
+            &[("a.txt", "a1"), ("notes.txt", "notes")],
+            "first",
         );
-        repo.commit(
-            "master",
-            "Second commit".to_string(),
-            vec![("README.md".to_string(), "Another change".to_string())],
+        let second = commit_files(
+            &mut repo,
+            &mut staging,
+            &[("a.txt", "a2"), ("b.txt", "b")],
+            "second",
         );
+        repo.checkout(&mut staging, &first, true).unwrap();
+
+        // local changes to a file that differs between the commits block the checkout
+        fs::write(staging.working_path("a.txt"), "local").unwrap();
+        let checkout = repo.checkout(&mut staging, &second, false);
+        assert!(matches!(
+            checkout,
+            Err(DvcsError::Conflict { paths, .. }) if paths == vec!["a.txt".to_string()]
+        ));
+        assert_eq!(read(&staging, "a.txt"), "local");
+
+        // so does an untracked file the commit would write over
+        fs::write(staging.working_path("a.txt"), "a1").unwrap();
+        fs::write(staging.working_path("b.txt"), "untracked").unwrap();
+        assert!(repo.checkout(&mut staging, &second, false).is_err());
+        fs::remove_file(staging.working_path("b.txt")).unwrap();
+
+        // local changes to files that are the same in both commits are kept
+        fs::write(staging.working_path("notes.txt"), "local notes").unwrap();
+        repo.checkout(&mut staging, &second, false).unwrap();
+        assert_eq!(read(&staging, "a.txt"), "a2");
+        assert_eq!(read(&staging, "notes.txt"), "local notes");
 
-        // concatenate the two commits
-        repo.concatenate("master", vec![]);
+        // forcing the checkout throws the local changes away
+        fs::write(staging.working_path("a.txt"), "local").unwrap();
+        repo.checkout(&mut staging, &first, true).unwrap();
+        assert_eq!(read(&staging, "a.txt"), "a1");
+        assert_eq!(read(&staging, "notes.txt"), "notes");
+    }
 
-        // check if the branch head was correctly updated
-        assert_eq!(repo.branch_heads.get("master"), Some(&"".to_string()));
+    #[test]
+    fn test_create_and_list_branches() {
+        let mut repo = test_repository("create_and_list_branches");
+        // nothing to point a branch at before the first commit
+        assert!(repo.create_branch("feature", None).is_err());
 
-        // check if the commit history was correctly updated
+        let first = commit_file(&mut repo, "a.txt", "a");
+        let second = commit_file(&mut repo, "b.txt", "b");
+        assert_eq!(repo.head_commit(), Some(second.clone()));
+        assert_eq!(repo.create_branch("feature", None).unwrap(), second.clone());
+        assert_eq!(
+            repo.create_branch("old", Some(&first)).unwrap(),
+            first.clone()
+        );
+        assert!(matches!(
+            repo.create_branch("feature", None),
+            Err(DvcsError::BranchExists(_))
+        ));
+        assert!(repo.create_branch("bad name", None).is_err());
+        assert!(repo.create_branch("a..b", None).is_err());
+        assert!(repo
+            .create_branch("missing", Some(&"f".repeat(40)))
+            .is_err());
         assert_eq!(
-            repo.commit_history,
+            repo.list_branches(),
             vec![
-                "Initial commit".to_string(),
-                "Second commit".to_string(),
-                "".to_string()
+                ("feature".to_string(), second.clone()),
+                ("master".to_string(), second),
+                ("old".to_string(), first),
             ]
         );
     }
 
     #[test]
-    fn test_checkout() {
-        let mut repo = RepositoryController {
-            commit_history: Vec::new(),
-            branch_heads: HashMap::new(),
-            file_history: HashMap::new(),
-            dvcs_hidden: String::from("./"),
-        };
+    fn test_delete_and_rename_branches() {
+        let mut repo = test_repository("delete_and_rename_branches");
+        let mut staging = staging(&repo);
+        commit_file(&mut repo, "a.txt", "a");
+        repo.create_branch("merged", None).unwrap();
+        repo.create_branch("ahead", None).unwrap();
+        repo.switch_branch(&mut staging, "ahead", false).unwrap();
+        commit_files(&mut repo, &mut staging, &[("b.txt", "b")], "not merged");
+        repo.switch_branch(&mut staging, "master", false).unwrap();
+
+        // only branches reachable from HEAD can be deleted without force
+        repo.delete_branch("merged", false).unwrap();
+        assert!(matches!(
+            repo.delete_branch("ahead", false),
+            Err(DvcsError::BranchNotMerged(_))
+        ));
+        repo.delete_branch("ahead", true).unwrap();
+        assert!(repo.delete_branch("master", true).is_err());
+        assert!(matches!(
+            repo.delete_branch("missing", false),
+            Err(DvcsError::BranchNotFound(_))
+        ));
 
-        // create a new commit
-        repo.commit(
-            "master",
-            "Initial commit".to_string(),
-            vec![("README.md".to_string(), "Hello, world!".to_string())],
+        // renaming the current branch moves HEAD along
+        repo.rename_branch("master", "main").unwrap();
+        assert_eq!(repo.head(), &Head::Branch("main".to_string()));
+        assert_eq!(
+            repo.list_branches()
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect::<Vec<String>>(),
+            vec!["main"]
         );
+        assert!(repo.rename_branch("missing", "other").is_err());
+    }
 
-        // check out the commit
-        repo.checkout("master", "Initial commit");
+    #[test]
+    fn test_tags() {
+        let mut repo = test_repository("tags");
+        assert!(repo.create_tag("v1", None).is_err());
+        let first = commit_file(&mut repo, "a.txt", "a");
+        let second = commit_file(&mut repo, "b.txt", "b");
 
-        // check if the branch head was correctly updated
+        assert_eq!(repo.create_tag("v1", Some("master~1")).unwrap(), first);
+        assert_eq!(repo.create_tag("v2", None).unwrap(), second);
+        assert!(repo.create_tag("v2", None).is_err());
+        assert!(repo.create_tag("HEAD", None).is_err());
         assert_eq!(
-            repo.branch_heads.get("master"),
-            Some(&"Initial commit".to_string())
+            repo.list_tags(),
+            vec![
+                ("v1".to_string(), first.clone()),
+                ("v2".to_string(), second)
+            ]
         );
+        // * Tags can start branches like any other revision
+        assert_eq!(repo.create_branch("from_tag", Some("v1")).unwrap(), first);
+        repo.delete_tag("v1").unwrap();
+        assert!(matches!(
+            repo.delete_tag("v1"),
+            Err(DvcsError::RevisionNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_switch_branch_and_detach_head() {
+        let mut repo = test_repository("switch_branch_and_detach_head");
+        let mut staging = staging(&repo);
+        let first = commit_files(&mut repo, &mut staging, &[("a.txt", "master")], "first");
+        repo.create_branch("feature", None).unwrap();
+        let second = commit_files(&mut repo, &mut staging, &[("a.txt", "master 2")], "second");
 
-        // check if the commit history was correctly updated
+        repo.switch_branch(&mut staging, "feature", false).unwrap();
+        assert_eq!(read(&staging, "a.txt"), "master");
+        assert_eq!(repo.current_branch(), Some("feature"));
+        assert!(repo.switch_branch(&mut staging, "missing", false).is_err());
+
+        // HEAD is saved in the dvcs hidden folder
+        repo.detach_head(&mut staging, &second, false).unwrap();
+        assert_eq!(read(&staging, "a.txt"), "master 2");
+        let reloaded = RepositoryController::new(&repo.dvcs_hidden).unwrap();
+        assert_eq!(reloaded.head(), &Head::Detached(second.clone()));
+        assert_eq!(reloaded.current_branch(), None);
+        assert_eq!(reloaded.head_commit(), Some(second));
+
+        // a detached HEAD on an old commit still lets branches be created from it
+        repo.detach_head(&mut staging, &first, false).unwrap();
+        assert_eq!(repo.create_branch("from_first", None).unwrap(), first);
+    }
+
+    #[test]
+    fn test_commit_staged() {
+        let mut repo = test_repository("commit_staged");
+        let mut staging = staging(&repo);
+        // nothing staged yet
+        assert!(repo.commit_staged(&mut staging, "empty").is_err());
+
+        fs::write(staging.working_path("a.txt"), "a").unwrap();
+        staging.stage_path(&staging.working_path("a.txt")).unwrap();
+        assert!(repo.commit_staged(&mut staging, "  ").is_err());
+        let first = repo.commit_staged(&mut staging, "first").unwrap();
         assert_eq!(
-            repo.commit_history,
-            vec!["Initial commit".to_string(), "Initial commit".to_string()]
+            repo.list_branches(),
+            vec![("master".to_string(), first.clone())]
         );
+        let comparison = staging.get_index().get("a.txt").unwrap();
+        assert_eq!(
+            comparison.repository_version.as_ref().unwrap().sha1,
+            comparison.staging.as_ref().unwrap().sha1
+        );
+        // committing again without changes is refused
+        assert!(matches!(
+            repo.commit_staged(&mut staging, "again"),
+            Err(DvcsError::NothingToCommit)
+        ));
+
+        // the next commit is on top of the first and the branch moves along
+        staging
+            .remove_file_from_staging(&staging.working_path("a.txt"))
+            .unwrap();
+        let second = repo.commit_staged(&mut staging, "remove a.txt").unwrap();
+        let commit = repo.get_commit(&second).unwrap();
+        assert_eq!(commit.parents, vec![first.clone()]);
+        assert!(Tree::flatten(&repo.storage().unwrap(), &commit.tree)
+            .unwrap()
+            .is_empty());
+        assert_eq!(repo.head_commit(), Some(second.clone()));
+
+        // a detached HEAD moves by itself, branches stay where they are
+        repo.detach_head(&mut staging, &first, false).unwrap();
+        fs::write(staging.working_path("b.txt"), "b").unwrap();
+        staging.stage_path(&staging.working_path("b.txt")).unwrap();
+        let third = repo.commit_staged(&mut staging, "detached").unwrap();
+        assert_eq!(repo.head(), &Head::Detached(third));
+        assert_eq!(repo.list_branches(), vec![("master".to_string(), second)]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    // * The executable bit of a staged file is committed and restored by checking out
+    fn test_commit_and_checkout_executable() {
+        use std::os::unix::fs::PermissionsExt;
+        let mut repo = test_repository("commit_executable");
+        let mut staging = staging(&repo);
+        let script = staging.working_path("run.sh");
+        let mode = |path: &str| fs::metadata(path).unwrap().permissions().mode() & 0o111;
+        fs::write(&script, "echo run").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        staging.stage_path(&script).unwrap();
+        let executable = repo.commit_staged(&mut staging, "executable").unwrap();
+        let tree = repo.get_commit(&executable).unwrap().tree;
+        let files = Tree::flatten(&repo.storage().unwrap(), &tree).unwrap();
+        assert_eq!(files["run.sh"].mode, EntryMode::Executable);
+
+        // * Only the mode changed, it is still a change to commit
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o644)).unwrap();
+        staging.stage_path(&script).unwrap();
+        let plain = repo.commit_staged(&mut staging, "not executable").unwrap();
+
+        repo.detach_head(&mut staging, &executable, false).unwrap();
+        assert_eq!(mode(&script), 0o111);
+        repo.detach_head(&mut staging, &plain, false).unwrap();
+        assert_eq!(mode(&script), 0);
     }
 }
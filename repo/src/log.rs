@@ -0,0 +1,633 @@
+use crate::RepositoryController;
+use dvcs_error::DvcsError;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet};
+use storage_hiding::commit_objects::Commit;
+use storage_hiding::repository_storage::RepositoryStorage;
+use storage_hiding::tree_objects::Tree;
+
+/// Which commits a log shows, every filter that is set has to match
+#[derive(Debug, Clone, Default)]
+pub struct LogOptions {
+    /// Revisions or ranges of revisions the log shows (see `RepositoryController::resolve_commit_set`), HEAD when empty
+    pub revisions: Vec<String>,
+    /// Only commits whose author name or email contains this text
+    pub author: Option<String>,
+    /// Only commits made at or after this time, in seconds since the unix epoch
+    pub since: Option<u64>,
+    /// Only commits made at or before this time, in seconds since the unix epoch
+    pub until: Option<u64>,
+    /// Only commits that changed something inside one of these paths (index keys)
+    pub paths: Vec<String>,
+    /// Stop after this many commits
+    pub max_count: Option<usize>,
+    /// Never show a commit before all of its children, as a `Graph` needs, this reads every commit of the log before the first one comes out
+    pub topological: bool,
+}
+
+/// Commit shown by a log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    pub id: String,
+    pub commit: Commit,
+}
+
+impl LogEntry {
+    /// Formats the commit with a template
+    ///
+    /// `%H` is the id, `%h` the short id, `%an`, `%ae` and `%ad` the author name, email and date, `%cn`, `%ce` and `%cd` the same for the committer, `%s` the first line of the message, `%b` the rest of it, `%n` a new line and `%%` a percent sign. Anything else is copied
+    pub fn format(&self, template: &str) -> String {
+        let (subject, body) = match self.commit.message.split_once('\n') {
+            Some((subject, body)) => (subject, body.trim_start_matches('\n')),
+            None => (self.commit.message.as_str(), ""),
+        };
+        let (author, committer) = (&self.commit.author, &self.commit.committer);
+        let mut output = String::new();
+        let mut rest = template;
+        while let Some(position) = rest.find('%') {
+            output.push_str(&rest[..position]);
+            rest = &rest[position..];
+            let placeholders = [
+                ("%an", author.name.clone()),
+                ("%ae", author.email.clone()),
+                ("%ad", author.date()),
+                ("%cn", committer.name.clone()),
+                ("%ce", committer.email.clone()),
+                ("%cd", committer.date()),
+                ("%H", self.id.clone()),
+                ("%h", self.short_id().to_string()),
+                ("%s", subject.to_string()),
+                ("%b", body.to_string()),
+                ("%n", "\n".to_string()),
+                ("%%", "%".to_string()),
+            ];
+            match placeholders
+                .iter()
+                .find(|(placeholder, _)| rest.starts_with(placeholder))
+            {
+                Some((placeholder, value)) => {
+                    output.push_str(value);
+                    rest = &rest[placeholder.len()..];
+                }
+                None => {
+                    output.push('%');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        output + rest
+    }
+
+    /// Gets the first 7 characters of the id
+    pub fn short_id(&self) -> &str {
+        &self.id[..self.id.len().min(7)]
+    }
+}
+
+/// Iterator over the commits reachable from the start revisions that match the options of a log, see `RepositoryController::log`
+///
+/// Commits come out newest first and are read as the log goes, with the topological option they never come out before a commit that has them as a parent
+pub struct Log {
+    storage: RepositoryStorage,
+    options: LogOptions,
+    // commits read but not shown yet, with the number of their children that are still to come in a topological order
+    commits: HashMap<String, (Commit, usize)>,
+    // commits that can come out next by commit time, the first one queued comes first among commits of the same time
+    ready: BinaryHeap<(u64, Reverse<usize>, String)>,
+    // number of commits put on `ready` so far
+    queued: usize,
+    // commits the walk reached, they aren't read again when another child leads to them
+    walked: HashSet<String>,
+    // parents of the last commit walked, not read until the log goes on
+    unwalked: Vec<String>,
+    // commits of the excluded side of a range, they and their parents are never walked
+    excluded: BTreeSet<String>,
+    // commits walked past since the last call to `take_skipped`
+    skipped: Vec<(String, Vec<String>)>,
+    shown: usize,
+}
+
+impl RepositoryController {
+    /// Walks the commit graph from the start revisions of the options, returning the commits that match them
+    pub fn log(&self, options: LogOptions) -> Result<Log, DvcsError> {
+        let mut starts = BTreeSet::new();
+        let mut exclude = vec![];
+        if options.revisions.is_empty() {
+            starts.insert(self.resolve_revision("HEAD")?);
+        }
+        for revision in &options.revisions {
+            let set = self.resolve_commit_set(revision)?;
+            starts.extend(set.include);
+            exclude.extend(set.exclude);
+        }
+        let excluded = self.reachable(&exclude)?;
+        let mut log = Log {
+            storage: self.storage()?,
+            options,
+            commits: HashMap::new(),
+            ready: BinaryHeap::new(),
+            queued: 0,
+            walked: HashSet::new(),
+            unwalked: vec![],
+            excluded,
+            skipped: vec![],
+            shown: 0,
+        };
+        let starts: Vec<String> = starts.into_iter().collect();
+        match log.options.topological {
+            true => log.read_all(starts)?,
+            false => log.queue(&starts)?,
+        }
+        Ok(log)
+    }
+}
+
+impl Log {
+    /// Private helper function reading every commit reachable from the starts up front to count its children, the commits without children are ready
+    fn read_all(&mut self, starts: Vec<String>) -> Result<(), DvcsError> {
+        let mut children: HashMap<String, usize> = HashMap::new();
+        let mut pending = starts;
+        while let Some(commit_id) = pending.pop() {
+            if self.commits.contains_key(&commit_id) || self.excluded.contains(&commit_id) {
+                continue;
+            }
+            let commit = Commit::read(&self.storage, &commit_id)?;
+            for parent in &commit.parents {
+                *children.entry(parent.clone()).or_default() += 1;
+                pending.push(parent.clone());
+            }
+            self.commits.insert(commit_id, (commit, 0));
+        }
+        for (commit_id, count) in children {
+            if let Some((_, children)) = self.commits.get_mut(&commit_id) {
+                *children = count;
+            }
+        }
+        let mut ready: Vec<(u64, String)> = self
+            .commits
+            .iter()
+            .filter(|(_, (_, children))| *children == 0)
+            .map(|(commit_id, (commit, _))| (commit.committer.timestamp, commit_id.clone()))
+            .collect();
+        ready.sort();
+        for (timestamp, commit_id) in ready {
+            self.push(timestamp, commit_id);
+        }
+        Ok(())
+    }
+
+    /// Private helper function reading the commits the walk reached for the first time and making them ready
+    fn queue(&mut self, commit_ids: &[String]) -> Result<(), DvcsError> {
+        for commit_id in commit_ids {
+            if self.excluded.contains(commit_id) || !self.walked.insert(commit_id.clone()) {
+                continue;
+            }
+            let commit = Commit::read(&self.storage, commit_id)?;
+            self.push(commit.committer.timestamp, commit_id.clone());
+            self.commits.insert(commit_id.clone(), (commit, 0));
+        }
+        Ok(())
+    }
+
+    /// Private helper function putting a commit on the ready heap
+    fn push(&mut self, timestamp: u64, commit_id: String) {
+        self.ready
+            .push((timestamp, Reverse(self.queued), commit_id));
+        self.queued += 1;
+    }
+
+    /// Takes the commits the filters left out since the last call, as (id, parents) in the order they were walked past
+    ///
+    /// A graph has to draw its lanes through them, see `Graph::skip`
+    pub fn take_skipped(&mut self) -> Vec<(String, Vec<String>)> {
+        std::mem::take(&mut self.skipped)
+    }
+
+    /// Private helper function checking if a commit matches the filters of the options
+    fn matches(&self, commit: &Commit) -> Result<bool, DvcsError> {
+        let options = &self.options;
+        if let Some(author) = &options.author {
+            if !commit.author.name.contains(author.as_str())
+                && !commit.author.email.contains(author.as_str())
+            {
+                return Ok(false);
+            }
+        }
+        let time = commit.committer.timestamp;
+        if options.since.is_some_and(|since| time < since)
+            || options.until.is_some_and(|until| time > until)
+        {
+            return Ok(false);
+        }
+        if options.paths.is_empty() {
+            return Ok(true);
+        }
+        // * A commit changed a path if it differs from every parent, a commit without parents changed every path it has
+        for path in &options.paths {
+            let entry = self.entry(&commit.tree, path)?;
+            let mut changed = entry.is_some() || !commit.parents.is_empty();
+            for parent in &commit.parents {
+                let parent_tree = match self.commits.get(parent) {
+                    Some((parent, _)) => parent.tree.clone(),
+                    None => Commit::read(&self.storage, parent)?.tree,
+                };
+                if self.entry(&parent_tree, path)? == entry {
+                    changed = false;
+                }
+            }
+            if changed {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Private helper function getting the id of what a tree has at a path, the working directory (`.`) is the tree itself
+    fn entry(&self, tree: &str, path: &str) -> Result<Option<String>, DvcsError> {
+        if path.is_empty() || path == "." {
+            return Ok(Some(tree.to_string()));
+        }
+        Ok(Tree::find(&self.storage, tree, path)?.map(|entry| entry.id))
+    }
+}
+
+impl Iterator for Log {
+    type Item = Result<LogEntry, DvcsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self
+            .options
+            .max_count
+            .is_some_and(|max_count| self.shown >= max_count)
+        {
+            return None;
+        }
+        loop {
+            // * The parents of the last commit walked are only read once the log goes on
+            let parents = std::mem::take(&mut self.unwalked);
+            if let Err(error) = self.queue(&parents) {
+                return Some(Err(error));
+            }
+            let (_, _, id) = self.ready.pop()?;
+            let commit = match self.commits.remove(&id) {
+                Some((commit, _)) => commit,
+                None => continue,
+            };
+            if self.options.topological {
+                for parent in &commit.parents {
+                    if let Some((parent_commit, children)) = self.commits.get_mut(parent) {
+                        *children -= 1;
+                        if *children == 0 {
+                            let timestamp = parent_commit.committer.timestamp;
+                            self.push(timestamp, parent.clone());
+                        }
+                    }
+                }
+            } else {
+                self.unwalked = commit.parents.clone();
+            }
+            match self.matches(&commit) {
+                Ok(true) => {
+                    self.shown += 1;
+                    return Some(Ok(LogEntry { id, commit }));
+                }
+                Ok(false) => self.skipped.push((id, commit.parents)),
+                Err(error) => return Some(Err(error)),
+            }
+        }
+    }
+}
+
+/// ASCII drawing of the commit graph for `log --graph`, commits have to be drawn in the order a `Log` returns them
+///
+/// Every lane is a commit still to come, the lanes of a merge open to the right and lanes leading to the same commit join before it
+#[derive(Debug, Clone, Default)]
+pub struct Graph {
+    lanes: Vec<String>,
+}
+
+/// Lines drawn for one commit
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GraphRow {
+    /// Lines joining the lanes that lead to the commit, drawn above it
+    pub before: Vec<String>,
+    /// Lanes of the commit line, the commit is a `*`
+    pub commit: String,
+    /// Lanes for the other lines of the commit
+    pub padding: String,
+    /// Line opening the lanes of the parents of a merge or closing the lane of a commit without parents, drawn below it
+    pub after: Vec<String>,
+}
+
+impl Graph {
+    /// Draws a commit and moves its lane on to its parents
+    pub fn draw(&mut self, commit_id: &str, parents: &[String]) -> GraphRow {
+        let mut before = vec![];
+        let column = self.place(commit_id, &mut before);
+        let lanes = |commit_column: Option<usize>| {
+            (0..self.lanes.len())
+                .map(|lane| {
+                    if Some(lane) == commit_column {
+                        "*"
+                    } else {
+                        "|"
+                    }
+                })
+                .collect::<Vec<&str>>()
+                .join(" ")
+        };
+        let (commit, padding) = (lanes(Some(column)), lanes(None));
+        GraphRow {
+            before,
+            commit,
+            padding,
+            after: self.advance(column, parents).into_iter().collect(),
+        }
+    }
+
+    /// Moves the lanes past a commit that isn't shown
+    pub fn skip(&mut self, commit_id: &str, parents: &[String]) {
+        let column = self.place(commit_id, &mut vec![]);
+        self.advance(column, parents);
+    }
+
+    /// Private helper function finding the lane of a commit (a new one on the right if no lane leads to it), the other lanes leading to it join it
+    fn place(&mut self, commit_id: &str, lines: &mut Vec<String>) -> usize {
+        let column = match self.lanes.iter().position(|lane| lane == commit_id) {
+            Some(column) => column,
+            None => {
+                self.lanes.push(commit_id.to_string());
+                self.lanes.len() - 1
+            }
+        };
+        while let Some(joined) = self.lanes[column + 1..]
+            .iter()
+            .position(|lane| lane == commit_id)
+            .map(|lane| lane + column + 1)
+        {
+            lines.push(self.shift_left(joined, true));
+            self.lanes.remove(joined);
+        }
+        column
+    }
+
+    /// Private helper function giving the lane of a commit to its first parent and opening lanes for the others, returns the line drawn below the commit if the lanes moved
+    fn advance(&mut self, column: usize, parents: &[String]) -> Option<String> {
+        let first_parent = match parents.first() {
+            Some(first_parent) => first_parent,
+            None => {
+                let moved = column + 1 < self.lanes.len();
+                let line = self.shift_left(column, false);
+                self.lanes.remove(column);
+                return if moved { Some(line) } else { None };
+            }
+        };
+        self.lanes[column] = first_parent.clone();
+        let mut opened = vec![];
+        for parent in &parents[1..] {
+            if !self.lanes.contains(parent) && !opened.contains(parent) {
+                opened.push(parent.clone());
+            }
+        }
+        if opened.is_empty() {
+            return None;
+        }
+        // * Lanes right of the commit move right to make room
+        let mut line = vec![' '; 2 * (self.lanes.len() + opened.len())];
+        for lane in 0..=column {
+            line[2 * lane] = '|';
+        }
+        for lane in column..self.lanes.len() + opened.len() - 1 {
+            line[2 * lane + 1] = '\\';
+        }
+        let tail = self.lanes.split_off(column + 1);
+        self.lanes.extend(opened);
+        self.lanes.extend(tail);
+        Some(line.into_iter().collect::<String>().trim_end().to_string())
+    }
+
+    /// Private helper function drawing the line where a lane ends, or `joins` the lane on its left, and every lane right of it moves one to the left
+    fn shift_left(&self, removed: usize, joins: bool) -> String {
+        let mut line = vec![' '; 2 * self.lanes.len()];
+        for lane in 0..removed {
+            line[2 * lane] = '|';
+        }
+        let first_moved = if joins { removed } else { removed + 1 };
+        for lane in first_moved..self.lanes.len() {
+            line[2 * lane - 1] = '/';
+        }
+        line.into_iter().collect::<String>().trim_end().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::MergeOutcome;
+    use crate::test_support::{commit_files, staging, test_repository};
+    use storage_hiding::tree_objects::EntryMode;
+
+    fn ids(repo: &RepositoryController, options: LogOptions) -> Vec<String> {
+        repo.log(options)
+            .unwrap()
+            .map(|entry| entry.unwrap().id)
+            .collect()
+    }
+
+    #[test]
+    // * Without the topological order commits are only read as the log reaches them, a limited log never reads the older ones
+    fn test_log_walks_lazily() {
+        let mut repo = test_repository("log_walks_lazily");
+        let mut staging = staging(&repo);
+        let first = commit_files(&mut repo, &mut staging, &[("a.txt", "a")], "First");
+        let tree = repo.get_commit(&first).unwrap().tree;
+        let missing = "f".repeat(40);
+        let orphan = repo
+            .write_commit(vec![missing.clone()], tree, "Parent missing".to_string())
+            .unwrap();
+        let log = |max_count: Option<usize>, topological: bool| {
+            repo.log(LogOptions {
+                revisions: vec![orphan.clone()],
+                max_count,
+                topological,
+                ..Default::default()
+            })
+            .and_then(|log| {
+                log.map(|entry| entry.map(|entry| entry.id))
+                    .collect::<Result<Vec<String>, DvcsError>>()
+            })
+        };
+        assert_eq!(log(Some(1), false).unwrap(), vec![orphan.clone()]);
+        assert!(log(None, false).is_err());
+        assert!(log(Some(1), true).is_err());
+    }
+
+    #[test]
+    // * Parents come after all of their children, whatever branch they are on
+    fn test_log_order_and_filters() {
+        let mut repo = test_repository("log_order_and_filters");
+        let mut staging = staging(&repo);
+        let base = commit_files(&mut repo, &mut staging, &[("src/a.txt", "a")], "Base");
+        repo.create_branch("topic", None).unwrap();
+        repo.switch_branch(&mut staging, "topic", false).unwrap();
+        let topic = commit_files(&mut repo, &mut staging, &[("b.txt", "b")], "Topic");
+        repo.switch_branch(&mut staging, "master", false).unwrap();
+        let master = commit_files(
+            &mut repo,
+            &mut staging,
+            &[("src/a.txt", "changed")],
+            "Master",
+        );
+        let merge = match repo.merge(&mut staging, "topic", "Merge").unwrap() {
+            MergeOutcome::Merged(merge) => merge,
+            outcome => panic!("unexpected merge outcome {:?}", outcome),
+        };
+
+        let all = ids(
+            &repo,
+            LogOptions {
+                revisions: vec![merge.clone()],
+                topological: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(all.len(), 4);
+        assert_eq!(all[0], merge);
+        assert_eq!(all[3], base);
+
+        // * Both branches together, without a merge
+        let branches = ids(
+            &repo,
+            LogOptions {
+                revisions: vec![master.clone(), "topic".to_string()],
+                ..Default::default()
+            },
+        );
+        assert_eq!(branches.len(), 3);
+        assert_eq!(branches[2], base);
+        let ranges = |revisions: &[&str]| {
+            ids(
+                &repo,
+                LogOptions {
+                    revisions: revisions
+                        .iter()
+                        .map(|revision| revision.to_string())
+                        .collect(),
+                    ..Default::default()
+                },
+            )
+        };
+        assert_eq!(ranges(&["topic..master~"]), vec![master.clone()]);
+        assert_eq!(
+            ranges(&["master~..master"]),
+            vec![merge.clone(), topic.clone()]
+        );
+        assert_eq!(ranges(&[&format!("{}...topic", master)]).len(), 2);
+
+        // * The merge has src/a.txt like its first parent, so only the commits that wrote it changed src
+        let src = ids(
+            &repo,
+            LogOptions {
+                revisions: vec![merge.clone()],
+                paths: vec!["src".to_string()],
+                ..Default::default()
+            },
+        );
+        assert_eq!(src, vec![master.clone(), base.clone()]);
+        let limited = ids(
+            &repo,
+            LogOptions {
+                revisions: vec![merge.clone()],
+                max_count: Some(1),
+                ..Default::default()
+            },
+        );
+        assert_eq!(limited, vec![merge.clone()]);
+        let nobody = ids(
+            &repo,
+            LogOptions {
+                revisions: vec![merge.clone()],
+                author: Some("nobody at all".to_string()),
+                ..Default::default()
+            },
+        );
+        assert!(nobody.is_empty());
+        let future = ids(
+            &repo,
+            LogOptions {
+                revisions: vec![merge],
+                since: Some(u64::MAX),
+                ..Default::default()
+            },
+        );
+        assert!(future.is_empty());
+        assert!(matches!(
+            repo.log(LogOptions {
+                revisions: vec!["unknown".to_string()],
+                ..Default::default()
+            }),
+            Err(DvcsError::RevisionNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_format() {
+        let mut repo = test_repository("log_format");
+        let storage = repo.storage().unwrap();
+        let tree = Tree::write_from_paths(
+            &storage,
+            &[(
+                "a.txt".to_string(),
+                EntryMode::File,
+                storage.put(b"a").unwrap(),
+            )],
+        )
+        .unwrap();
+        let id = repo
+            .write_commit(vec![], tree, "Subject\n\nBody line".to_string())
+            .unwrap();
+        let entry = LogEntry {
+            commit: repo.get_commit(&id).unwrap(),
+            id: id.clone(),
+        };
+        assert_eq!(
+            entry.format("%h %s%n%b %% %x"),
+            format!("{} Subject\nBody line % %x", &id[..7])
+        );
+        assert_eq!(entry.format("%H"), id);
+        assert_eq!(entry.format("%an"), entry.commit.author.name);
+    }
+
+    #[test]
+    fn test_graph() {
+        let ids = |ids: &[&str]| -> Vec<String> { ids.iter().map(|id| id.to_string()).collect() };
+        let mut graph = Graph::default();
+        let merge = graph.draw("merge", &ids(&["master", "topic"]));
+        assert_eq!(
+            (merge.commit.as_str(), merge.after),
+            ("*", vec!["|\\".to_string()])
+        );
+        let topic = graph.draw("topic", &ids(&["base"]));
+        assert_eq!(
+            (topic.commit.as_str(), topic.padding.as_str()),
+            ("| *", "| |")
+        );
+        assert_eq!(graph.draw("master", &ids(&["base"])).commit, "* |");
+        let base = graph.draw("base", &[]);
+        assert_eq!(
+            (base.before, base.commit.as_str(), base.after),
+            (vec!["|/".to_string()], "*", vec![])
+        );
+
+        // * A commit without parents closes its lane, the lanes right of it move left
+        let mut graph = Graph::default();
+        graph.draw("a", &ids(&["root a", "b"]));
+        graph.skip("b", &ids(&["root b"]));
+        let root = graph.draw("root a", &[]);
+        assert_eq!(
+            (root.commit.as_str(), root.after),
+            ("* |", vec![" /".to_string()])
+        );
+        assert_eq!(graph.draw("root b", &[]).commit, "*");
+    }
+}
@@ -0,0 +1,409 @@
+use crate::{Head, RepositoryController};
+use dvcs_error::DvcsError;
+use repo_directory_hiding::{merge_states, State};
+use staging::staging_storage::{set_executable, StagedData, Staging};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use storage_hiding::repository_storage::RepositoryStorage;
+use storage_hiding::tree_objects::{EntryMode, Tree, TreeEntry};
+
+/// What merging a commit into the current branch did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeOutcome {
+    /// The commit is already part of the branch, nothing changed
+    UpToDate,
+    /// The branch had no commits of its own and was moved forward to the commit
+    FastForward(String),
+    /// Both sides were merged and committed as the merge commit with this id
+    Merged(String),
+    /// Files both sides changed couldn't be merged, they are left with conflict markers and unmerged, the merge is committed once they are fixed and staged
+    Conflicts(Vec<String>),
+}
+
+impl RepositoryController {
+    /// Merges a revision into the current branch
+    ///
+    /// The branch is moved forward when it has no commits of its own, otherwise the changes both sides made since their merge base are merged file by file (see `merge_states`) and committed with both commits as parents. Files that can't be merged are written with conflict markers and marked unmerged in the staging index, the files that could be merged are staged, and the commit made once every unmerged file was staged or removed again concludes the merge
+    ///
+    /// Merging refuses to start with staged changes or with local modifications to files it would change
+    ///
+    /// #### Arguments
+    ///
+    /// * `staging` - Staging index of the working directory
+    /// * `revision` - Revision of the commit to merge (see `resolve_revision`)
+    /// * `message` - Message of the merge commit
+    pub fn merge(
+        &mut self,
+        staging: &mut Staging,
+        revision: &str,
+        message: &str,
+    ) -> Result<MergeOutcome, DvcsError> {
+        self.require_working_directory()?;
+        let branch = match &self.head {
+            Head::Branch(name) => name.clone(),
+            Head::Detached(_) => {
+                return Err(DvcsError::InvalidArgument(
+                    "HEAD is detached, switch to the branch to merge into first".to_string(),
+                ))
+            }
+        };
+        if self.merge_head.is_some() {
+            return Err(DvcsError::InvalidArgument(
+                "A merge is in progress, fix the conflicts and commit it first".to_string(),
+            ));
+        }
+        let theirs = self.resolve_revision(revision)?;
+        let ours = match self.head_commit() {
+            Some(ours) if self.is_ancestor(&theirs, &ours)? => return Ok(MergeOutcome::UpToDate),
+            Some(ours) if !self.is_ancestor(&ours, &theirs)? => ours,
+            // * Nothing to merge, HEAD only has to move forward
+            _ => {
+                self.checkout(staging, &theirs, false)?;
+                self.branch_heads.insert(branch, theirs.clone());
+                self.save_locally()?;
+                return Ok(MergeOutcome::FastForward(theirs));
+            }
+        };
+
+        let storage = self.storage()?;
+        let base = match self.merge_bases(&ours, &theirs)?.first() {
+            Some(base) => Tree::flatten(&storage, &self.get_commit(base)?.tree)?,
+            None => BTreeMap::new(),
+        };
+        let ours_files = Tree::flatten(&storage, &self.get_commit(&ours)?.tree)?;
+        let theirs_files = Tree::flatten(&storage, &self.get_commit(&theirs)?.tree)?;
+        let paths: BTreeSet<&String> = base
+            .keys()
+            .chain(ours_files.keys())
+            .chain(theirs_files.keys())
+            .collect();
+
+        // * Files whose merged version differs from ours, None to delete them
+        let mut changes: Vec<(String, Option<Vec<u8>>)> = vec![];
+        // * Files of the changes written with their executable bit set
+        let mut executable = BTreeSet::new();
+        let mut conflicts = vec![];
+        for path in paths {
+            let (ancestor, ours_entry, theirs_entry) =
+                (base.get(path), ours_files.get(path), theirs_files.get(path));
+            if same_file(ours_entry, theirs_entry) || same_file(ancestor, theirs_entry) {
+                continue;
+            }
+            if same_file(ancestor, ours_entry) {
+                let contents = match theirs_entry {
+                    Some(entry) => Some(storage.get(&entry.id)?),
+                    None => None,
+                };
+                if is_executable(theirs_entry) {
+                    executable.insert(path.clone());
+                }
+                changes.push((path.clone(), contents));
+                continue;
+            }
+            // * Both sides changed the file differently, an executable bit only one side changed is taken from it
+            let mode_side = match is_executable(ancestor) == is_executable(ours_entry) {
+                true => theirs_entry,
+                false => ours_entry,
+            };
+            if is_executable(mode_side) {
+                executable.insert(path.clone());
+            }
+            let merged = match (ours_entry, theirs_entry) {
+                (Some(ours_entry), Some(theirs_entry)) => merge_file(
+                    &storage,
+                    ancestor,
+                    (ours_entry, "HEAD"),
+                    (theirs_entry, revision),
+                )?,
+                // * Deleted on one side and changed on the other, the changed version is kept to decide on
+                (None, Some(theirs_entry)) => Err(storage.get(&theirs_entry.id)?),
+                _ => {
+                    conflicts.push(path.clone());
+                    continue;
+                }
+            };
+            match merged {
+                Ok(contents) => changes.push((path.clone(), Some(contents))),
+                Err(contents) => {
+                    conflicts.push(path.clone());
+                    changes.push((path.clone(), Some(contents)));
+                }
+            }
+        }
+
+        for (path, _) in &changes {
+            Self::check_tree_path(path)?;
+        }
+        self.check_mergeable(staging, &changes)?;
+        for (path, contents) in &changes {
+            let file_path = PathBuf::from(staging.working_path(path));
+            match contents {
+                Some(contents) => {
+                    if let Some(parent) = file_path.parent() {
+                        fs::create_dir_all(parent).map_err(|e| DvcsError::io(parent, e))?;
+                    }
+                    fs::write(&file_path, contents).map_err(|e| DvcsError::io(&file_path, e))?;
+                    set_executable(&file_path.display().to_string(), executable.contains(path))?;
+                }
+                None => {
+                    if file_path.is_file() {
+                        fs::remove_file(&file_path).map_err(|e| DvcsError::io(&file_path, e))?;
+                    }
+                    Self::remove_empty_folders(&file_path, staging.get_working_directory());
+                }
+            }
+            if conflicts.contains(path) {
+                continue;
+            }
+            let working_path = staging.working_path(path);
+            match contents {
+                Some(_) => staging.stage_path(&working_path)?,
+                None => staging.remove_file_from_staging(&working_path)?,
+            }
+        }
+
+        self.merge_head = Some(theirs);
+        if !conflicts.is_empty() {
+            staging.mark_unmerged(&conflicts)?;
+            self.save_locally()?;
+            return Ok(MergeOutcome::Conflicts(conflicts));
+        }
+        Ok(MergeOutcome::Merged(self.commit_staged(staging, message)?))
+    }
+
+    /// Private helper function refusing to merge with staged changes, or when the merge would write over local modifications or untracked files
+    fn check_mergeable(
+        &self,
+        staging: &Staging,
+        changes: &[(String, Option<Vec<u8>>)],
+    ) -> Result<(), DvcsError> {
+        let staged_file = |data: &Option<StagedData>| {
+            data.as_ref()
+                .filter(|data| data.is_file)
+                .map(|data| data.sha1.clone())
+        };
+        let mut paths = vec![];
+        for (key, comparison) in staging.get_index() {
+            if staged_file(&comparison.staging) != staged_file(&comparison.repository_version) {
+                paths.push(key.clone());
+            }
+        }
+        for (path, _) in changes {
+            let comparison = staging.get_index().get(path).cloned().unwrap_or_default();
+            let staged = staged_file(&comparison.staging);
+            if staged != staging.get_working_file_hash(path)
+                && (staged.is_some() || Path::new(&staging.working_path(path)).exists())
+            {
+                paths.push(path.clone());
+            }
+        }
+        if paths.is_empty() {
+            return Ok(());
+        }
+        paths.sort();
+        paths.dedup();
+        Err(DvcsError::Conflict {
+            operation: "merge".to_string(),
+            paths,
+        })
+    }
+}
+
+/// Private helper function comparing two versions of a file by contents and mode, missing on both sides counts as the same
+fn same_file(first: Option<&TreeEntry>, second: Option<&TreeEntry>) -> bool {
+    first.map(|entry| (&entry.id, &entry.mode)) == second.map(|entry| (&entry.id, &entry.mode))
+}
+
+/// Private helper function checking if a version of a file is executable, a missing file isn't
+fn is_executable(entry: Option<&TreeEntry>) -> bool {
+    entry.is_some_and(|entry| entry.mode == EntryMode::Executable)
+}
+
+/// Private helper function merging the lines of a file both sides changed, Ok with the merged contents or Err with the contents to leave in the working directory (with conflict markers for text files, ours for binary files)
+fn merge_file(
+    storage: &RepositoryStorage,
+    ancestor: Option<&TreeEntry>,
+    (ours, ours_name): (&TreeEntry, &str),
+    (theirs, theirs_name): (&TreeEntry, &str),
+) -> Result<Result<Vec<u8>, Vec<u8>>, DvcsError> {
+    let ancestor = match ancestor {
+        Some(entry) => storage.get(&entry.id)?,
+        None => vec![],
+    };
+    let (ours, theirs) = (storage.get(&ours.id)?, storage.get(&theirs.id)?);
+    let (ancestor_text, ours_text, theirs_text) = match (
+        String::from_utf8(ancestor),
+        String::from_utf8(ours.clone()),
+        String::from_utf8(theirs),
+    ) {
+        (Ok(ancestor), Ok(ours), Ok(theirs)) => (ancestor, ours, theirs),
+        _ => return Ok(Err(ours)),
+    };
+    let merged = merge_states(
+        &State::from_text("ancestor", &ancestor_text),
+        &State::from_text(ours_name, &ours_text),
+        &State::from_text(theirs_name, &theirs_text),
+    );
+    let contents = merged.to_text().into_bytes();
+    Ok(match merged.is_clean() {
+        true => Ok(contents),
+        false => Err(contents),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{commit_files as commit, staging, test_repository};
+
+    fn merge_repository(name: &str) -> (RepositoryController, Staging) {
+        let repo = test_repository(&format!("merge_{}", name));
+        let staging = staging(&repo);
+        (repo, staging)
+    }
+
+    fn read(staging: &Staging, path: &str) -> String {
+        fs::read_to_string(staging.working_path(path)).unwrap()
+    }
+
+    #[test]
+    // * Changes of both sides to different files and lines end up in a merge commit with both parents
+    fn test_merge() {
+        let (mut repo, mut staging) = merge_repository("clean");
+        let base = commit(
+            &mut repo,
+            &mut staging,
+            &[("a.txt", "1\n2\n3\n"), ("b.txt", "b\n")],
+            "Base",
+        );
+        repo.create_branch("topic", None).unwrap();
+        let ours = commit(&mut repo, &mut staging, &[("a.txt", "one\n2\n3\n")], "Ours");
+        repo.switch_branch(&mut staging, "topic", false).unwrap();
+        let theirs = commit(
+            &mut repo,
+            &mut staging,
+            &[("a.txt", "1\n2\nthree\n"), ("c.txt", "c\n")],
+            "Theirs",
+        );
+        repo.switch_branch(&mut staging, "master", false).unwrap();
+
+        assert_eq!(
+            repo.merge(&mut staging, &base, "Merge").unwrap(),
+            MergeOutcome::UpToDate
+        );
+        let merge = match repo.merge(&mut staging, "topic", "Merge topic").unwrap() {
+            MergeOutcome::Merged(merge) => merge,
+            outcome => panic!("unexpected {:?}", outcome),
+        };
+        assert_eq!(repo.get_commit(&merge).unwrap().parents, vec![ours, theirs]);
+        assert_eq!(repo.head_commit(), Some(merge));
+        assert_eq!(read(&staging, "a.txt"), "one\n2\nthree\n");
+        assert_eq!(read(&staging, "c.txt"), "c\n");
+        assert!(matches!(
+            repo.commit_staged(&mut staging, "Again"),
+            Err(DvcsError::NothingToCommit)
+        ));
+
+        // * The other branch has nothing of its own, so it only moves forward
+        repo.switch_branch(&mut staging, "topic", false).unwrap();
+        assert!(matches!(
+            repo.merge(&mut staging, "master", "Merge"),
+            Ok(MergeOutcome::FastForward(_))
+        ));
+        assert_eq!(repo.head_commit(), repo.resolve_revision("master").ok());
+    }
+
+    #[test]
+    // * Conflicting changes are left with markers and the next commit concludes the merge
+    fn test_merge_conflicts() {
+        let (mut repo, mut staging) = merge_repository("conflicts");
+        commit(&mut repo, &mut staging, &[("a.txt", "a\n")], "Base");
+        repo.create_branch("topic", None).unwrap();
+        let ours = commit(&mut repo, &mut staging, &[("a.txt", "ours\n")], "Ours");
+        repo.switch_branch(&mut staging, "topic", false).unwrap();
+        let theirs = commit(
+            &mut repo,
+            &mut staging,
+            &[("a.txt", "theirs\n"), ("b.txt", "b\n")],
+            "Theirs",
+        );
+        repo.switch_branch(&mut staging, "master", false).unwrap();
+
+        // * Local changes to a file the merge changes stop it before anything is written
+        fs::write(staging.working_path("a.txt"), "local\n").unwrap();
+        assert!(matches!(
+            repo.merge(&mut staging, "topic", "Merge"),
+            Err(DvcsError::Conflict { paths, .. }) if paths == vec!["a.txt".to_string()]
+        ));
+        assert!(!Path::new(&staging.working_path("b.txt")).exists());
+        fs::write(staging.working_path("a.txt"), "ours\n").unwrap();
+
+        assert_eq!(
+            repo.merge(&mut staging, "topic", "Merge").unwrap(),
+            MergeOutcome::Conflicts(vec!["a.txt".to_string()])
+        );
+        assert_eq!(
+            read(&staging, "a.txt"),
+            "<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> topic\n"
+        );
+        assert_eq!(read(&staging, "b.txt"), "b\n");
+        assert!(repo.merge(&mut staging, "topic", "Merge").is_err());
+        // * The conflict markers can't be committed before the file is fixed and staged
+        assert_eq!(staging.unmerged_paths(), vec!["a.txt"]);
+        assert!(matches!(
+            repo.commit_staged(&mut staging, "Merge"),
+            Err(DvcsError::Conflict { paths, .. }) if paths == vec!["a.txt".to_string()]
+        ));
+        assert_eq!(repo.head_commit(), Some(ours.clone()));
+
+        let merge = commit(&mut repo, &mut staging, &[("a.txt", "both\n")], "Merge");
+        assert_eq!(repo.get_commit(&merge).unwrap().parents, vec![ours, theirs]);
+        let tree = repo.get_commit(&merge).unwrap().tree;
+        let files = Tree::flatten(&repo.storage().unwrap(), &tree).unwrap();
+        assert_eq!(files.keys().collect::<Vec<_>>(), vec!["a.txt", "b.txt"]);
+    }
+
+    #[test]
+    // * A file one side deleted and the other changed stays unmerged until it is staged or removed
+    fn test_merge_delete_modify_conflict() {
+        let (mut repo, mut staging) = merge_repository("delete_modify");
+        commit(
+            &mut repo,
+            &mut staging,
+            &[("a.txt", "a\n"), ("b.txt", "b\n")],
+            "Base",
+        );
+        repo.create_branch("topic", None).unwrap();
+        commit(&mut repo, &mut staging, &[("a.txt", "ours\n")], "Ours");
+        repo.switch_branch(&mut staging, "topic", false).unwrap();
+        fs::remove_file(staging.working_path("a.txt")).unwrap();
+        staging
+            .remove_file_from_staging(&staging.working_path("a.txt"))
+            .unwrap();
+        commit(&mut repo, &mut staging, &[("b.txt", "theirs\n")], "Theirs");
+        repo.switch_branch(&mut staging, "master", false).unwrap();
+
+        assert_eq!(
+            repo.merge(&mut staging, "topic", "Merge").unwrap(),
+            MergeOutcome::Conflicts(vec!["a.txt".to_string()])
+        );
+        assert_eq!(staging.unmerged_paths(), vec!["a.txt"]);
+        assert!(matches!(
+            repo.commit_staged(&mut staging, "Merge"),
+            Err(DvcsError::Conflict { .. })
+        ));
+
+        staging
+            .remove_file_from_staging(&staging.working_path("a.txt"))
+            .unwrap();
+        assert!(staging.unmerged_paths().is_empty());
+        let merge = repo.commit_staged(&mut staging, "Merge").unwrap();
+        let files = Tree::flatten(
+            &repo.storage().unwrap(),
+            &repo.get_commit(&merge).unwrap().tree,
+        )
+        .unwrap();
+        assert_eq!(files.keys().collect::<Vec<_>>(), vec!["b.txt"]);
+    }
+}
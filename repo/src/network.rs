@@ -0,0 +1,771 @@
+use crate::remote::{unexpected_tip, PushMode, RefUpdate, RemoteRefs};
+use crate::{Head, RepositoryController};
+use dvcs_error::DvcsError;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use storage_hiding::object_transfer::{check_connected, objects_to_send, MissingObjects};
+use storage_hiding::repository_lock::RepositoryLock;
+use storage_hiding::repository_storage::RepositoryStorage;
+
+/// Port `dvcs serve` listens on and `dvcs://` urls without a port connect to
+pub const DEFAULT_PORT: u16 = 9419;
+
+/// Longest line of the protocol, a longer line is refused
+const MAX_LINE: u64 = 4096;
+
+/// How long the server waits for a client that stopped sending
+const IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Server and repository a `dvcs://host[:port]/path` url points to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkAddress {
+    pub host: String,
+    pub port: u16,
+    /// Path of the repository inside the folder the server serves, empty for the folder itself
+    pub path: String,
+}
+
+impl NetworkAddress {
+    /// Parses a `dvcs://host[:port]/path` url, the port is `DEFAULT_PORT` when it is left out
+    pub fn parse(url: &str) -> Result<NetworkAddress, DvcsError> {
+        let invalid = || DvcsError::InvalidArgument(format!("'{}' is not a valid dvcs url", url));
+        let rest = url.strip_prefix("dvcs://").ok_or_else(invalid)?;
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        // * An IPv6 host is written in brackets, its colons aren't a port
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !authority.ends_with(']') => {
+                (host, port.parse::<u16>().map_err(|_| invalid())?)
+            }
+            _ => (authority, DEFAULT_PORT),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(NetworkAddress {
+            host: host.to_string(),
+            port,
+            path: path.trim_matches('/').to_string(),
+        })
+    }
+}
+
+impl fmt::Display for NetworkAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.host.contains(':') {
+            true => write!(f, "dvcs://[{}]:{}/{}", self.host, self.port, self.path),
+            false => write!(f, "dvcs://{}:{}/{}", self.host, self.port, self.path),
+        }
+    }
+}
+
+/// Serves the repositories inside a folder to `dvcs://` clients, every connection is answered on a thread of its own
+///
+/// A connection asks for one repository, by its path inside the served folder, and either fetches from it or pushes one branch to it.
+/// Pushes are checked again by the server, so a branch that moved since the client looked at it isn't overwritten
+#[derive(Debug)]
+pub struct Server {
+    listener: TcpListener,
+    root: PathBuf,
+    /// Held while a push updates a repository, the repository lock is shared by every thread of the process
+    push_lock: Arc<Mutex<()>>,
+}
+
+impl Server {
+    /// Listens for clients
+    ///
+    /// #### Arguments
+    ///
+    /// * `address` - Address to listen on, such as `127.0.0.1:9419`, port 0 picks a free port
+    /// * `root` - Folder of the served repositories, clients can't reach anything outside of it
+    pub fn bind(address: &str, root: &str) -> Result<Server, DvcsError> {
+        let root = fs::canonicalize(root).map_err(|e| DvcsError::io(root, e))?;
+        let listener = TcpListener::bind(address).map_err(|e| DvcsError::io(address, e))?;
+        Ok(Server {
+            listener,
+            root,
+            push_lock: Arc::new(Mutex::new(())),
+        })
+    }
+
+    /// Gets the address the server listens on, with the port that was picked for port 0
+    pub fn local_address(&self) -> Result<SocketAddr, DvcsError> {
+        self.listener
+            .local_addr()
+            .map_err(|e| DvcsError::io(&self.root, e))
+    }
+
+    /// Answers clients until listening fails, a connection that fails is passed to `report` and doesn't stop the server
+    pub fn run(&self, report: fn(&DvcsError)) -> Result<(), DvcsError> {
+        for stream in self.listener.incoming() {
+            let stream = stream.map_err(|e| DvcsError::io(&self.root, e))?;
+            let root = self.root.clone();
+            let push_lock = Arc::clone(&self.push_lock);
+            thread::spawn(move || {
+                if let Err(error) = serve_connection(stream, &root, &push_lock) {
+                    report(&error);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+impl RepositoryController {
+    /// Copies the objects of a repository served over the network that this repository is missing, returns the refs of the served repository
+    ///
+    /// The tips of its branches and tags this repository doesn't have are asked for, with the commits this repository has, so the server only sends the missing objects
+    pub(crate) fn fetch_pack(&mut self, address: &NetworkAddress) -> Result<RemoteRefs, DvcsError> {
+        let mut connection = Connection::open(address, "upload-pack")?;
+        let refs = connection.receive_refs()?;
+        let storage = self.storage()?;
+        // * Objects stored by a fetch that failed halfway don't count, only commits in the history are complete
+        let known: HashSet<&str> = self.commit_history.iter().map(String::as_str).collect();
+        let wants: Vec<String> = refs
+            .tips()
+            .into_iter()
+            .filter(|id| !known.contains(id.as_str()))
+            .collect();
+        for want in &wants {
+            connection.send(&format!("want {}", want))?;
+        }
+        if !wants.is_empty() {
+            for have in &self.commit_history {
+                connection.send(&format!("have {}", have))?;
+            }
+        }
+        connection.send("done")?;
+        connection.flush()?;
+        connection.receive_pack(&storage)?;
+        let received = check_connected(&storage, &refs.tips(), |id| known.contains(id))?;
+        self.add_commits(received);
+        Ok(refs)
+    }
+
+    /// Pushes a branch to a repository served over the network (see `push_to`), the server moves its branch only if nobody moved it in the meantime
+    pub(crate) fn push_pack(
+        &mut self,
+        remote: &str,
+        address: &NetworkAddress,
+        branch: &str,
+        mode: PushMode,
+    ) -> Result<RefUpdate, DvcsError> {
+        let mut connection = Connection::open(address, "receive-pack")?;
+        let refs = connection.receive_refs()?;
+        let old = refs.branches.get(branch).cloned();
+        let tip = self.check_push(remote, branch, old.as_ref(), refs.checked_out(), &mode)?;
+        if old.as_ref() == Some(&tip) {
+            connection.send("done")?;
+            connection.flush()?;
+            return self.record_push(remote, branch, old, tip);
+        }
+
+        let haves: HashSet<String> = refs.tips().into_iter().collect();
+        let storage = self.storage()?;
+        let missing = objects_to_send(&storage, std::slice::from_ref(&tip), &haves)?;
+        connection.send(&format!(
+            "update {} {} {}",
+            branch,
+            old.as_deref().unwrap_or("-"),
+            tip
+        ))?;
+        connection.send_pack(&storage, &missing)?;
+        let reply = connection.receive()?;
+        match reply.split_once(' ') {
+            Some(("ok", _)) => self.record_push(remote, branch, old, tip),
+            Some(("rejected", reason)) => Err(DvcsError::PushRejected {
+                branch: branch.to_string(),
+                reason: reason.to_string(),
+            }),
+            _ => Err(unexpected(&reply)),
+        }
+    }
+}
+
+/// Private helper struct reading and writing the lines and packs of the protocol
+///
+/// Every message is a line, objects follow the line announcing them as raw bytes. A line starting with `error` ends the conversation,
+/// the rest of the line says what went wrong
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+    /// Other end of the connection, for errors
+    peer: String,
+}
+
+impl Connection {
+    /// Private helper function wrapping a connected stream
+    fn new(stream: TcpStream, peer: String) -> Result<Connection, DvcsError> {
+        let writer = stream.try_clone().map_err(|e| DvcsError::io(&peer, e))?;
+        Ok(Connection {
+            reader: BufReader::new(stream),
+            writer: BufWriter::new(writer),
+            peer,
+        })
+    }
+
+    /// Private helper function connecting to a server and asking for a repository
+    fn open(address: &NetworkAddress, request: &str) -> Result<Connection, DvcsError> {
+        let peer = address.to_string();
+        let stream = TcpStream::connect((address.host.as_str(), address.port))
+            .map_err(|e| DvcsError::io(&peer, e))?;
+        let mut connection = Connection::new(stream, peer)?;
+        connection.send(&format!("{} /{}", request, address.path))?;
+        connection.flush()?;
+        Ok(connection)
+    }
+
+    fn send(&mut self, line: &str) -> Result<(), DvcsError> {
+        writeln!(self.writer, "{}", line).map_err(|e| DvcsError::io(&self.peer, e))
+    }
+
+    fn flush(&mut self) -> Result<(), DvcsError> {
+        self.writer
+            .flush()
+            .map_err(|e| DvcsError::io(&self.peer, e))
+    }
+
+    /// Private helper function reading the next line without its line ending, an `error` line becomes a `Remote` error
+    fn receive(&mut self) -> Result<String, DvcsError> {
+        let mut line = String::new();
+        (&mut self.reader)
+            .take(MAX_LINE)
+            .read_line(&mut line)
+            .map_err(|e| DvcsError::io(&self.peer, e))?;
+        if line.is_empty() {
+            return Err(DvcsError::Remote(format!(
+                "{} closed the connection",
+                self.peer
+            )));
+        }
+        if !line.ends_with('\n') {
+            return Err(DvcsError::Remote(format!(
+                "{} sent a line that is too long",
+                self.peer
+            )));
+        }
+        let line = line.trim_end_matches('\n');
+        match line.strip_prefix("error ") {
+            Some(message) => Err(DvcsError::Remote(message.to_string())),
+            None => Ok(line.to_string()),
+        }
+    }
+
+    /// Private helper function sending the branches, tags and HEAD of a repository
+    fn send_refs(&mut self, refs: &RemoteRefs) -> Result<(), DvcsError> {
+        for (name, id) in &refs.branches {
+            self.send(&format!("branch {} {}", name, id))?;
+        }
+        for (name, id) in &refs.tags {
+            self.send(&format!("tag {} {}", name, id))?;
+        }
+        match &refs.head {
+            Head::Branch(name) => self.send(&format!("head branch {}", name))?,
+            Head::Detached(id) => self.send(&format!("head detached {}", id))?,
+        }
+        self.send(&format!("bare {}", refs.bare))?;
+        self.send("end")?;
+        self.flush()
+    }
+
+    /// Private helper function reading what `send_refs` sent
+    fn receive_refs(&mut self) -> Result<RemoteRefs, DvcsError> {
+        let mut refs = RemoteRefs {
+            branches: BTreeMap::new(),
+            tags: BTreeMap::new(),
+            head: Head::default(),
+            bare: false,
+        };
+        loop {
+            let line = self.receive()?;
+            let fields: Vec<&str> = line.split(' ').collect();
+            match fields.as_slice() {
+                ["end"] => return Ok(refs),
+                ["branch", name, id] => {
+                    refs.branches.insert(name.to_string(), id.to_string());
+                }
+                ["tag", name, id] => {
+                    refs.tags.insert(name.to_string(), id.to_string());
+                }
+                ["head", "branch", name] => refs.head = Head::Branch(name.to_string()),
+                ["head", "detached", id] => refs.head = Head::Detached(id.to_string()),
+                ["bare", bare] => refs.bare = *bare == "true",
+                _ => return Err(unexpected(&line)),
+            }
+        }
+    }
+
+    /// Private helper function sending missing objects as one pack, every object after the objects it points to
+    fn send_pack(
+        &mut self,
+        storage: &RepositoryStorage,
+        missing: &MissingObjects,
+    ) -> Result<(), DvcsError> {
+        let commits: HashSet<&String> = missing.commits.iter().collect();
+        self.send(&format!("pack {}", missing.objects.len()))?;
+        for id in &missing.objects {
+            let contents = storage.get(id)?;
+            let kind = match commits.contains(id) {
+                true => "commit",
+                false => "object",
+            };
+            self.send(&format!("{} {} {}", kind, id, contents.len()))?;
+            self.writer
+                .write_all(&contents)
+                .map_err(|e| DvcsError::io(&self.peer, e))?;
+        }
+        self.flush()
+    }
+
+    /// Private helper function storing the objects of a pack, nothing checks yet that the objects they point to arrived too (see `check_connected`)
+    fn receive_pack(&mut self, storage: &RepositoryStorage) -> Result<(), DvcsError> {
+        let line = self.receive()?;
+        let count: usize = match line.split_once(' ') {
+            Some(("pack", count)) => count.parse().map_err(|_| unexpected(&line))?,
+            _ => return Err(unexpected(&line)),
+        };
+        for _ in 0..count {
+            let line = self.receive()?;
+            let fields: Vec<&str> = line.split(' ').collect();
+            let (id, length) = match fields.as_slice() {
+                ["commit" | "object", id, length] => (
+                    id.to_string(),
+                    length.parse::<u64>().map_err(|_| unexpected(&line))?,
+                ),
+                _ => return Err(unexpected(&line)),
+            };
+            // * The contents are read as they arrive, a made up length doesn't allocate anything
+            let mut contents = vec![];
+            (&mut self.reader)
+                .take(length)
+                .read_to_end(&mut contents)
+                .map_err(|e| DvcsError::io(&self.peer, e))?;
+            if contents.len() as u64 != length {
+                return Err(DvcsError::Remote(format!(
+                    "{} closed the connection in the middle of a pack",
+                    self.peer
+                )));
+            }
+            if storage.put(&contents)? != id {
+                return Err(DvcsError::ObjectCorrupt {
+                    id,
+                    reason: "the received contents don't match the id".to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Private helper function for a line that doesn't follow the protocol
+fn unexpected(line: &str) -> DvcsError {
+    DvcsError::Remote(format!("unexpected message '{}'", line))
+}
+
+/// Private helper function answering one client, an error is also sent to the client before the connection is closed
+fn serve_connection(
+    stream: TcpStream,
+    root: &Path,
+    push_lock: &Mutex<()>,
+) -> Result<(), DvcsError> {
+    let peer = match stream.peer_addr() {
+        Ok(address) => address.to_string(),
+        Err(_) => "client".to_string(),
+    };
+    stream
+        .set_read_timeout(Some(IDLE_TIMEOUT))
+        .map_err(|e| DvcsError::io(&peer, e))?;
+    let mut connection = Connection::new(stream, peer)?;
+    let result = connection
+        .receive()
+        .and_then(|request| match request.split_once(' ') {
+            Some(("upload-pack", path)) => upload_pack(&mut connection, root, path),
+            Some(("receive-pack", path)) => receive_pack(&mut connection, root, path, push_lock),
+            _ => Err(unexpected(&request)),
+        });
+    if let Err(error) = &result {
+        // * The client may be gone already, the error is reported either way
+        let _ = connection.send(&format!("error {}", error.to_string().replace('\n', " ")));
+        let _ = connection.flush();
+    }
+    result
+}
+
+/// Private helper function opening a repository inside the served folder, paths that lead out of it are refused
+fn open_served(root: &Path, path: &str) -> Result<RepositoryController, DvcsError> {
+    let not_found = || DvcsError::NotARepository(PathBuf::from(path));
+    let relative = Path::new(path.trim_start_matches('/'));
+    if relative
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_)))
+    {
+        return Err(not_found());
+    }
+    // * A symbolic link could still lead out of the served folder
+    match root.join(relative).canonicalize() {
+        Ok(folder) if folder.starts_with(root) => {
+            RepositoryController::open(&folder.display().to_string()).map_err(|e| match e {
+                DvcsError::NotARepository(_) => not_found(),
+                e => e,
+            })
+        }
+        _ => Err(not_found()),
+    }
+}
+
+/// Private helper function sending a client the objects it wants, without the objects reachable from the commits it has
+fn upload_pack(connection: &mut Connection, root: &Path, path: &str) -> Result<(), DvcsError> {
+    let repository = open_served(root, path)?;
+    connection.send_refs(&repository.remote_refs())?;
+    let mut wants = vec![];
+    let mut haves = HashSet::new();
+    loop {
+        let line = connection.receive()?;
+        match line.split_once(' ') {
+            Some(("want", id)) => {
+                // * Only commits of the repository can be asked for, not any object that happens to be stored
+                repository.get_commit(id)?;
+                wants.push(id.to_string());
+            }
+            Some(("have", id)) => {
+                haves.insert(id.to_string());
+            }
+            _ if line == "done" => break,
+            _ => return Err(unexpected(&line)),
+        }
+    }
+    let storage = repository.storage()?;
+    let missing = objects_to_send(&storage, &wants, &haves)?;
+    connection.send_pack(&storage, &missing)
+}
+
+/// Private helper function storing the objects a client pushes and moving the branch, as long as it is still where the client saw it
+fn receive_pack(
+    connection: &mut Connection,
+    root: &Path,
+    path: &str,
+    push_lock: &Mutex<()>,
+) -> Result<(), DvcsError> {
+    let repository = open_served(root, path)?;
+    connection.send_refs(&repository.remote_refs())?;
+    let line = connection.receive()?;
+    if line == "done" {
+        return Ok(());
+    }
+    let fields: Vec<&str> = line.split(' ').collect();
+    let (branch, expected, tip) = match fields.as_slice() {
+        ["update", branch, expected, tip] => (
+            branch.to_string(),
+            Some(expected.to_string()).filter(|expected| expected != "-"),
+            tip.to_string(),
+        ),
+        _ => return Err(unexpected(&line)),
+    };
+    RepositoryController::check_ref_name(&branch, "branch")?;
+    let storage = repository.storage()?;
+    connection.receive_pack(&storage)?;
+    let known: HashSet<&str> = repository
+        .commit_history
+        .iter()
+        .map(String::as_str)
+        .collect();
+    let received = check_connected(&storage, std::slice::from_ref(&tip), |id| {
+        known.contains(id)
+    })?;
+
+    // * Read again once locked, so nothing moves the branch between checking and updating it
+    let _guard = push_lock
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let _lock = RepositoryLock::acquire(&repository.hidden_folder())?;
+    let mut repository = RepositoryController::open(&repository.dvcs_hidden)?;
+    let old = repository.branch_heads.get(&branch).cloned();
+    if old != expected {
+        let reason = unexpected_tip(old.as_ref(), expected.as_ref());
+        return connection.send(&format!("rejected {}, fetch first", reason));
+    }
+    if repository.remote_refs().checked_out() == Some(branch.as_str()) {
+        return connection.send("rejected it is checked out in the remote repository");
+    }
+    repository.add_commits(received);
+    repository.branch_heads.insert(branch.clone(), tip);
+    repository.save_locally()?;
+    connection.send(&format!("ok {}", branch))?;
+    connection.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote::{clone_repository, DEFAULT_REMOTE};
+    use crate::test_support::{commit_file, test_folder};
+    use crate::DEFAULT_BRANCH;
+
+    /// Gets an empty folder for a test of the network transport
+    fn network_folder(name: &str) -> PathBuf {
+        let root = test_folder(&format!("network_{}", name));
+        fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    /// Serves a folder on a free port of localhost and returns the url of the folder
+    fn serve(root: &Path) -> String {
+        let server = Server::bind("127.0.0.1:0", root.to_str().unwrap()).unwrap();
+        let address = server.local_address().unwrap();
+        thread::spawn(move || server.run(|_| {}));
+        format!("dvcs://{}", address)
+    }
+
+    #[test]
+    fn test_network_address() {
+        assert_eq!(
+            NetworkAddress::parse("dvcs://example.com:7000/team/project/").unwrap(),
+            NetworkAddress {
+                host: "example.com".to_string(),
+                port: 7000,
+                path: "team/project".to_string(),
+            }
+        );
+        let address = NetworkAddress::parse("dvcs://[::1]/project").unwrap();
+        assert_eq!((address.host.as_str(), address.port), ("::1", DEFAULT_PORT));
+        assert_eq!(address.to_string(), "dvcs://[::1]:9419/project");
+        assert_eq!(NetworkAddress::parse("dvcs://host").unwrap().path, "");
+        for url in [
+            "dvcs://:7000/project",
+            "dvcs://host:port/project",
+            "file:///tmp",
+        ] {
+            assert!(matches!(
+                NetworkAddress::parse(url),
+                Err(DvcsError::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    // * Clone, fetch and push work against a server on localhost, with the same checks as a remote on the local filesystem
+    fn test_network_sync() {
+        let root = network_folder("sync");
+        fs::create_dir_all(root.join("hub")).unwrap();
+        RepositoryController::init_bare(root.join("hub").to_str().unwrap()).unwrap();
+        let url = serve(&root) + "/hub";
+
+        // * Cloning an empty repository gives an empty clone
+        let first_clone = root.join("first").display().to_string();
+        let mut first = clone_repository(&url, &first_clone).unwrap();
+        assert!(first.list_branches().is_empty());
+        assert_eq!(first.remote_url(DEFAULT_REMOTE).unwrap(), url);
+
+        let one = commit_file(&mut first, "a.txt", "a\n");
+        let update = first
+            .push(DEFAULT_REMOTE, DEFAULT_BRANCH, PushMode::FastForward)
+            .unwrap();
+        assert_eq!((update.old, update.new), (None, Some(one.clone())));
+        let two = commit_file(&mut first, "b.txt", "b\n");
+        first
+            .push(DEFAULT_REMOTE, DEFAULT_BRANCH, PushMode::FastForward)
+            .unwrap();
+        first.create_tag("v1", Some(&one)).unwrap();
+        let hub = RepositoryController::open(root.join("hub").to_str().unwrap()).unwrap();
+        assert_eq!(hub.resolve_revision(DEFAULT_BRANCH).unwrap(), two);
+        assert_eq!(hub.commit_history, vec![one.clone(), two.clone()]);
+
+        let second_clone = root.join("second").display().to_string();
+        let mut second = clone_repository(&url, &second_clone).unwrap();
+        assert_eq!(second.head_commit(), Some(two.clone()));
+        assert_eq!(
+            fs::read_to_string(root.join("second/b.txt")).unwrap(),
+            "b\n"
+        );
+
+        // * Fetching only brings the new commit
+        let three = commit_file(&mut first, "a.txt", "a3\n");
+        first
+            .push(DEFAULT_REMOTE, DEFAULT_BRANCH, PushMode::FastForward)
+            .unwrap();
+        let updates = second.fetch(DEFAULT_REMOTE).unwrap();
+        assert_eq!(
+            updates,
+            vec![RefUpdate {
+                name: "origin/master".to_string(),
+                old: Some(two.clone()),
+                new: Some(three.clone()),
+            }]
+        );
+        assert_eq!(second.commit_history, vec![one, two, three.clone()]);
+        assert!(second.fetch(DEFAULT_REMOTE).unwrap().is_empty());
+
+        // * The branch of the hub moved since the second clone looked at it
+        let ours = commit_file(&mut second, "c.txt", "c\n");
+        commit_file(&mut first, "a.txt", "a4\n");
+        first
+            .push(DEFAULT_REMOTE, DEFAULT_BRANCH, PushMode::FastForward)
+            .unwrap();
+        assert!(matches!(
+            second.push(DEFAULT_REMOTE, DEFAULT_BRANCH, PushMode::FastForward),
+            Err(DvcsError::PushRejected { .. })
+        ));
+        assert!(matches!(
+            second.push(
+                DEFAULT_REMOTE,
+                DEFAULT_BRANCH,
+                PushMode::ForceWithLease(None)
+            ),
+            Err(DvcsError::PushRejected { .. })
+        ));
+        second
+            .push(DEFAULT_REMOTE, DEFAULT_BRANCH, PushMode::Force)
+            .unwrap();
+        let hub = RepositoryController::open(root.join("hub").to_str().unwrap()).unwrap();
+        assert_eq!(hub.resolve_revision(DEFAULT_BRANCH).unwrap(), ours);
+    }
+
+    /// Writes a commit with one file into a scratch object database and returns its id and contents, without the tree and blob it points to
+    fn lone_commit(name: &str) -> (String, Vec<u8>) {
+        let folder = network_folder(name);
+        fs::create_dir_all(folder.join(".dvcs_hidden")).unwrap();
+        let mut scratch = RepositoryController::new(folder.to_str().unwrap()).unwrap();
+        let commit = commit_file(&mut scratch, "a.txt", "a\n");
+        let contents = scratch.storage().unwrap().get(&commit).unwrap();
+        (commit, contents)
+    }
+
+    #[test]
+    // * A pushed commit whose tree never arrived doesn't move the branch of the server
+    fn test_push_without_objects() {
+        let root = network_folder("push_without_objects");
+        fs::create_dir_all(root.join("hub")).unwrap();
+        RepositoryController::init_bare(root.join("hub").to_str().unwrap()).unwrap();
+        let address = NetworkAddress::parse(&(serve(&root) + "/hub")).unwrap();
+        let (commit, contents) = lone_commit("push_without_objects_scratch");
+
+        let mut connection = Connection::open(&address, "receive-pack").unwrap();
+        connection.receive_refs().unwrap();
+        connection
+            .send(&format!("update {} - {}", DEFAULT_BRANCH, commit))
+            .unwrap();
+        connection.send("pack 1").unwrap();
+        connection
+            .send(&format!("commit {} {}", commit, contents.len()))
+            .unwrap();
+        connection.writer.write_all(&contents).unwrap();
+        connection.flush().unwrap();
+        assert!(matches!(connection.receive(), Err(DvcsError::Remote(_))));
+        let hub = RepositoryController::open(root.join("hub").to_str().unwrap()).unwrap();
+        assert!(hub.list_branches().is_empty());
+        assert!(hub.commit_history.is_empty());
+    }
+
+    #[test]
+    // * A server that leaves out the tree of a commit can't be cloned or fetched from, not even once the commit itself is stored
+    fn test_fetch_without_objects() {
+        let root = network_folder("fetch_without_objects");
+        let (commit, contents) = lone_commit("fetch_without_objects_scratch");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            // * The commit is sent to the clone and the first fetch, the second fetch gets an empty pack
+            for pack in [1, 1, 0] {
+                let (stream, _) = listener.accept().unwrap();
+                let mut connection = Connection::new(stream, "client".to_string()).unwrap();
+                connection.receive().unwrap();
+                connection
+                    .send(&format!("branch master {}", commit))
+                    .unwrap();
+                connection.send("head branch master").unwrap();
+                connection.send("bare true").unwrap();
+                connection.send("end").unwrap();
+                connection.flush().unwrap();
+                while connection.receive().unwrap() != "done" {}
+                connection.send(&format!("pack {}", pack)).unwrap();
+                if pack == 1 {
+                    connection
+                        .send(&format!("commit {} {}", commit, contents.len()))
+                        .unwrap();
+                    connection.writer.write_all(&contents).unwrap();
+                }
+                connection.flush().unwrap();
+            }
+        });
+        let url = format!("dvcs://{}/hub", address);
+
+        let destination = root.join("clone");
+        assert!(matches!(
+            clone_repository(&url, destination.to_str().unwrap()),
+            Err(DvcsError::ObjectMissing(_))
+        ));
+        assert!(!destination.exists());
+
+        fs::create_dir_all(root.join("local/.dvcs_hidden")).unwrap();
+        let mut local = RepositoryController::new(root.join("local").to_str().unwrap()).unwrap();
+        local.add_remote(DEFAULT_REMOTE, &url).unwrap();
+        for _ in 0..2 {
+            assert!(matches!(
+                local.fetch(DEFAULT_REMOTE),
+                Err(DvcsError::ObjectMissing(_))
+            ));
+            assert!(local.list_remote_branches().is_empty());
+            assert!(local.commit_history.is_empty());
+        }
+    }
+
+    #[test]
+    // * The server refuses paths outside of its folder and branches checked out in a working directory
+    fn test_server_refusals() {
+        let root = network_folder("refusals");
+        let served = root.join("served");
+        fs::create_dir_all(served.join("project/.dvcs_hidden")).unwrap();
+        let mut project =
+            RepositoryController::new(served.join("project").to_str().unwrap()).unwrap();
+        commit_file(&mut project, "a.txt", "a\n");
+        let url = serve(&served);
+
+        for path in ["/../served/project", "/missing", "/"] {
+            let destination = root.join("clone").display().to_string();
+            assert!(matches!(
+                clone_repository(&(url.clone() + path), &destination),
+                Err(DvcsError::Remote(_))
+            ));
+            assert!(!Path::new(&destination).exists());
+        }
+
+        let destination = root.join("clone").display().to_string();
+        let mut clone = clone_repository(&(url.clone() + "/project"), &destination).unwrap();
+        commit_file(&mut clone, "b.txt", "b\n");
+        assert!(matches!(
+            clone.push(DEFAULT_REMOTE, DEFAULT_BRANCH, PushMode::Force),
+            Err(DvcsError::PushRejected { .. })
+        ));
+        clone.create_branch("topic", None).unwrap();
+        clone
+            .push(DEFAULT_REMOTE, "topic", PushMode::FastForward)
+            .unwrap();
+        assert!(
+            RepositoryController::open(served.join("project").to_str().unwrap())
+                .unwrap()
+                .resolve_revision("topic")
+                .is_ok()
+        );
+
+        // * Nobody listens anymore on a port that was given back
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        assert!(matches!(
+            clone_repository(
+                &format!("dvcs://127.0.0.1:{}/project", port),
+                &root.join("nobody").display().to_string()
+            ),
+            Err(DvcsError::Io { .. })
+        ));
+    }
+}
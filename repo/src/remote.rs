@@ -0,0 +1,916 @@
+use crate::merge::MergeOutcome;
+use crate::network::NetworkAddress;
+use crate::{is_bare_repository, Head, RepositoryController, DEFAULT_BRANCH};
+use dvcs_error::DvcsError;
+use staging::staging_storage::Staging;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use storage_hiding::object_transfer::copy_objects;
+use storage_hiding::repository_lock::RepositoryLock;
+
+/// Name of the remote a clone is made from
+pub const DEFAULT_REMOTE: &str = "origin";
+
+/// Change of a ref made while fetching, None when the ref didn't exist before or doesn't exist anymore
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefUpdate {
+    /// Name of the ref, `<remote>/<branch>` for remote-tracking branches
+    pub name: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// How far a push may move the branch of the remote
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PushMode {
+    /// Only forward, the remote branch has to be part of the pushed history
+    FastForward,
+    /// Anywhere, commits only the remote has are dropped from the branch
+    Force,
+    /// Anywhere as long as the remote branch is still at the expected revision, None to expect it where the remote-tracking branch is
+    ForceWithLease(Option<String>),
+}
+
+/// Where the repository of a remote is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    /// Folder of a repository on the local filesystem
+    Local(PathBuf),
+    /// Repository served by `dvcs serve` on another machine
+    Network(NetworkAddress),
+}
+
+impl Location {
+    /// Gets the last part of the path of the repository, what a clone is named after
+    pub fn repository_name(&self) -> Option<String> {
+        match self {
+            Location::Local(path) => path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string()),
+            Location::Network(address) => address
+                .path
+                .rsplit('/')
+                .find(|part| !part.is_empty())
+                .map(|part| part.to_string()),
+        }
+    }
+}
+
+/// Finds a repository from its url, a path, a `file://` url or a `dvcs://host:port/path` url
+pub fn location(url: &str) -> Result<Location, DvcsError> {
+    if let Some(path) = url.strip_prefix("file://") {
+        return Ok(Location::Local(PathBuf::from(path)));
+    }
+    match url.split_once("://") {
+        Some(("dvcs", _)) => Ok(Location::Network(NetworkAddress::parse(url)?)),
+        Some((scheme, _)) => Err(DvcsError::InvalidArgument(format!(
+            "'{}' urls are not supported: {}",
+            scheme, url
+        ))),
+        None => Ok(Location::Local(PathBuf::from(url))),
+    }
+}
+
+/// Branches, tags and HEAD a repository shows the repositories that fetch from it or push to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteRefs {
+    /// Every branch with the id of its tip commit
+    pub branches: BTreeMap<String, String>,
+    /// Every tag with the id of its commit
+    pub tags: BTreeMap<String, String>,
+    pub head: Head,
+    pub bare: bool,
+}
+
+impl RemoteRefs {
+    /// Gets the commits of every branch and tag, what is copied when fetching
+    pub fn tips(&self) -> Vec<String> {
+        let mut tips: Vec<String> = self.branches.values().cloned().collect();
+        tips.extend(self.tags.values().cloned());
+        tips.sort();
+        tips.dedup();
+        tips
+    }
+
+    /// Gets the branch checked out in the working directory, None for a bare repository or a detached HEAD
+    pub fn checked_out(&self) -> Option<&str> {
+        match &self.head {
+            Head::Branch(name) if !self.bare => Some(name),
+            _ => None,
+        }
+    }
+}
+
+impl RepositoryController {
+    /// Opens the repository of a working directory that already has one, or a bare repository, unlike `new` nothing is created
+    pub fn open(working_directory: &str) -> Result<RepositoryController, DvcsError> {
+        let hidden_folder = Path::new(working_directory).join(".dvcs_hidden");
+        if !hidden_folder.is_dir() && !is_bare_repository(Path::new(working_directory)) {
+            return Err(DvcsError::NotARepository(PathBuf::from(working_directory)));
+        }
+        Self::new(working_directory)
+    }
+
+    /// Adds a remote repository under a name
+    ///
+    /// #### Arguments
+    ///
+    /// * `name` - Name the remote and its remote-tracking branches are known by
+    /// * `url` - Path or `file://` url of the remote repository
+    pub fn add_remote(&mut self, name: &str, url: &str) -> Result<(), DvcsError> {
+        Self::check_ref_name(name, "remote")?;
+        if name.contains('/') {
+            return Err(DvcsError::InvalidArgument(format!(
+                "'{}' is not a valid remote name",
+                name
+            )));
+        }
+        if self.remotes.contains_key(name) {
+            return Err(DvcsError::InvalidArgument(format!(
+                "Remote '{}' already exists",
+                name
+            )));
+        }
+        self.remotes.insert(name.to_string(), url.to_string());
+        self.save_locally()
+    }
+
+    /// Removes a remote together with its remote-tracking branches
+    pub fn remove_remote(&mut self, name: &str) -> Result<(), DvcsError> {
+        self.remote_url(name)?;
+        self.remotes.remove(name);
+        let prefix = format!("{}/", name);
+        self.remote_branches
+            .retain(|branch, _| !branch.starts_with(&prefix));
+        self.save_locally()
+    }
+
+    /// Gets the url of a remote
+    pub fn remote_url(&self, name: &str) -> Result<&str, DvcsError> {
+        match self.remotes.get(name) {
+            Some(url) => Ok(url),
+            None => Err(DvcsError::InvalidArgument(format!(
+                "No remote named '{}'",
+                name
+            ))),
+        }
+    }
+
+    /// Lists every remote with its url, sorted by name
+    pub fn list_remotes(&self) -> Vec<(String, String)> {
+        let mut remotes: Vec<(String, String)> = self
+            .remotes
+            .iter()
+            .map(|(name, url)| (name.clone(), url.clone()))
+            .collect();
+        remotes.sort();
+        remotes
+    }
+
+    /// Lists every remote-tracking branch (`<remote>/<branch>`) with the id of its tip commit, sorted by name
+    pub fn list_remote_branches(&self) -> Vec<(String, String)> {
+        let mut branches: Vec<(String, String)> = self
+            .remote_branches
+            .iter()
+            .map(|(name, tip)| (name.clone(), tip.clone()))
+            .collect();
+        branches.sort();
+        branches
+    }
+
+    /// Gets the branches, tags and HEAD this repository shows the repositories that fetch from it or push to it
+    pub fn remote_refs(&self) -> RemoteRefs {
+        RemoteRefs {
+            branches: self.branch_heads.clone().into_iter().collect(),
+            tags: self.tags.clone().into_iter().collect(),
+            head: self.head.clone(),
+            bare: self.bare,
+        }
+    }
+
+    /// Copies the branches and tags of another repository into this one and returns the remote-tracking branches that changed
+    ///
+    /// The objects reachable from the branches and tags that this repository is missing are copied, every branch of the source becomes the remote-tracking branch `<remote>/<branch>` and remote-tracking branches of branches the source deleted are removed. Tags this repository doesn't have yet are added, existing tags are kept
+    ///
+    /// #### Arguments
+    ///
+    /// * `remote` - Name of the remote the source repository is known by
+    /// * `source` - Repository to copy from
+    pub fn fetch_from(
+        &mut self,
+        remote: &str,
+        source: &RepositoryController,
+    ) -> Result<Vec<RefUpdate>, DvcsError> {
+        let refs = source.remote_refs();
+        let known: HashSet<&str> = self.commit_history.iter().map(String::as_str).collect();
+        let copied = copy_objects(&source.storage()?, &self.storage()?, &refs.tips(), |id| {
+            known.contains(id)
+        })?;
+        self.add_commits(copied);
+        self.record_fetch(remote, &refs)
+    }
+
+    /// Moves the remote-tracking branches of a remote to its fetched branches and adds its new tags, the objects have to be there already (see `fetch_from`)
+    pub(crate) fn record_fetch(
+        &mut self,
+        remote: &str,
+        refs: &RemoteRefs,
+    ) -> Result<Vec<RefUpdate>, DvcsError> {
+        let prefix = format!("{}/", remote);
+        let mut updates = vec![];
+        for (branch, tip) in &refs.branches {
+            let name = prefix.clone() + branch;
+            let old = self.remote_branches.insert(name.clone(), tip.clone());
+            if old.as_ref() != Some(tip) {
+                updates.push(RefUpdate {
+                    name,
+                    old,
+                    new: Some(tip.clone()),
+                });
+            }
+        }
+        let stale: Vec<String> = self
+            .remote_branches
+            .keys()
+            .filter(|name| {
+                name.strip_prefix(&prefix)
+                    .is_some_and(|branch| !refs.branches.contains_key(branch))
+            })
+            .cloned()
+            .collect();
+        for name in stale {
+            let old = self.remote_branches.remove(&name);
+            updates.push(RefUpdate {
+                name,
+                old,
+                new: None,
+            });
+        }
+        for (tag, commit_id) in &refs.tags {
+            self.tags
+                .entry(tag.clone())
+                .or_insert_with(|| commit_id.clone());
+        }
+        updates.sort_by(|a, b| a.name.cmp(&b.name));
+        self.save_locally()?;
+        Ok(updates)
+    }
+
+    /// Adds copied commits to the commit history, skipping the ones it already has
+    pub(crate) fn add_commits(&mut self, copied: Vec<String>) {
+        let known: HashSet<String> = self.commit_history.iter().cloned().collect();
+        self.commit_history
+            .extend(copied.into_iter().filter(|id| !known.contains(id)));
+    }
+
+    /// Fetches the branches and tags of a remote, on the local filesystem or over the network (see `fetch_from`)
+    pub fn fetch(&mut self, remote: &str) -> Result<Vec<RefUpdate>, DvcsError> {
+        match location(self.remote_url(remote)?)? {
+            Location::Local(source) => {
+                let source = RepositoryController::open(&source.display().to_string())?;
+                self.fetch_from(remote, &source)
+            }
+            Location::Network(address) => {
+                let refs = self.fetch_pack(&address)?;
+                self.record_fetch(remote, &refs)
+            }
+        }
+    }
+
+    /// Pushes a branch to a remote (see `push_to`), a remote on the local filesystem stays locked while it is updated
+    pub fn push(
+        &mut self,
+        remote: &str,
+        branch: &str,
+        mode: PushMode,
+    ) -> Result<RefUpdate, DvcsError> {
+        let target = match location(self.remote_url(remote)?)? {
+            Location::Local(target) => target.display().to_string(),
+            Location::Network(address) => return self.push_pack(remote, &address, branch, mode),
+        };
+        let hidden_folder = RepositoryController::open(&target)?.hidden_folder();
+        let _lock = RepositoryLock::acquire(&hidden_folder)?;
+        // * Read again once locked, so nothing moves the branch between checking and updating it
+        let mut target = RepositoryController::open(&target)?;
+        self.push_to(remote, &mut target, branch, mode)
+    }
+
+    /// Copies the commits of a branch another repository is missing and moves its branch of the same name, returns how the branch moved
+    ///
+    /// The branch of the other repository has to be part of the pushed history unless the push is forced, and with a lease it has to be where it was expected. A branch checked out in the working directory of the other repository is never moved, as that would leave its working directory behind, bare repositories take any branch. The remote-tracking branch follows the pushed branch
+    ///
+    /// #### Arguments
+    ///
+    /// * `remote` - Name of the remote the target repository is known by
+    /// * `target` - Repository to push to
+    /// * `branch` - Local branch to push
+    /// * `mode` - How far the branch of the target may move
+    pub fn push_to(
+        &mut self,
+        remote: &str,
+        target: &mut RepositoryController,
+        branch: &str,
+        mode: PushMode,
+    ) -> Result<RefUpdate, DvcsError> {
+        let old = target.branch_heads.get(branch).cloned();
+        let refs = target.remote_refs();
+        let tip = self.check_push(remote, branch, old.as_ref(), refs.checked_out(), &mode)?;
+        if old.as_ref() != Some(&tip) {
+            let known: HashSet<&str> = target.commit_history.iter().map(String::as_str).collect();
+            let copied = copy_objects(
+                &self.storage()?,
+                &target.storage()?,
+                std::slice::from_ref(&tip),
+                |id| known.contains(id),
+            )?;
+            target.add_commits(copied);
+            target.branch_heads.insert(branch.to_string(), tip.clone());
+            target.save_locally()?;
+        }
+        self.record_push(remote, branch, old, tip)
+    }
+
+    /// Checks that a push may move the branch of a remote from `old` and returns the tip it moves to (see `push_to`)
+    ///
+    /// #### Arguments
+    ///
+    /// * `remote` - Name of the remote the target repository is known by
+    /// * `branch` - Local branch to push
+    /// * `old` - Tip of the branch in the target repository, None if it doesn't have it
+    /// * `checked_out` - Branch checked out in the working directory of the target repository
+    /// * `mode` - How far the branch of the target may move
+    pub(crate) fn check_push(
+        &self,
+        remote: &str,
+        branch: &str,
+        old: Option<&String>,
+        checked_out: Option<&str>,
+        mode: &PushMode,
+    ) -> Result<String, DvcsError> {
+        let tip = match self.branch_heads.get(branch) {
+            Some(tip) => tip.clone(),
+            None => return Err(DvcsError::BranchNotFound(branch.to_string())),
+        };
+        let rejected = |reason: String| DvcsError::PushRejected {
+            branch: branch.to_string(),
+            reason,
+        };
+        let tracking = format!("{}/{}", remote, branch);
+        match mode {
+            PushMode::FastForward => {
+                if let Some(old) = old {
+                    // * Commits only the remote has can't be reached from the pushed tip, whether they were fetched or not
+                    if !self.is_ancestor(old, &tip)? {
+                        return Err(rejected("the remote has commits the pushed branch doesn't have, pull them first or use --force".to_string()));
+                    }
+                }
+            }
+            PushMode::Force => {}
+            PushMode::ForceWithLease(expected) => {
+                let expected = match expected {
+                    Some(expected) => Some(self.resolve_revision(expected)?),
+                    None => self.remote_branches.get(&tracking).cloned(),
+                };
+                if old != expected.as_ref() {
+                    return Err(rejected(unexpected_tip(old, expected.as_ref())));
+                }
+            }
+        }
+        if checked_out == Some(branch) && old != Some(&tip) {
+            return Err(rejected(
+                "it is checked out in the remote repository".to_string(),
+            ));
+        }
+        Ok(tip)
+    }
+
+    /// Moves the remote-tracking branch of a pushed branch and returns how the branch of the remote moved
+    pub(crate) fn record_push(
+        &mut self,
+        remote: &str,
+        branch: &str,
+        old: Option<String>,
+        tip: String,
+    ) -> Result<RefUpdate, DvcsError> {
+        let tracking = format!("{}/{}", remote, branch);
+        self.remote_branches.insert(tracking, tip.clone());
+        self.save_locally()?;
+        Ok(RefUpdate {
+            name: branch.to_string(),
+            old,
+            new: Some(tip),
+        })
+    }
+
+    /// Fetches a remote and merges one of its branches into the current branch (see `merge`)
+    ///
+    /// #### Arguments
+    ///
+    /// * `staging` - Staging index of the working directory
+    /// * `remote` - Name of the remote to pull from
+    /// * `branch` - Branch of the remote to merge, None for the branch with the same name as the current branch
+    pub fn pull(
+        &mut self,
+        staging: &mut Staging,
+        remote: &str,
+        branch: Option<&str>,
+    ) -> Result<(Vec<RefUpdate>, MergeOutcome), DvcsError> {
+        self.require_working_directory()?;
+        let branch = match (branch, self.current_branch()) {
+            (Some(branch), _) | (None, Some(branch)) => branch.to_string(),
+            (None, None) => {
+                return Err(DvcsError::InvalidArgument(
+                    "HEAD is detached, name the branch to pull".to_string(),
+                ))
+            }
+        };
+        let updates = self.fetch(remote)?;
+        let tracking = format!("{}/{}", remote, branch);
+        if !self.remote_branches.contains_key(&tracking) {
+            return Err(DvcsError::BranchNotFound(tracking));
+        }
+        let message = format!("Merge branch '{}' of {}", branch, self.remote_url(remote)?);
+        let outcome = self.merge(staging, &tracking, &message)?;
+        Ok((updates, outcome))
+    }
+}
+
+/// Describes why a branch that moved since it was last seen can't be pushed to
+pub(crate) fn unexpected_tip(tip: Option<&String>, expected: Option<&String>) -> String {
+    let describe = |commit_id: Option<&String>| match commit_id {
+        Some(commit_id) => commit_id.chars().take(7).collect(),
+        None => "nothing".to_string(),
+    };
+    format!(
+        "it is at {} but {} was expected",
+        describe(tip),
+        describe(expected)
+    )
+}
+
+/// Copies a repository into a new working directory and checks out its default branch
+///
+/// The source becomes the `origin` remote and its branches the remote-tracking branches `origin/<branch>`. The default branch is the branch HEAD of the source points to, or master, or else the first branch by name, a local branch with the same name is created from it and checked out. The destination is removed again if cloning fails
+///
+/// #### Arguments
+///
+/// * `source` - Path, `file://` or `dvcs://` url of the repository to clone
+/// * `destination` - Folder to create the new working directory in, it must not exist or be empty
+pub fn clone_repository(
+    source: &str,
+    destination: &str,
+) -> Result<RepositoryController, DvcsError> {
+    let source_location = location(source)?;
+    let source_repository = match &source_location {
+        Location::Local(path) => Some(RepositoryController::open(&path.display().to_string())?),
+        Location::Network(_) => None,
+    };
+    let existed = Path::new(destination).exists();
+    if existed
+        && fs::read_dir(destination)
+            .map_err(|e| DvcsError::io(destination, e))?
+            .next()
+            .is_some()
+    {
+        return Err(DvcsError::InvalidArgument(format!(
+            "Destination path '{}' already exists and is not an empty folder",
+            destination
+        )));
+    }
+    // * The remote keeps working from another folder, so it is saved as an absolute path
+    let url = match &source_location {
+        Location::Local(path) if !source.starts_with("file://") => match path.canonicalize() {
+            Ok(path) => path.display().to_string(),
+            Err(_) => source.to_string(),
+        },
+        _ => source.to_string(),
+    };
+    let result = clone_into(&url, destination, |repository| {
+        match (&source_repository, &source_location) {
+            (Some(source), _) => {
+                repository.fetch_from(DEFAULT_REMOTE, source)?;
+                Ok(source.remote_refs())
+            }
+            (None, Location::Network(address)) => {
+                let refs = repository.fetch_pack(address)?;
+                repository.record_fetch(DEFAULT_REMOTE, &refs)?;
+                Ok(refs)
+            }
+            (None, Location::Local(path)) => Err(DvcsError::NotARepository(path.clone())),
+        }
+    });
+    if result.is_err() {
+        // * Don't leave a half copied repository behind, an empty destination that already existed stays
+        let _ = fs::remove_dir_all(destination);
+        if existed {
+            let _ = fs::create_dir(destination);
+        }
+    }
+    result
+}
+
+/// Private helper function filling an empty destination folder with a clone, `fetch` copies the source into the new repository and returns its refs
+fn clone_into(
+    url: &str,
+    destination: &str,
+    fetch: impl FnOnce(&mut RepositoryController) -> Result<RemoteRefs, DvcsError>,
+) -> Result<RepositoryController, DvcsError> {
+    let hidden_folder = destination.to_owned() + "/.dvcs_hidden";
+    fs::create_dir_all(&hidden_folder).map_err(|e| DvcsError::io(&hidden_folder, e))?;
+    let mut repository = RepositoryController::new(destination)?;
+    repository.add_remote(DEFAULT_REMOTE, url)?;
+    let source = fetch(&mut repository)?;
+
+    let default_branch = match &source.head {
+        Head::Branch(name) if source.branches.contains_key(name) => Some(name.clone()),
+        _ if source.branches.contains_key(DEFAULT_BRANCH) => Some(DEFAULT_BRANCH.to_string()),
+        _ => source.branches.keys().next().cloned(),
+    };
+    match default_branch {
+        Some(name) => {
+            repository
+                .branch_heads
+                .insert(name.clone(), source.branches[&name].clone());
+            let mut staging = Staging::new(&hidden_folder, destination)?;
+            repository.switch_branch(&mut staging, &name, false)?;
+        }
+        // * Nothing was committed to the source yet, the clone is on the same unborn branch
+        None => {
+            if let Head::Branch(name) = &source.head {
+                repository.head = Head::Branch(name.clone());
+            }
+            repository.save_locally()?;
+        }
+    }
+    Ok(repository)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{commit_file, commit_files, staging, test_folder, test_repository};
+
+    #[test]
+    fn test_location() {
+        assert_eq!(
+            location("../other").unwrap(),
+            Location::Local(PathBuf::from("../other"))
+        );
+        assert_eq!(
+            location("file:///tmp/other").unwrap(),
+            Location::Local(PathBuf::from("/tmp/other"))
+        );
+        let network = location("dvcs://example.com:7000/team/project").unwrap();
+        assert!(matches!(&network, Location::Network(address) if address.port == 7000));
+        assert_eq!(network.repository_name(), Some("project".to_string()));
+        assert!(matches!(
+            location("https://example.com/repo"),
+            Err(DvcsError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    // * A clone has every commit, remote-tracking branches for every branch and the default branch checked out
+    fn test_clone_repository() {
+        let mut source = test_repository("clone_source");
+        let mut source_staging = staging(&source);
+        let first = commit_files(
+            &mut source,
+            &mut source_staging,
+            &[("src/a.txt", "a")],
+            "First",
+        );
+        source.create_branch("topic", None).unwrap();
+        source
+            .switch_branch(&mut source_staging, "topic", false)
+            .unwrap();
+        let topic = commit_files(&mut source, &mut source_staging, &[("b.txt", "b")], "Topic");
+        source.create_tag("v1", Some(&first)).unwrap();
+
+        let destination = test_folder("clone_destination");
+        let url = format!("file://{}", source.dvcs_hidden);
+        let clone = clone_repository(&url, destination.to_str().unwrap()).unwrap();
+        assert_eq!(clone.current_branch(), Some("topic"));
+        assert_eq!(clone.head_commit(), Some(topic.clone()));
+        assert_eq!(clone.commit_history, vec![first.clone(), topic.clone()]);
+        assert_eq!(
+            clone.list_remote_branches(),
+            vec![
+                ("origin/master".to_string(), first.clone()),
+                ("origin/topic".to_string(), topic)
+            ]
+        );
+        assert_eq!(clone.resolve_revision("origin/master").unwrap(), first);
+        assert_eq!(clone.list_tags(), vec![("v1".to_string(), first)]);
+        assert_eq!(clone.remote_url(DEFAULT_REMOTE).unwrap(), url);
+        assert_eq!(
+            fs::read_to_string(destination.join("src/a.txt")).unwrap(),
+            "a"
+        );
+        assert!(destination.join("b.txt").exists());
+
+        // * The clone is a repository of its own
+        let reopened = RepositoryController::open(destination.to_str().unwrap()).unwrap();
+        assert_eq!(reopened.list_branches(), clone.list_branches());
+
+        // * A destination with files in it is refused and left alone
+        assert!(matches!(
+            clone_repository(&url, destination.to_str().unwrap()),
+            Err(DvcsError::InvalidArgument(_))
+        ));
+        assert!(destination.join("b.txt").exists());
+    }
+
+    #[test]
+    fn test_clone_repository_errors() {
+        let destination = test_folder("clone_missing_destination");
+        assert!(matches!(
+            clone_repository("/missing/dvcs/repository", destination.to_str().unwrap()),
+            Err(DvcsError::NotARepository(_))
+        ));
+        assert!(!destination.exists());
+
+        // * An empty source gives an empty clone on the same branch
+        let source = test_repository("clone_empty_source");
+        let clone = clone_repository(&source.dvcs_hidden, destination.to_str().unwrap()).unwrap();
+        assert_eq!(clone.current_branch(), Some(DEFAULT_BRANCH));
+        assert!(clone.list_branches().is_empty());
+    }
+
+    #[test]
+    // * A source with a tree naming a path outside of its folder can't be cloned, and nothing is written outside of the destination
+    fn test_clone_refuses_crafted_trees() {
+        let root = test_folder("clone_crafted");
+        fs::create_dir_all(root.join("evil/.dvcs_hidden")).unwrap();
+        let mut source = RepositoryController::new(root.join("evil").to_str().unwrap()).unwrap();
+        let storage = source.storage().unwrap();
+        let blob = storage.put(b"escaped").unwrap();
+        let tree = storage
+            .put(format!("100644 {}\t../escape.txt\n", blob).as_bytes())
+            .unwrap();
+        let crafted = source
+            .write_commit(vec![], tree, "Crafted".to_string())
+            .unwrap();
+        source
+            .branch_heads
+            .insert(DEFAULT_BRANCH.to_string(), crafted);
+        source.save_locally().unwrap();
+
+        let destination = root.join("victim");
+        let url = format!("file://{}", source.dvcs_hidden);
+        for source in [source.dvcs_hidden.as_str(), url.as_str()] {
+            assert!(matches!(
+                clone_repository(source, destination.to_str().unwrap()),
+                Err(DvcsError::ObjectCorrupt { .. })
+            ));
+            assert!(!destination.exists());
+            assert!(!root.join("escape.txt").exists());
+        }
+    }
+
+    #[test]
+    // * Fetching only updates the remote-tracking branches, pulling moves the current branch forward or merges
+    fn test_fetch_and_pull() {
+        let mut source = test_repository("pull_source");
+        commit_file(&mut source, "a.txt", "a\n");
+        source.create_branch("topic", None).unwrap();
+        let destination = test_folder("pull_destination");
+        let mut clone =
+            clone_repository(&source.dvcs_hidden, destination.to_str().unwrap()).unwrap();
+        let mut clone_staging = staging(&clone);
+
+        assert!(clone.fetch(DEFAULT_REMOTE).unwrap().is_empty());
+        let old = source.head_commit();
+        let second = commit_file(&mut source, "a.txt", "a2\n");
+        source.delete_branch("topic", true).unwrap();
+        assert_eq!(
+            clone.fetch(DEFAULT_REMOTE).unwrap(),
+            vec![
+                RefUpdate {
+                    name: "origin/master".to_string(),
+                    old: old.clone(),
+                    new: Some(second.clone()),
+                },
+                RefUpdate {
+                    name: "origin/topic".to_string(),
+                    old,
+                    new: None,
+                },
+            ]
+        );
+        // * Fetching leaves the working directory and the local branch alone
+        assert_eq!(
+            fs::read_to_string(destination.join("a.txt")).unwrap(),
+            "a\n"
+        );
+
+        let (updates, outcome) = clone
+            .pull(&mut clone_staging, DEFAULT_REMOTE, None)
+            .unwrap();
+        assert!(updates.is_empty());
+        assert_eq!(outcome, MergeOutcome::FastForward(second.clone()));
+        assert_eq!(
+            fs::read_to_string(destination.join("a.txt")).unwrap(),
+            "a2\n"
+        );
+
+        // * Both sides committed, pulling merges
+        let third = commit_file(&mut source, "a.txt", "a3\n");
+        let local = commit_file(&mut clone, "b.txt", "b\n");
+        let merge = match clone
+            .pull(&mut clone_staging, DEFAULT_REMOTE, None)
+            .unwrap()
+        {
+            (_, MergeOutcome::Merged(merge)) => merge,
+            outcome => panic!("unexpected {:?}", outcome),
+        };
+        let merge = clone.get_commit(&merge).unwrap();
+        assert_eq!(merge.parents, vec![local, third]);
+        assert!(merge.message.starts_with("Merge branch 'master' of "));
+        assert_eq!(
+            fs::read_to_string(destination.join("a.txt")).unwrap(),
+            "a3\n"
+        );
+
+        assert!(matches!(
+            clone.pull(&mut clone_staging, DEFAULT_REMOTE, Some("missing")),
+            Err(DvcsError::BranchNotFound(_))
+        ));
+        clone.remove_remote(DEFAULT_REMOTE).unwrap();
+        assert!(clone.list_remote_branches().is_empty());
+        assert!(clone.fetch(DEFAULT_REMOTE).is_err());
+    }
+
+    #[test]
+    // * A pull that stops on conflicts can't be committed until the conflicted files are fixed and staged again
+    fn test_pull_conflicts() {
+        let mut source = test_repository("pull_conflicts_source");
+        commit_file(&mut source, "a.txt", "a\n");
+        let destination = test_folder("pull_conflicts_destination");
+        let mut clone =
+            clone_repository(&source.dvcs_hidden, destination.to_str().unwrap()).unwrap();
+        let theirs = commit_file(&mut source, "a.txt", "theirs\n");
+        let ours = commit_file(&mut clone, "a.txt", "ours\n");
+
+        let mut clone_staging = staging(&clone);
+        let (_, outcome) = clone
+            .pull(&mut clone_staging, DEFAULT_REMOTE, None)
+            .unwrap();
+        assert_eq!(outcome, MergeOutcome::Conflicts(vec!["a.txt".to_string()]));
+        assert!(matches!(
+            clone.commit_staged(&mut clone_staging, "Merge"),
+            Err(DvcsError::Conflict { paths, .. }) if paths == vec!["a.txt".to_string()]
+        ));
+        assert_eq!(clone.head_commit(), Some(ours.clone()));
+
+        // * The unmerged paths are kept in the index file
+        let mut clone_staging = staging(&clone);
+        assert_eq!(clone_staging.unmerged_paths(), vec!["a.txt"]);
+        fs::write(destination.join("a.txt"), "both\n").unwrap();
+        clone_staging
+            .stage_path(&clone_staging.working_path("a.txt"))
+            .unwrap();
+        let merge = clone.commit_staged(&mut clone_staging, "Merge").unwrap();
+        assert_eq!(
+            clone.get_commit(&merge).unwrap().parents,
+            vec![ours, theirs]
+        );
+    }
+
+    #[test]
+    // * Pushing moves the branch of the remote forward, anything else needs force or a lease that still holds
+    fn test_push() {
+        let mut source = test_repository("push_source");
+        let first = commit_file(&mut source, "a.txt", "a\n");
+        let destination = test_folder("push_destination");
+        let mut clone =
+            clone_repository(&source.dvcs_hidden, destination.to_str().unwrap()).unwrap();
+        let mut clone_staging = staging(&clone);
+        clone.create_branch("topic", None).unwrap();
+        clone
+            .switch_branch(&mut clone_staging, "topic", false)
+            .unwrap();
+
+        let update = clone
+            .push(DEFAULT_REMOTE, "topic", PushMode::FastForward)
+            .unwrap();
+        assert_eq!((update.old, update.new), (None, Some(first.clone())));
+        let second = commit_file(&mut clone, "b.txt", "b\n");
+        clone
+            .push(DEFAULT_REMOTE, "topic", PushMode::FastForward)
+            .unwrap();
+        let source = RepositoryController::open(&source.dvcs_hidden).unwrap();
+        assert_eq!(source.resolve_revision("topic").unwrap(), second);
+        assert!(source.get_commit(&second).is_ok());
+        assert_eq!(clone.resolve_revision("origin/topic").unwrap(), second);
+
+        // * A teammate pushed in the meantime
+        let mut teammate = RepositoryController::open(&source.dvcs_hidden).unwrap();
+        let mut teammate_staging = staging(&teammate);
+        teammate
+            .switch_branch(&mut teammate_staging, "topic", false)
+            .unwrap();
+        let theirs = commit_files(
+            &mut teammate,
+            &mut teammate_staging,
+            &[("c.txt", "c")],
+            "Teammate",
+        );
+        teammate
+            .switch_branch(&mut teammate_staging, "master", false)
+            .unwrap();
+        let ours = commit_file(&mut clone, "b.txt", "b2\n");
+        assert!(matches!(
+            clone.push(DEFAULT_REMOTE, "topic", PushMode::FastForward),
+            Err(DvcsError::PushRejected { .. })
+        ));
+        // * The lease is on what was last seen of the remote, which is out of date
+        assert!(matches!(
+            clone.push(DEFAULT_REMOTE, "topic", PushMode::ForceWithLease(None)),
+            Err(DvcsError::PushRejected { .. })
+        ));
+        clone.fetch(DEFAULT_REMOTE).unwrap();
+        assert!(matches!(
+            clone.push(
+                DEFAULT_REMOTE,
+                "topic",
+                PushMode::ForceWithLease(Some(second.clone()))
+            ),
+            Err(DvcsError::PushRejected { .. })
+        ));
+        let update = clone
+            .push(DEFAULT_REMOTE, "topic", PushMode::ForceWithLease(None))
+            .unwrap();
+        assert_eq!((update.old, update.new), (Some(theirs), Some(ours.clone())));
+        clone
+            .push(DEFAULT_REMOTE, "topic", PushMode::Force)
+            .unwrap();
+
+        // * master is checked out in the source, moving it would leave its working directory behind
+        clone.branch_heads.insert("master".to_string(), ours);
+        assert!(matches!(
+            clone.push(DEFAULT_REMOTE, "master", PushMode::Force),
+            Err(DvcsError::PushRejected { .. })
+        ));
+        assert_eq!(
+            RepositoryController::open(&source.dvcs_hidden)
+                .unwrap()
+                .resolve_revision("master")
+                .unwrap(),
+            first
+        );
+        assert!(matches!(
+            clone.push(DEFAULT_REMOTE, "missing", PushMode::Force),
+            Err(DvcsError::BranchNotFound(_))
+        ));
+    }
+
+    #[test]
+    // * A bare hub takes pushes to any branch, can be cloned and refuses what needs a working directory
+    fn test_bare_repository() {
+        let hub = test_folder("bare_hub");
+        fs::create_dir_all(&hub).unwrap();
+        let hub = hub.to_str().unwrap();
+        let bare = RepositoryController::init_bare(hub).unwrap();
+        assert!(bare.is_bare());
+        assert!(!Path::new(hub).join(".dvcs_hidden").exists());
+        assert!(RepositoryController::init_bare(hub).unwrap().is_bare());
+
+        let mut source = test_repository("bare_source");
+        let first = commit_file(&mut source, "a.txt", "a\n");
+        source.add_remote(DEFAULT_REMOTE, hub).unwrap();
+        source
+            .push(DEFAULT_REMOTE, DEFAULT_BRANCH, PushMode::FastForward)
+            .unwrap();
+        assert!(matches!(
+            RepositoryController::init_bare(&source.dvcs_hidden),
+            Err(DvcsError::InvalidArgument(_))
+        ));
+
+        // * History and refs work without a working directory
+        let mut bare = RepositoryController::open(hub).unwrap();
+        assert_eq!(bare.head_commit(), Some(first.clone()));
+        bare.create_branch("topic", Some("master")).unwrap();
+        bare.create_tag("v1", None).unwrap();
+        assert_eq!(bare.blame("a.txt", None).unwrap()[0].commit_id, first);
+        let mut staging = staging(&source);
+        assert!(matches!(
+            bare.checkout(&mut staging, &first, false),
+            Err(DvcsError::BareRepository(_))
+        ));
+        assert!(matches!(
+            bare.commit_staged(&mut staging, "Message"),
+            Err(DvcsError::BareRepository(_))
+        ));
+
+        let destination = test_folder("bare_clone");
+        let clone = clone_repository(hub, destination.to_str().unwrap()).unwrap();
+        assert!(!clone.is_bare());
+        assert_eq!(clone.head_commit(), Some(first));
+        assert_eq!(
+            fs::read_to_string(destination.join("a.txt")).unwrap(),
+            "a\n"
+        );
+    }
+}
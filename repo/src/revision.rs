@@ -0,0 +1,341 @@
+use crate::RepositoryController;
+use dvcs_error::DvcsError;
+use std::collections::BTreeSet;
+use storage_hiding::commit_objects::Commit;
+
+/// Length of a full commit id
+const FULL_ID_LENGTH: usize = 40;
+
+/// Shortest abbreviated commit id that is looked up
+pub const MIN_ABBREVIATED_LENGTH: usize = 4;
+
+/// Step from a commit to one of its ancestors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ancestry {
+    /// `~N`, N generations up following the first parents
+    Generations(usize),
+    /// `^N`, the Nth parent, `^0` is the commit itself
+    Parent(usize),
+}
+
+/// Revision naming one commit, a name (HEAD, branch, tag or full or abbreviated commit id) followed by ancestry steps like `master~2^2`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Revision {
+    pub name: String,
+    pub steps: Vec<Ancestry>,
+}
+
+impl Revision {
+    /// Parses a revision, `~` and `^` without a number mean 1
+    pub fn parse(expression: &str) -> Result<Revision, DvcsError> {
+        let invalid = || DvcsError::InvalidArgument(format!("'{}' is not a revision", expression));
+        let end = expression.find(['~', '^']).unwrap_or(expression.len());
+        let (name, mut rest) = expression.split_at(end);
+        if name.is_empty() || name.chars().any(char::is_whitespace) {
+            return Err(invalid());
+        }
+        let mut steps = vec![];
+        while let Some(operator) = rest.chars().next() {
+            let digits = rest[1..]
+                .find(|c: char| !c.is_ascii_digit())
+                .map_or(&rest[1..], |length| &rest[1..1 + length]);
+            let count = match digits {
+                "" => 1,
+                digits => digits.parse().map_err(|_| invalid())?,
+            };
+            steps.push(match operator {
+                '~' => Ancestry::Generations(count),
+                '^' => Ancestry::Parent(count),
+                _ => return Err(invalid()),
+            });
+            rest = &rest[1 + digits.len()..];
+        }
+        Ok(Revision {
+            name: name.to_string(),
+            steps,
+        })
+    }
+}
+
+/// Revision expression naming a commit or a range of commits, a side left out of a range is HEAD
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RevisionRange {
+    /// `A`, the commit and its ancestors
+    Single(Revision),
+    /// `A..B`, the commits reachable from B but not from A
+    Range(Revision, Revision),
+    /// `A...B`, the commits reachable from either A or B but not from both
+    Symmetric(Revision, Revision),
+}
+
+impl RevisionRange {
+    /// Parses a revision or a range of revisions
+    pub fn parse(expression: &str) -> Result<RevisionRange, DvcsError> {
+        let side = |side: &str| Revision::parse(if side.is_empty() { "HEAD" } else { side });
+        if let Some((left, right)) = expression.split_once("...") {
+            return Ok(RevisionRange::Symmetric(side(left)?, side(right)?));
+        }
+        if let Some((left, right)) = expression.split_once("..") {
+            return Ok(RevisionRange::Range(side(left)?, side(right)?));
+        }
+        Revision::parse(expression).map(RevisionRange::Single)
+    }
+}
+
+/// Commits selected by revision expressions, the ones reachable from `include` but not from `exclude`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommitSet {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl RepositoryController {
+    /// Gets the id of the commit a revision names, see `Revision`
+    ///
+    /// A name is looked up as HEAD, then as a branch, then as a tag, then as a remote-tracking branch (`origin/master`) and last as a full or abbreviated (at least 4 characters) commit id
+    pub fn resolve_revision(&self, expression: &str) -> Result<String, DvcsError> {
+        self.resolve_parsed(&Revision::parse(expression)?, expression)
+    }
+
+    /// Gets the commits a revision or range of revisions names, see `RevisionRange`
+    pub fn resolve_commit_set(&self, expression: &str) -> Result<CommitSet, DvcsError> {
+        Ok(match RevisionRange::parse(expression)? {
+            RevisionRange::Single(revision) => CommitSet {
+                include: vec![self.resolve_parsed(&revision, expression)?],
+                exclude: vec![],
+            },
+            RevisionRange::Range(from, to) => CommitSet {
+                include: vec![self.resolve_parsed(&to, expression)?],
+                exclude: vec![self.resolve_parsed(&from, expression)?],
+            },
+            RevisionRange::Symmetric(left, right) => {
+                let left = self.resolve_parsed(&left, expression)?;
+                let right = self.resolve_parsed(&right, expression)?;
+                CommitSet {
+                    exclude: self.merge_bases(&left, &right)?,
+                    include: vec![left, right],
+                }
+            }
+        })
+    }
+
+    /// Gets the two commits the diff of a revision expression compares, the second is None for the working directory
+    ///
+    /// `A` compares A with the working directory, `A..B` compares A with B and `A...B` compares the merge base of A and B with B
+    pub fn resolve_diff(&self, expression: &str) -> Result<(String, Option<String>), DvcsError> {
+        Ok(match RevisionRange::parse(expression)? {
+            RevisionRange::Single(revision) => (self.resolve_parsed(&revision, expression)?, None),
+            RevisionRange::Range(old, new) => (
+                self.resolve_parsed(&old, expression)?,
+                Some(self.resolve_parsed(&new, expression)?),
+            ),
+            RevisionRange::Symmetric(left, right) => {
+                let left = self.resolve_parsed(&left, expression)?;
+                let right = self.resolve_parsed(&right, expression)?;
+                match self.merge_bases(&left, &right)?.into_iter().next() {
+                    Some(base) => (base, Some(right)),
+                    None => {
+                        return Err(DvcsError::InvalidArgument(format!(
+                            "{} has no merge base",
+                            expression
+                        )))
+                    }
+                }
+            }
+        })
+    }
+
+    /// Gets the best common ancestors of two commits, the common ancestors that aren't an ancestor of another one
+    pub fn merge_bases(&self, first: &str, second: &str) -> Result<Vec<String>, DvcsError> {
+        let first = self.reachable(&[first.to_string()])?;
+        let common: BTreeSet<String> = self
+            .reachable(&[second.to_string()])?
+            .into_iter()
+            .filter(|commit_id| first.contains(commit_id))
+            .collect();
+        let storage = self.storage()?;
+        let mut parents = vec![];
+        for commit_id in &common {
+            parents.extend(Commit::read(&storage, commit_id)?.parents);
+        }
+        let below = self.reachable(&parents)?;
+        Ok(common
+            .into_iter()
+            .filter(|commit_id| !below.contains(commit_id))
+            .collect())
+    }
+
+    /// Gets every commit reachable from the given commits, them included
+    pub(crate) fn reachable(&self, starts: &[String]) -> Result<BTreeSet<String>, DvcsError> {
+        let storage = self.storage()?;
+        let mut reachable = BTreeSet::new();
+        let mut pending = starts.to_vec();
+        while let Some(commit_id) = pending.pop() {
+            if reachable.insert(commit_id.clone()) {
+                pending.extend(Commit::read(&storage, &commit_id)?.parents);
+            }
+        }
+        Ok(reachable)
+    }
+
+    /// Private helper function resolving a parsed revision, errors name the whole expression
+    fn resolve_parsed(&self, revision: &Revision, expression: &str) -> Result<String, DvcsError> {
+        let mut commit_id = self.resolve_name(&revision.name)?;
+        let not_found = || DvcsError::RevisionNotFound(expression.to_string());
+        for step in &revision.steps {
+            let (generations, parent) = match *step {
+                Ancestry::Generations(generations) => (generations, 1),
+                Ancestry::Parent(0) => continue,
+                Ancestry::Parent(parent) => (1, parent),
+            };
+            for _ in 0..generations {
+                let parents = Commit::read(&self.storage()?, &commit_id)?.parents;
+                commit_id = parents.get(parent - 1).cloned().ok_or_else(not_found)?;
+            }
+        }
+        Ok(commit_id)
+    }
+
+    /// Private helper function looking up the name of a revision without its ancestry steps
+    fn resolve_name(&self, name: &str) -> Result<String, DvcsError> {
+        let not_found = || DvcsError::RevisionNotFound(name.to_string());
+        if name == "HEAD" {
+            return self.head_commit().ok_or_else(not_found);
+        }
+        if let Some(tip) = self.branch_heads.get(name) {
+            return Ok(tip.clone());
+        }
+        if let Some(commit_id) = self.tags.get(name) {
+            return Ok(commit_id.clone());
+        }
+        if let Some(tip) = self.remote_branches.get(name) {
+            return Ok(tip.clone());
+        }
+        if name.len() < MIN_ABBREVIATED_LENGTH
+            || name.len() > FULL_ID_LENGTH
+            || !name.chars().all(|c| c.is_ascii_hexdigit())
+        {
+            return Err(not_found());
+        }
+        let prefix = name.to_ascii_lowercase();
+        let candidates: BTreeSet<&String> = self
+            .commit_history
+            .iter()
+            .filter(|commit_id| commit_id.starts_with(&prefix))
+            .collect();
+        match candidates.len() {
+            0 => Err(not_found()),
+            1 => Ok(candidates.into_iter().next().cloned().unwrap_or_default()),
+            _ => Err(DvcsError::AmbiguousRevision {
+                revision: name.to_string(),
+                candidates: candidates.into_iter().cloned().collect(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::MergeOutcome;
+    use crate::test_support::{commit_files, staging, test_repository};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Revision::parse("master~2^2^").unwrap(),
+            Revision {
+                name: "master".to_string(),
+                steps: vec![
+                    Ancestry::Generations(2),
+                    Ancestry::Parent(2),
+                    Ancestry::Parent(1)
+                ],
+            }
+        );
+        assert!(matches!(
+            RevisionRange::parse("..topic").unwrap(),
+            RevisionRange::Range(from, to) if from.name == "HEAD" && to.name == "topic"
+        ));
+        assert!(matches!(
+            RevisionRange::parse("a...b~1").unwrap(),
+            RevisionRange::Symmetric(left, right)
+                if left.name == "a" && right.steps == [Ancestry::Generations(1)]
+        ));
+        for invalid in ["", "~1", "master~x", "two words"] {
+            assert!(matches!(
+                Revision::parse(invalid),
+                Err(DvcsError::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    // * base - master - merge, with topic branching off base and merged as the second parent
+    fn test_resolve() {
+        let mut repo = test_repository("resolve_revision");
+        let mut staging = staging(&repo);
+        let base = commit_files(&mut repo, &mut staging, &[("a.txt", "a")], "Base");
+        repo.create_branch("topic", None).unwrap();
+        repo.switch_branch(&mut staging, "topic", false).unwrap();
+        let topic = commit_files(&mut repo, &mut staging, &[("b.txt", "b")], "Topic");
+        repo.switch_branch(&mut staging, "master", false).unwrap();
+        let master = commit_files(&mut repo, &mut staging, &[("c.txt", "c")], "Master");
+        let merge = match repo.merge(&mut staging, "topic", "Merge").unwrap() {
+            MergeOutcome::Merged(merge) => merge,
+            outcome => panic!("unexpected merge outcome {:?}", outcome),
+        };
+        repo.create_tag("v1", Some(&base)).unwrap();
+
+        assert_eq!(repo.resolve_revision("HEAD").unwrap(), merge);
+        assert_eq!(repo.resolve_revision("master~1").unwrap(), master);
+        assert_eq!(repo.resolve_revision("HEAD~2").unwrap(), base);
+        assert_eq!(repo.resolve_revision("master^2").unwrap(), topic);
+        assert_eq!(repo.resolve_revision("master^2~").unwrap(), base);
+        assert_eq!(repo.resolve_revision("topic^0").unwrap(), topic);
+        assert_eq!(repo.resolve_revision("v1").unwrap(), base);
+        assert_eq!(repo.resolve_revision(&merge[..8]).unwrap(), merge);
+        assert_eq!(repo.resolve_revision(&merge.to_uppercase()).unwrap(), merge);
+        for missing in ["v1~1", "master^3", "unknown", "abc", &"0".repeat(40)] {
+            assert!(matches!(
+                repo.resolve_revision(missing),
+                Err(DvcsError::RevisionNotFound(_))
+            ));
+        }
+
+        assert_eq!(
+            repo.merge_bases(&master, &topic).unwrap(),
+            vec![base.clone()]
+        );
+        assert_eq!(
+            repo.resolve_commit_set("topic...master~").unwrap(),
+            CommitSet {
+                include: vec![topic.clone(), master.clone()],
+                exclude: vec![base.clone()],
+            }
+        );
+        assert_eq!(
+            repo.resolve_commit_set("v1..").unwrap(),
+            CommitSet {
+                include: vec![merge.clone()],
+                exclude: vec![base.clone()],
+            }
+        );
+        assert_eq!(
+            repo.resolve_diff("topic...master~").unwrap(),
+            (base.clone(), Some(master.clone()))
+        );
+        assert_eq!(repo.resolve_diff("v1").unwrap(), (base, None));
+    }
+
+    #[test]
+    fn test_ambiguous_id() {
+        let mut repo = test_repository("ambiguous_id");
+        repo.commit_history = vec!["abcd1".repeat(8), "abcd2".repeat(8)];
+        assert!(matches!(
+            repo.resolve_revision("abcd"),
+            Err(DvcsError::AmbiguousRevision { candidates, .. }) if candidates.len() == 2
+        ));
+        assert_eq!(repo.resolve_revision("abcd2").unwrap(), "abcd2".repeat(8));
+    }
+}
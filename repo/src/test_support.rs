@@ -0,0 +1,47 @@
+use crate::RepositoryController;
+use staging::staging_storage::Staging;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Gets a folder of its own in the temp folder for a test, whatever an earlier run left in it is removed
+pub(crate) fn test_folder(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("dvcs_repo_{}", name));
+    let _ = fs::remove_dir_all(&root);
+    root
+}
+
+/// Creates an empty repository with a working directory in a temp folder of its own, so tests don't write over each other
+pub(crate) fn test_repository(name: &str) -> RepositoryController {
+    let root = test_folder(name);
+    fs::create_dir_all(root.join(".dvcs_hidden")).unwrap();
+    RepositoryController::new(root.to_str().unwrap()).unwrap()
+}
+
+/// Opens the staging index of the working directory of a repository
+pub(crate) fn staging(repo: &RepositoryController) -> Staging {
+    Staging::new(&repo.hidden_folder(), &repo.dvcs_hidden).unwrap()
+}
+
+/// Writes files into the working directory, stages them and commits them on top of HEAD (see `commit_staged`), returns the id of the commit
+pub(crate) fn commit_files(
+    repo: &mut RepositoryController,
+    staging: &mut Staging,
+    files: &[(&str, &str)],
+    message: &str,
+) -> String {
+    for (path, contents) in files {
+        let file_path = staging.working_path(path);
+        if let Some(parent) = Path::new(&file_path).parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(&file_path, contents).unwrap();
+        staging.stage_path(&file_path).unwrap();
+    }
+    repo.commit_staged(staging, message).unwrap()
+}
+
+/// Commits one file with its path as the message (see `commit_files`)
+pub(crate) fn commit_file(repo: &mut RepositoryController, path: &str, contents: &str) -> String {
+    let mut staging = staging(repo);
+    commit_files(repo, &mut staging, &[(path, contents)], path)
+}
@@ -1,67 +1,151 @@
+pub mod line_diff;
+
+use line_diff::{diff, split_lines, Edit};
+
+/// One version of a file, `address` names the version in conflict markers and `contents` holds its lines with their line endings (see `split_lines`)
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct State {
-    address: String,
-    contents: Vec<String>,
+    pub address: String,
+    pub contents: Vec<String>,
 }
 
-pub fn merge_states(ancestor: State, ours: State, theirs: State) -> (Vec<String>, Vec<String>) {
-    //find similar elements between ancestor and ours, theirs
-
-    let tup = find_same_diff(ancestor.contents.clone(), ours.contents.clone());
-    let edited_elements_ours = tup.0;
-    let deleted_ours = tup.1;
+impl State {
+    /// Creates a state from the text of a file, `\r\n` line endings and a missing newline at the end are kept
+    pub fn from_text(address: &str, text: &str) -> State {
+        State {
+            address: address.to_string(),
+            contents: split_lines(text)
+                .into_iter()
+                .map(|line| line.to_string())
+                .collect(),
+        }
+    }
+}
 
-    let tup = find_same_diff(ancestor.contents.clone(), theirs.contents.clone());
-    let edited_elements_theirs = tup.0;
-    let deleted_theirs = tup.1;
+/// Region where both sides changed the same lines of the ancestor differently, starts are 0 based line positions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// Position of the `<<<<<<<` line in the merged contents
+    pub merged_start: usize,
+    pub ancestor_start: usize,
+    pub ancestor: Vec<String>,
+    pub ours_start: usize,
+    pub ours: Vec<String>,
+    pub theirs_start: usize,
+    pub theirs: Vec<String>,
+}
 
-    //find elements in diff_ours_ancestor that are not in ancestor but not in ours
-    //elements
-    let added_ours = find_added(ancestor.contents.clone(), ours.contents.clone());
-    let added_theirs = find_added(ancestor.contents.clone(), theirs.contents.clone());
+/// Merged lines, with conflict hunks written in them when `conflicts` is not empty
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeResult {
+    pub contents: Vec<String>,
+    pub conflicts: Vec<Conflict>,
+}
 
-    if deleted_ours != deleted_theirs {
-        //conflict
+impl MergeResult {
+    /// True when both sides could be merged without conflicts
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
     }
 
-    let mut to_be_added = Vec::new();
-    for i in added_theirs {
-        if !added_ours.contains(&i) {
-            to_be_added.push(i);
-        }
+    /// Joins the merged lines into the text of a file, every line keeps the line ending it had
+    pub fn to_text(&self) -> String {
+        self.contents.concat()
     }
+}
 
-    let mut to_be_merged = Vec::new();
-    for i in edited_elements_ours {
-        if edited_elements_theirs.contains(&i) {
-            to_be_merged.push(i);
-        }
+/// Private helper function ending the last line of a file without a newline, so a conflict marker after it starts on a line of its own
+fn ended(line: &str) -> String {
+    match line.ends_with('\n') {
+        true => line.to_string(),
+        false => line.to_string() + "\n",
     }
-
-    return (to_be_added, to_be_merged);
 }
 
-//function that returns the elements that are the same between two vectors
-fn find_same_diff(a: Vec<String>, b: Vec<String>) -> (Vec<String>, Vec<String>) {
-    let mut same = Vec::new();
-    let mut diff = Vec::new();
-    for i in a {
-        if b.contains(&i) {
-            same.push(i);
-        } else {
-            diff.push(i);
+/// Private helper function mapping every ancestor line to the line it was kept as in the other version, None if it was changed or deleted
+fn matching_lines(ancestor: &[String], other: &[String]) -> Vec<Option<usize>> {
+    let mut matches = vec![None; ancestor.len()];
+    for edit in diff(ancestor, other) {
+        if let Edit::Equal { old, new } = edit {
+            matches[old] = Some(new);
         }
     }
-    return (same, diff);
+    matches
 }
 
-fn find_added(a: Vec<String>, b: Vec<String>) -> Vec<String> {
-    let mut added = Vec::new();
-    for i in b {
-        if !a.contains(&i) {
-            added.push(i);
+/// Merges the changes made by ours and theirs since their common ancestor (diff3), line order and duplicate lines are kept
+///
+/// Lines kept by both sides split the files into chunks, a chunk changed by only one side (or the same way by both) takes that change while a chunk changed differently by both is a conflict written as
+///
+/// ```text
+/// <<<<<<< ours.address
+/// lines of ours
+/// =======
+/// lines of theirs
+/// >>>>>>> theirs.address
+/// ```
+pub fn merge_states(ancestor: &State, ours: &State, theirs: &State) -> MergeResult {
+    let (base, left, right) = (&ancestor.contents, &ours.contents, &theirs.contents);
+    let ours_matches = matching_lines(base, left);
+    let theirs_matches = matching_lines(base, right);
+    let mut result = MergeResult {
+        contents: vec![],
+        conflicts: vec![],
+    };
+
+    let (mut a, mut o, mut t) = (0, 0, 0);
+    loop {
+        // * Copy the lines that are unchanged on both sides
+        while a < base.len() && ours_matches[a] == Some(o) && theirs_matches[a] == Some(t) {
+            result.contents.push(base[a].clone());
+            a += 1;
+            o += 1;
+            t += 1;
+        }
+        if a == base.len() && o == left.len() && t == right.len() {
+            return result;
         }
+
+        // * The changed chunk ends at the next ancestor line both sides kept, or at the end of the files
+        let (next_a, next_o, next_t) = (a..base.len())
+            .find_map(|line| match (ours_matches[line], theirs_matches[line]) {
+                (Some(next_o), Some(next_t)) => Some((line, next_o, next_t)),
+                _ => None,
+            })
+            .unwrap_or((base.len(), left.len(), right.len()));
+        let (base_chunk, ours_chunk, theirs_chunk) =
+            (&base[a..next_a], &left[o..next_o], &right[t..next_t]);
+
+        if ours_chunk == base_chunk {
+            result.contents.extend_from_slice(theirs_chunk);
+        } else if theirs_chunk == base_chunk || ours_chunk == theirs_chunk {
+            result.contents.extend_from_slice(ours_chunk);
+        } else {
+            result.conflicts.push(Conflict {
+                merged_start: result.contents.len(),
+                ancestor_start: a,
+                ancestor: base_chunk.to_vec(),
+                ours_start: o,
+                ours: ours_chunk.to_vec(),
+                theirs_start: t,
+                theirs: theirs_chunk.to_vec(),
+            });
+            result.contents.push(format!("<<<<<<< {}\n", ours.address));
+            result
+                .contents
+                .extend(ours_chunk.iter().map(|line| ended(line)));
+            result.contents.push("=======\n".to_string());
+            result
+                .contents
+                .extend(theirs_chunk.iter().map(|line| ended(line)));
+            result
+                .contents
+                .push(format!(">>>>>>> {}\n", theirs.address));
+        }
+        a = next_a;
+        o = next_o;
+        t = next_t;
     }
-    return added;
 }
 
 #[cfg(test)]
@@ -70,42 +154,109 @@ mod tests {
 
     use crate::merge_states;
 
+    fn state(address: &str, contents: &[&str]) -> State {
+        State {
+            address: address.to_string(),
+            contents: contents.iter().map(|line| format!("{}\n", line)).collect(),
+        }
+    }
+
     #[test]
     fn it_works() {
-        let ancestor: State = State {
-            address: "a".to_string(),
-            contents: vec![
-                "a".to_string(),
-                "b".to_string(),
-                "c".to_string(),
-                "d".to_string(),
-            ],
-        };
-        let ours: State = State {
-            address: "b".to_string(),
-            contents: vec![
-                "a".to_string(),
-                "b".to_string(),
-                "c".to_string(),
-                "e".to_string(),
-            ],
-        };
-        let theirs: State = State {
-            address: "c".to_string(),
-            contents: vec![
-                "a".to_string(),
-                "b".to_string(),
-                "c".to_string(),
-                "f".to_string(),
-            ],
-        };
+        let ancestor = state("a", &["a", "b", "c", "d"]);
+        let ours = state("b", &["a", "b", "c", "e"]);
+        let theirs = state("c", &["a", "b", "c", "f"]);
 
+        // both sides changed the last line, so it is a conflict
+        let merged = merge_states(&ancestor, &ours, &theirs);
+        assert!(!merged.is_clean());
         assert_eq!(
-            merge_states(ancestor, ours, theirs),
-            (
-                vec!["f".to_string()],
-                vec!["a".to_string(), "b".to_string(), "c".to_string()]
+            merged.to_text(),
+            "a\nb\nc\n<<<<<<< b\ne\n=======\nf\n>>>>>>> c\n"
+        );
+        assert_eq!(
+            merged.conflicts,
+            vec![Conflict {
+                merged_start: 3,
+                ancestor_start: 3,
+                ancestor: vec!["d\n".to_string()],
+                ours_start: 3,
+                ours: vec!["e\n".to_string()],
+                theirs_start: 3,
+                theirs: vec!["f\n".to_string()],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_merge_clean() {
+        // changes to different lines are both kept, in order
+        let ancestor = state("base", &["1", "2", "3", "4", "5"]);
+        let ours = state("ours", &["0", "1", "2", "three", "4", "5"]);
+        let theirs = state("theirs", &["1", "2", "3", "4"]);
+        let merged = merge_states(&ancestor, &ours, &theirs);
+        assert!(merged.is_clean());
+        assert_eq!(merged.to_text(), "0\n1\n2\nthree\n4\n");
+
+        // the same change on both sides is taken once
+        let ours = state("ours", &["1", "2", "x", "4", "5"]);
+        let merged = merge_states(&ancestor, &ours, &ours.clone());
+        assert!(merged.is_clean());
+        assert_eq!(merged.contents, ours.contents);
+    }
+
+    #[test]
+    fn test_merge_duplicate_lines() {
+        // duplicate lines used to be merged away as if they were one
+        let ancestor = state("base", &["}", "x", "}", "y", "}"]);
+        let ours = state("ours", &["}", "x", "}", "}", "y", "}"]);
+        let theirs = state("theirs", &["}", "x", "}", "y", "}", "z"]);
+        let merged = merge_states(&ancestor, &ours, &theirs);
+        assert!(merged.is_clean());
+        assert_eq!(merged.to_text(), "}\nx\n}\n}\ny\n}\nz\n");
+    }
+
+    #[test]
+    fn test_merge_delete_and_modify_conflict() {
+        let ancestor = State::from_text("base", "keep\nchange\nkeep too\n");
+        let ours = State::from_text("HEAD", "keep\nkeep too\n");
+        let theirs = State::from_text("feature", "keep\nchanged\nkeep too\n");
+        let merged = merge_states(&ancestor, &ours, &theirs);
+        assert_eq!(merged.conflicts.len(), 1);
+        assert!(merged.conflicts[0].ours.is_empty());
+        assert_eq!(
+            merged.to_text(),
+            "keep\n<<<<<<< HEAD\n=======\nchanged\n>>>>>>> feature\nkeep too\n"
+        );
+    }
+
+    #[test]
+    // * Line endings and a missing newline at the end of the file survive a merge, and a change to them alone is merged like any other change
+    fn test_merge_keeps_line_endings() {
+        let text = "a\r\nb\r\nc";
+        assert_eq!(
+            merge_states(
+                &State::from_text("base", text),
+                &State::from_text("ours", text),
+                &State::from_text("theirs", text),
             )
+            .to_text(),
+            text
+        );
+
+        let ancestor = State::from_text("base", "a\nb\nc\n");
+        let ours = State::from_text("ours", "a\r\nb\nc\n");
+        let theirs = State::from_text("theirs", "a\nb\nc");
+        let merged = merge_states(&ancestor, &ours, &theirs);
+        assert!(merged.is_clean());
+        assert_eq!(merged.to_text(), "a\r\nb\nc");
+
+        // * The last line of a conflict without a newline doesn't run into the marker after it
+        let ours = State::from_text("ours", "a\nb\nours");
+        let merged = merge_states(&ancestor, &ours, &theirs);
+        assert_eq!(
+            merged.to_text(),
+            "a\nb\n<<<<<<< ours\nours\n=======\nc\n>>>>>>> theirs\n"
         );
     }
 }
@@ -0,0 +1,337 @@
+/// Number of unchanged lines shown around every change when none is asked for
+pub const DEFAULT_CONTEXT_LINES: usize = 3;
+
+/// Single step turning the old sequence into the new one, holding the (0 based) positions of the lines involved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edit {
+    Equal { old: usize, new: usize },
+    Delete { old: usize },
+    Insert { new: usize },
+}
+
+/// Group of changes with the unchanged lines around them, line numbers are 1 based like in a unified diff
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+    pub edits: Vec<Edit>,
+}
+
+/// Splits text into lines that keep their line ending, so a missing newline at the end of a file counts as a change
+pub fn split_lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').collect()
+}
+
+/// Computes the shortest list of edits turning `old` into `new` with the Myers algorithm
+pub fn diff<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Edit> {
+    // * Lines shared at the start and end are always kept, only the middle needs to be searched
+    let prefix = old
+        .iter()
+        .zip(new.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut edits: Vec<Edit> = (0..prefix)
+        .map(|line| Edit::Equal {
+            old: line,
+            new: line,
+        })
+        .collect();
+    edits.extend(
+        myers(
+            &old[prefix..old.len() - suffix],
+            &new[prefix..new.len() - suffix],
+        )
+        .into_iter()
+        .map(|edit| match edit {
+            Edit::Equal { old, new } => Edit::Equal {
+                old: old + prefix,
+                new: new + prefix,
+            },
+            Edit::Delete { old } => Edit::Delete { old: old + prefix },
+            Edit::Insert { new } => Edit::Insert { new: new + prefix },
+        }),
+    );
+    edits.extend((0..suffix).map(|line| Edit::Equal {
+        old: old.len() - suffix + line,
+        new: new.len() - suffix + line,
+    }));
+    edits
+}
+
+/// Private helper function running the greedy Myers search, keeping every round so the path can be traced back
+fn myers<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Edit> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = n + m;
+    // * v[k] is the furthest x reached on diagonal k (x - y = k), shifted so negative diagonals fit
+    let offset = max + 1;
+    let mut v = vec![0isize; (2 * max + 3) as usize];
+    let mut trace = vec![];
+    for d in 0..=max {
+        trace.push(v.clone());
+        let mut k = -d;
+        while k <= d {
+            let index = (k + offset) as usize;
+            // * Either move down (insert) from diagonal k + 1 or right (delete) from diagonal k - 1
+            let mut x = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+                v[index + 1]
+            } else {
+                v[index - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index] = x;
+            if x >= n && y >= m {
+                return backtrack(&trace, n, m, offset);
+            }
+            k += 2;
+        }
+    }
+    vec![]
+}
+
+/// Private helper function walking the Myers rounds backwards from the end of both sequences
+fn backtrack(trace: &[Vec<isize>], n: isize, m: isize, offset: isize) -> Vec<Edit> {
+    let mut edits = vec![];
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let index = (k + offset) as usize;
+        let previous_k = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let previous_x = v[(previous_k + offset) as usize];
+        let previous_y = previous_x - previous_k;
+        while x > previous_x && y > previous_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit::Equal {
+                old: x as usize,
+                new: y as usize,
+            });
+        }
+        if d > 0 {
+            if x == previous_x {
+                edits.push(Edit::Insert {
+                    new: previous_y as usize,
+                });
+            } else {
+                edits.push(Edit::Delete {
+                    old: previous_x as usize,
+                });
+            }
+        }
+        x = previous_x;
+        y = previous_y;
+    }
+    edits.reverse();
+    edits
+}
+
+/// Groups edits into hunks keeping `context` unchanged lines around every change, changes closer than twice the context share a hunk
+pub fn hunks(edits: &[Edit], context: usize) -> Vec<Hunk> {
+    let changes: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Equal { .. }))
+        .map(|(position, _)| position)
+        .collect();
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for position in changes {
+        let start = position.saturating_sub(context);
+        let end = (position + context + 1).min(edits.len());
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    ranges
+        .into_iter()
+        .map(|(start, end)| {
+            // * Count how many lines of each side came before the hunk to get where it starts
+            let (old_before, new_before) = line_counts(&edits[..start]);
+            let (old_lines, new_lines) = line_counts(&edits[start..end]);
+            Hunk {
+                // * Empty sides start at the line before them, like in a unified diff
+                old_start: if old_lines == 0 {
+                    old_before
+                } else {
+                    old_before + 1
+                },
+                old_lines,
+                new_start: if new_lines == 0 {
+                    new_before
+                } else {
+                    new_before + 1
+                },
+                new_lines,
+                edits: edits[start..end].to_vec(),
+            }
+        })
+        .collect()
+}
+
+/// Private helper function counting the old and new lines covered by edits
+fn line_counts(edits: &[Edit]) -> (usize, usize) {
+    edits.iter().fold((0, 0), |(old, new), edit| match edit {
+        Edit::Equal { .. } => (old + 1, new + 1),
+        Edit::Delete { .. } => (old + 1, new),
+        Edit::Insert { .. } => (old, new + 1),
+    })
+}
+
+/// Private helper function formatting a hunk side count, a single line is written without its count
+fn hunk_range(start: usize, lines: usize) -> String {
+    if lines == 1 {
+        start.to_string()
+    } else {
+        format!("{},{}", start, lines)
+    }
+}
+
+/// Private helper function writing one line of a hunk, marking lines without a newline at the end of the file
+fn push_line(output: &mut String, marker: char, line: &str) {
+    output.push(marker);
+    output.push_str(line.strip_suffix('\n').unwrap_or(line));
+    output.push('\n');
+    if !line.ends_with('\n') {
+        output.push_str("\\ No newline at end of file\n");
+    }
+}
+
+/// Creates the unified diff between two texts, empty if they are the same
+///
+/// #### Arguments
+///
+/// * `old_name` - Name written on the `---` line (`/dev/null` for a new file)
+/// * `new_name` - Name written on the `+++` line (`/dev/null` for a deleted file)
+/// * `old` - Old contents
+/// * `new` - New contents
+/// * `context` - Number of unchanged lines shown around every change
+pub fn unified_diff(
+    old_name: &str,
+    new_name: &str,
+    old: &str,
+    new: &str,
+    context: usize,
+) -> String {
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let hunks = hunks(&diff(&old_lines, &new_lines), context);
+    if hunks.is_empty() {
+        return String::new();
+    }
+
+    let mut output = format!("--- {}\n+++ {}\n", old_name, new_name);
+    for hunk in hunks {
+        output.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(hunk.old_start, hunk.old_lines),
+            hunk_range(hunk.new_start, hunk.new_lines)
+        ));
+        for edit in hunk.edits {
+            match edit {
+                Edit::Equal { old, .. } => push_line(&mut output, ' ', old_lines[old]),
+                Edit::Delete { old } => push_line(&mut output, '-', old_lines[old]),
+                Edit::Insert { new } => push_line(&mut output, '+', new_lines[new]),
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // applies edits to the old sequence, which has to give back the new one
+    fn apply(old: &[&str], new: &[&str], edits: &[Edit]) -> Vec<String> {
+        edits
+            .iter()
+            .filter_map(|edit| match edit {
+                Edit::Equal { old: line, .. } => Some(old[*line].to_string()),
+                Edit::Delete { .. } => None,
+                Edit::Insert { new: line } => Some(new[*line].to_string()),
+            })
+            .collect()
+    }
+
+    fn changes(edits: &[Edit]) -> usize {
+        edits
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Equal { .. }))
+            .count()
+    }
+
+    #[test]
+    fn test_diff_shortest_edit_script() {
+        // classic example from the Myers paper, the shortest edit script has 5 changes
+        let old = vec!["a", "b", "c", "a", "b", "b", "a"];
+        let new = vec!["c", "b", "a", "b", "a", "c"];
+        let edits = diff(&old, &new);
+        assert_eq!(changes(&edits), 5);
+        assert_eq!(apply(&old, &new, &edits), new);
+    }
+
+    #[test]
+    fn test_diff_edge_cases() {
+        let empty: Vec<&str> = vec![];
+        assert!(diff(&empty, &empty).is_empty());
+        assert_eq!(diff(&empty, &["a"]), vec![Edit::Insert { new: 0 }]);
+        assert_eq!(diff(&["a"], &empty), vec![Edit::Delete { old: 0 }]);
+        assert_eq!(changes(&diff(&["a", "b"], &["a", "b"])), 0);
+
+        // duplicate lines keep their order
+        let old = vec!["x", "x", "y", "x"];
+        let new = vec!["x", "y", "x", "x"];
+        let edits = diff(&old, &new);
+        assert_eq!(changes(&edits), 2);
+        assert_eq!(apply(&old, &new, &edits), new);
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let new = "1\n2\nthree\n4\n5\n6\n7\n8\n9\n10\n11\n";
+        assert_eq!(
+            unified_diff("a/file.txt", "b/file.txt", old, new, 1),
+            "--- a/file.txt\n+++ b/file.txt\n\
+             @@ -2,3 +2,3 @@\n 2\n-3\n+three\n 4\n\
+             @@ -10 +10,2 @@\n 10\n+11\n"
+        );
+
+        // with more context both changes share a hunk
+        let diff = unified_diff("a/file.txt", "b/file.txt", old, new, 3);
+        assert_eq!(diff.matches("@@ -").count(), 2);
+        let diff = unified_diff("a/file.txt", "b/file.txt", old, new, 4);
+        assert_eq!(diff.matches("@@ -").count(), 1);
+        assert!(diff.contains("@@ -1,10 +1,11 @@\n"));
+    }
+
+    #[test]
+    fn test_unified_diff_new_and_missing_newline() {
+        assert_eq!(
+            unified_diff("/dev/null", "b/new.txt", "", "a\nb", 3),
+            "--- /dev/null\n+++ b/new.txt\n@@ -0,0 +1,2 @@\n+a\n+b\n\\ No newline at end of file\n"
+        );
+        assert_eq!(
+            unified_diff("a/same.txt", "b/same.txt", "a\n", "a\n", 3),
+            ""
+        );
+    }
+}
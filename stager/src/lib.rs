@@ -1,208 +1,500 @@
+pub mod status;
+
 pub mod stager {
-    use staging::staging_storage::{StagedComparison, Staging};
+    use crate::status::{FileStatus, StatusEntry, StatusReport};
+    use dvcs_error::DvcsError;
+    use repo_directory_hiding::line_diff::unified_diff;
+    use staging::ignore::IgnoreRule;
+    use staging::staging_storage::{StagedData, Staging};
+    use std::collections::{BTreeMap, BTreeSet};
     use std::fs;
-    use std::fs::File;
-    use std::io;
+    use storage_hiding::commit_objects::Commit;
+    use storage_hiding::repository_storage::RepositoryStorage;
+    use storage_hiding::tree_objects::Tree;
 
-    const DVCS_HIDDEN: &str = "/tmp/dvcs_team";
+    /// Which two versions of the files a diff compares
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub enum DiffTarget {
+        /// Working directory against the staged version
+        WorkingToStaged,
+        /// Staged version against the repository version (HEAD)
+        StagedToHead,
+        /// Commit against the working directory
+        CommitToWorking(String),
+        /// Two commits, from the first to the second
+        Commits(String, String),
+    }
 
-    pub struct Repo {
-        root_path: String,
-        head: String,
-        modified: bool,
+    impl DiffTarget {
+        /// Parses the revision argument of the diff command, empty compares against the staged version, `--cached` or `--staged` compares the staged version against HEAD, `<commit>` compares a commit against the working directory and `<commit>..<commit>` compares two commits
+        pub fn parse(revision: &str) -> DiffTarget {
+            match revision {
+                "" => DiffTarget::WorkingToStaged,
+                "--cached" | "--staged" => DiffTarget::StagedToHead,
+                _ => match revision.split_once("..") {
+                    Some((old, new)) => DiffTarget::Commits(old.to_string(), new.to_string()),
+                    None => DiffTarget::CommitToWorking(revision.to_string()),
+                },
+            }
+        }
     }
-    impl Repo {}
 
     #[derive(Clone, Debug)]
     pub struct Stager {
+        dvcs_hidden: String,
         staging: Staging,
     }
 
     impl Stager {
-        pub fn new(dvcs_hidden: &str, working_directory: &str) -> Result<Stager, String> {
-            let staging_ = Staging::new(dvcs_hidden, working_directory);
-            if staging_.is_err() {
-                return Err(staging_.err().unwrap());
+        pub fn new(dvcs_hidden: &str, working_directory: &str) -> Result<Stager, DvcsError> {
+            Ok(Stager {
+                dvcs_hidden: dvcs_hidden.to_string(),
+                staging: Staging::new(dvcs_hidden, working_directory)?,
+            })
+        }
+
+        /// Private helper function writing the unified diff of every file that differs between two versions, each version maps paths to blob ids
+        fn show_diff(
+            &self,
+            storage: &RepositoryStorage,
+            old: &BTreeMap<String, String>,
+            new: &BTreeMap<String, String>,
+            new_is_working: bool,
+            context: usize,
+        ) -> Result<String, DvcsError> {
+            let paths: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+            let mut output = String::new();
+            for path in paths {
+                let (old_id, new_id) = (old.get(path), new.get(path));
+                if old_id == new_id {
+                    continue;
+                }
+                let old_contents = match old_id {
+                    Some(id) => storage.get(id)?,
+                    None => vec![],
+                };
+                let new_contents = match new_id {
+                    Some(_) if new_is_working => {
+                        let working_path = self.staging.working_path(path);
+                        fs::read(&working_path).map_err(|e| DvcsError::io(&working_path, e))?
+                    }
+                    Some(id) => storage.get(id)?,
+                    None => vec![],
+                };
+
+                output.push_str(&format!("diff --dvcs a/{} b/{}\n", path, path));
+                let old_name = match old_id {
+                    Some(_) => format!("a/{}", path),
+                    None => "/dev/null".to_string(),
+                };
+                let new_name = match new_id {
+                    Some(_) => format!("b/{}", path),
+                    None => "/dev/null".to_string(),
+                };
+                // * Files with null bytes or that aren't text can't be shown line by line
+                match (
+                    std::str::from_utf8(&old_contents),
+                    std::str::from_utf8(&new_contents),
+                ) {
+                    (Ok(old_text), Ok(new_text))
+                        if !old_text.contains('\0') && !new_text.contains('\0') =>
+                    {
+                        output.push_str(&unified_diff(
+                            &old_name, &new_name, old_text, new_text, context,
+                        ));
+                    }
+                    _ => output.push_str(&format!(
+                        "Binary files {} and {} differ\n",
+                        old_name, new_name
+                    )),
+                }
             }
-            return Ok(Stager {
-                staging: staging_.unwrap(),
-            });
+            Ok(output)
         }
 
-        fn show_diff(current_path: String, orig: String) -> String {
-            return String::from("");
+        /// Private helper function collecting the blob ids of one slot of the index, the staged version falls back to nothing for files removed from staging
+        fn index_files(&self, staged: bool) -> BTreeMap<String, String> {
+            self.staging
+                .get_index()
+                .iter()
+                .filter_map(|(key, comparison)| {
+                    let data = if staged {
+                        comparison.staging.as_ref()
+                    } else {
+                        comparison.repository_version.as_ref()
+                    };
+                    data.filter(|data| data.is_file)
+                        .map(|data| (key.clone(), data.sha1.clone()))
+                })
+                .collect()
         }
 
-        fn is_repo(current_path: String) -> bool {
-            return true;
+        /// Private helper function hashing the working directory version of the given paths, missing files are left out
+        fn working_files<'a>(
+            &self,
+            paths: impl Iterator<Item = &'a String>,
+        ) -> BTreeMap<String, String> {
+            paths
+                .filter_map(|path| {
+                    self.staging
+                        .get_working_file_hash(path)
+                        .map(|hash| (path.clone(), hash))
+                })
+                .collect()
         }
 
-        fn is_changed(current_path: String) -> bool {
-            return true;
+        /// Private helper function collecting the blob ids of every file in a commit
+        fn commit_files(
+            storage: &RepositoryStorage,
+            commit_id: &str,
+        ) -> Result<BTreeMap<String, String>, DvcsError> {
+            let commit = Commit::read(storage, commit_id).map_err(|e| match e {
+                DvcsError::ObjectMissing(_) | DvcsError::InvalidArgument(_) => {
+                    DvcsError::RevisionNotFound(commit_id.to_string())
+                }
+                e => e,
+            })?;
+            Ok(Tree::flatten(storage, &commit.tree)?
+                .into_iter()
+                .map(|(path, entry)| (path, entry.id))
+                .collect())
         }
 
-        pub fn diff(self, file_path: String, head: String) -> Result<String, String> {
-            let mut path: String;
-            if head.is_empty() {
-                //find path to head origin or error
-                return Ok(String::from(""));
+        /// Shows the changes between two versions of the files as a unified diff
+        ///
+        /// #### Arguments
+        ///
+        /// * `file_path` - File or folder to limit the diff to, empty for the whole working directory
+        /// * `target` - Which two versions to compare
+        /// * `context` - Number of unchanged lines shown around every change
+        pub fn diff(
+            &self,
+            file_path: &str,
+            target: DiffTarget,
+            context: usize,
+        ) -> Result<String, DvcsError> {
+            let storage = RepositoryStorage::new(&self.dvcs_hidden)?;
+            let (old, new, new_is_working) = match target {
+                DiffTarget::WorkingToStaged => {
+                    let staged = self.index_files(true);
+                    let working = self.working_files(staged.keys());
+                    (staged, working, true)
+                }
+                DiffTarget::StagedToHead => {
+                    (self.index_files(false), self.index_files(true), false)
+                }
+                DiffTarget::CommitToWorking(commit_id) => {
+                    let committed = Self::commit_files(&storage, &commit_id)?;
+                    let staged = self.index_files(true);
+                    let working = self.working_files(committed.keys().chain(staged.keys()));
+                    (committed, working, true)
+                }
+                DiffTarget::Commits(old_id, new_id) => (
+                    Self::commit_files(&storage, &old_id)?,
+                    Self::commit_files(&storage, &new_id)?,
+                    false,
+                ),
+            };
+
+            // * Only keep the files inside the requested path
+            let prefix = if file_path.is_empty() {
+                ".".to_string()
             } else {
-                //find path to revision or error
-                path = String::from("/tmp/1");
-            }
-            return Ok(Self::show_diff(file_path, path));
+                self.staging.index_key(file_path)?
+            };
+            let inside = |path: &String| {
+                prefix == "." || *path == prefix || path.starts_with(&(prefix.clone() + "/"))
+            };
+            let old = old.into_iter().filter(|(path, _)| inside(path)).collect();
+            let new = new.into_iter().filter(|(path, _)| inside(path)).collect();
+            self.show_diff(&storage, &old, &new, new_is_working, context)
         }
 
-        pub fn status(&mut self, file_path: String) -> Result<String, String> {
+        /// Classifies every changed path inside `file_path` by comparing the repository version (HEAD), the staged version and the working directory
+        pub fn status(&mut self, file_path: String) -> Result<StatusReport, DvcsError> {
             if file_path.is_empty() {
-                return Err(String::from("empty path"));
-            } else {
-                self.staging.update_staged_files();
-                // let ddd = &self.staging;
-
-                let values: Vec<StagedComparison> = self
-                    .staging
-                    .clone()
-                    .get_index()
-                    .clone()
-                    .into_values()
-                    .collect();
-                let update: String =
-                    values
-                        .iter()
-                        .fold(String::from("Tracking:\n"), |mut acc, val| {
-                            let staging_ = val.staging.clone();
-                            let other_ = val.working_directory.clone();
-                            if staging_.is_none() || other_.is_none() {
-                                return acc;
-                            }
-                            let sta = staging_.clone().unwrap();
-                            let oth = other_.unwrap();
-                            if sta.sha1 != oth.sha1 {
-                                let mod_str =
-                                    staging_.clone().unwrap().path + &"(modified)\n".to_string();
-                                acc.push_str(&mod_str);
-                            } else {
-                                let mod_str = staging_.clone().unwrap().path + &"\n".to_string();
-                                acc.push_str(&mod_str);
-                            }
-                            return acc;
-                        });
-                return Ok(update);
+                return Err(DvcsError::InvalidArgument("No path specified".to_string()));
             }
+            self.staging.update_staged_files()?;
+            let prefix = self.staging.index_key(&file_path)?;
+            let inside = |path: &str| {
+                prefix == "." || path == prefix || path.starts_with(&(prefix.clone() + "/"))
+            };
+            // * Folders are implied by the files in them, only files have a status, a file whose executable bit changed is modified
+            let file_id = |data: &Option<StagedData>| {
+                data.as_ref()
+                    .filter(|data| data.is_file)
+                    .map(|data| (data.sha1.clone(), data.executable))
+            };
+
+            let mut entries = vec![];
+            let (mut head_ids, mut staged_ids) = (vec![], vec![]);
+            for (key, comparison) in self.staging.get_index() {
+                if !inside(key) {
+                    continue;
+                }
+                let head = file_id(&comparison.repository_version);
+                let staged = file_id(&comparison.staging);
+                let working = file_id(&comparison.working_directory);
+                let mut push = |status| {
+                    entries.push(StatusEntry {
+                        path: key.clone(),
+                        status,
+                    })
+                };
+                // * Until it is staged again the version of an unmerged file in the index says nothing
+                if comparison.unmerged {
+                    push(FileStatus::Unmerged);
+                    continue;
+                }
+                match (&head, &staged) {
+                    (None, Some(staged)) => {
+                        staged_ids.push((key.clone(), staged.0.clone()));
+                        push(FileStatus::StagedNew)
+                    }
+                    (Some(head), Some(staged)) if head != staged => {
+                        push(FileStatus::StagedModified)
+                    }
+                    (Some(head), None) => {
+                        head_ids.push((key.clone(), head.0.clone()));
+                        push(FileStatus::StagedDeleted)
+                    }
+                    _ => {}
+                }
+                match (&staged, &working) {
+                    (Some(_), None) => push(FileStatus::UnstagedDeleted),
+                    (Some(staged), Some(working)) if staged != working => {
+                        push(FileStatus::UnstagedModified)
+                    }
+                    _ => {}
+                }
+            }
+            // * Ignored files are left out, see .dvcsignore
+            let unmerged = self.staging.unmerged_paths();
+            for key in self.staging.untracked_files()? {
+                if inside(&key) && !unmerged.contains(&key) {
+                    entries.push(StatusEntry {
+                        path: key,
+                        status: FileStatus::Untracked,
+                    });
+                }
+            }
+            Ok(StatusReport::new(entries, &head_ids, &staged_ids))
         }
 
-        pub fn add(mut self, file_path: String) -> Result<(), String> {
+        #[allow(clippy::should_implement_trait)]
+        pub fn add(mut self, file_path: String) -> Result<(), DvcsError> {
             if file_path.is_empty() {
-                return Err(String::from("No path specified"));
-            } else {
-                // let staging = Staging::new(DVCS_HIDDEN, file_path.as_str());
-                let res = self.staging.add_file_to_staging(file_path.as_str());
-                if res.is_ok() {
-                    return Ok(());
-                } else {
-                    return Err(res.err().unwrap());
-                }
+                return Err(DvcsError::InvalidArgument("No path specified".to_string()));
             }
+            // * Folders are added with everything in them that isn't ignored
+            self.staging.stage_path(&file_path)
         }
 
-        pub fn remove(mut self, file_path: String) -> Result<(), String> {
+        /// Finds the ignore rule deciding if a path is ignored, a negated rule means it was re-included, None if no rule matches
+        pub fn check_ignore(&self, file_path: &str) -> Result<Option<IgnoreRule>, DvcsError> {
+            self.staging.check_ignore(file_path)
+        }
+
+        pub fn remove(mut self, file_path: String) -> Result<(), DvcsError> {
             if file_path.is_empty() {
-                return Err(String::from("No path specified"));
-            } else {
-                let res = self.staging.remove_file_from_staging(file_path.as_str());
-                if res.is_ok() {
-                    return Ok(());
-                } else {
-                    return Err(res.err().unwrap());
-                }
+                return Err(DvcsError::InvalidArgument("No path specified".to_string()));
             }
+            self.staging.remove_file_from_staging(file_path.as_str())
         }
 
-        pub fn init(mut self, file_path: String) -> Result<(), String> {
+        pub fn init(mut self, file_path: String) -> Result<(), DvcsError> {
             if file_path.is_empty() {
-                return Err(String::from("No path specified"));
-            } else {
-                let contents: Result<fs::ReadDir, io::Error> = fs::read_dir(&file_path);
-                if contents.unwrap().next().is_some() {
-                    return Ok(());
-                }
-                self.staging.set_staging_snapshot(1); // 1 = working directory
+                return Err(DvcsError::InvalidArgument("No path specified".to_string()));
+            }
+            let mut contents =
+                fs::read_dir(&file_path).map_err(|e| DvcsError::io(&file_path, e))?;
+            if contents.next().is_some() {
                 return Ok(());
             }
+            self.staging.refresh_working_copy()
         }
     }
 
     #[cfg(test)]
     mod tests {
-        use crate::stager;
-
         use super::*;
 
+        // creates an empty repository in the temp folder, returns the dvcs hidden folder and the working directory
+        fn temp_repository(name: &str) -> (String, String) {
+            let root = std::env::temp_dir().join(format!("dvcs_stager_{}", name));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join(".dvcs_hidden")).unwrap();
+            let root = root.to_str().unwrap().to_string();
+            (root.clone() + "/.dvcs_hidden", root)
+        }
+
         #[test]
-        // * Adding a file to be stored in the staging storage successfully
+        // * Nothing changed, the diff is empty
         fn success_diff() {
-            let stager_i = Stager::new(DVCS_HIDDEN, "/tmp/one").unwrap();
-            let a = stager_i.diff(String::from("/tmp/one"), String::from(""));
+            let (dvcs_hidden, root) = temp_repository("success_diff");
+            let stager_i = Stager::new(&dvcs_hidden, &root).unwrap();
+            let a = stager_i.diff(&root, DiffTarget::WorkingToStaged, 3);
 
-            assert_eq!(a, Ok(String::from("")));
+            assert_eq!(a.unwrap(), "");
         }
 
         #[test]
-        // * Adding a file to be stored in the staging storage successfully
-        fn all_status() {
-            //               let stager_ = Stager {};
-            //               let a = stager_.status(String::from("/tmp/one"));
-            fs::create_dir(DVCS_HIDDEN);
+        // * Working directory against staged and staged against the repository version
+        fn diff_working_and_staged() {
+            let (dvcs_hidden, root) = temp_repository("diff_working_and_staged");
+            let file_path = root.clone() + "/one.txt";
+            fs::write(&file_path, "a\nb\n").unwrap();
+            Stager::new(&dvcs_hidden, &root)
+                .unwrap()
+                .add(file_path.clone())
+                .unwrap();
+            fs::write(&file_path, "a\nc\n").unwrap();
 
-            fs::remove_dir_all("/tmp/dvcs_test/");
-            fs::create_dir("/tmp/dvcs_test/");
-
-            let mut stager_i = Stager::new(DVCS_HIDDEN, "/tmp/one").unwrap();
+            let stager_i = Stager::new(&dvcs_hidden, &root).unwrap();
+            assert_eq!(
+                stager_i.diff("", DiffTarget::WorkingToStaged, 3).unwrap(),
+                "diff --dvcs a/one.txt b/one.txt\n--- a/one.txt\n+++ b/one.txt\n@@ -1,2 +1,2 @@\n a\n-b\n+c\n"
+            );
+            // the file isn't in the repository version yet, so it shows as new
+            assert_eq!(
+                stager_i.diff(&file_path, DiffTarget::StagedToHead, 3).unwrap(),
+                "diff --dvcs a/one.txt b/one.txt\n--- /dev/null\n+++ b/one.txt\n@@ -0,0 +1,2 @@\n+a\n+b\n"
+            );
+            // paths outside of the changed file give an empty diff
+            fs::create_dir_all(root.clone() + "/other").unwrap();
+            assert_eq!(
+                stager_i
+                    .diff(&(root.clone() + "/other"), DiffTarget::WorkingToStaged, 3)
+                    .unwrap(),
+                ""
+            );
+        }
 
-            let a = stager_i.init(String::from("/tmp/dvcs_test/"));
+        #[test]
+        // * Any two commits can be compared, unknown commits are an error
+        fn diff_commits() {
+            let (dvcs_hidden, root) = temp_repository("diff_commits");
+            let storage = RepositoryStorage::new(&dvcs_hidden).unwrap();
+            let commit = |contents: &str| {
+                let blob = storage.put(contents.as_bytes()).unwrap();
+                let tree = Tree::write_from_paths(
+                    &storage,
+                    &[(
+                        "src/main.rs".to_string(),
+                        storage_hiding::tree_objects::EntryMode::File,
+                        blob,
+                    )],
+                )
+                .unwrap();
+                let signature = storage_hiding::commit_objects::Signature::now("a", "b");
+                Commit {
+                    tree,
+                    parents: vec![],
+                    author: signature.clone(),
+                    committer: signature,
+                    message: contents.to_string(),
+                }
+                .write(&storage)
+                .unwrap()
+            };
+            let first = commit("fn main() {}\n");
+            let second = commit("fn main() {\n}\n");
 
-            let file = File::create("/tmp/dvcs_test/one.txt");
-            // stager_i.add(String::from("/tmp/dvcs_test/one.txt"));
+            let stager_i = Stager::new(&dvcs_hidden, &root).unwrap();
+            assert_eq!(
+                stager_i.diff("", DiffTarget::Commits(first.clone(), second.clone()), 0).unwrap(),
+                "diff --dvcs a/src/main.rs b/src/main.rs\n--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1 +1,2 @@\n-fn main() {}\n+fn main() {\n+}\n"
+            );
+            assert!(stager_i
+                .diff("", DiffTarget::Commits(first, "f".repeat(40)), 3)
+                .is_err());
+            assert_eq!(
+                DiffTarget::parse("a..b"),
+                DiffTarget::Commits("a".to_string(), "b".to_string())
+            );
+        }
 
-            //            let a = stager_i.clone().status(String::from("/tmp/dvcs_test/"));
+        #[test]
+        // * Adding a file to be stored in the staging storage successfully
+        fn all_status() {
+            let (dvcs_hidden, root) = temp_repository("all_status");
+            let stager_i = Stager::new(&dvcs_hidden, &root).unwrap();
+            let a = stager_i.clone().init(root.clone());
+            assert!(a.is_ok());
 
-            assert_eq!(a.is_ok(), true);
-            //            assert_eq!(stager_i.clone().status(String::from("")), Err(String::from("empty path")));
+            let file_path = root.clone() + "/one.txt";
+            fs::write(&file_path, "one").unwrap();
+            stager_i.add(file_path.clone()).unwrap();
+            let mut stager_i = Stager::new(&dvcs_hidden, &root).unwrap();
+            assert_eq!(
+                stager_i.status(root.clone()).unwrap().to_porcelain(),
+                "A  one.txt\n"
+            );
+            // * Untracked files are listed unless they are ignored
+            fs::write(root.clone() + "/two.txt", "two").unwrap();
+            fs::write(root.clone() + "/three.tmp", "three").unwrap();
+            fs::write(root.clone() + "/.dvcsignore", "*.tmp\n.dvcsignore\n").unwrap();
+            assert_eq!(
+                stager_i.status(root.clone()).unwrap().to_porcelain(),
+                "A  one.txt\n?? two.txt\n"
+            );
+            fs::write(&file_path, "changed").unwrap();
+            assert_eq!(
+                stager_i.status(root.clone()).unwrap().to_porcelain(),
+                "AM one.txt\n?? two.txt\n"
+            );
+            // * Only paths inside the given path are reported
+            assert_eq!(
+                stager_i
+                    .status(root.clone() + "/two.txt")
+                    .unwrap()
+                    .to_porcelain(),
+                "?? two.txt\n"
+            );
+            fs::remove_file(&file_path).unwrap();
+            assert_eq!(
+                stager_i.status(root).unwrap().to_porcelain(),
+                "AD one.txt\n?? two.txt\n"
+            );
+            assert!(matches!(
+                stager_i.status(String::new()),
+                Err(DvcsError::InvalidArgument(_))
+            ));
         }
 
         #[test]
-        // * Adding a file to be stored in the staging storage successfully
+        // * Adding a file that doesn't exist fails
         fn all_add() {
-            let stager_i = Stager::new(DVCS_HIDDEN, "/tmp/one").unwrap();
-            let a = stager_i.add(String::from("/tmp/one"));
+            let (dvcs_hidden, root) = temp_repository("all_add");
+            let stager_i = Stager::new(&dvcs_hidden, &root).unwrap();
+            let a = stager_i.add(root + "/one");
 
-            assert_eq!(a.is_ok(), false);
+            assert!(matches!(a, Err(DvcsError::PathNotFound(_))));
         }
 
         #[test]
-        // * Adding a file to be stored in the staging storage successfully
+        // * Removing a file that isn't staged fails
         fn all_remove() {
-            let stager_i = Stager::new(DVCS_HIDDEN, "/tmp/one").unwrap();
-            let a = stager_i.remove(String::from("/tmp/one"));
+            let (dvcs_hidden, root) = temp_repository("all_remove");
+            let stager_i = Stager::new(&dvcs_hidden, &root).unwrap();
+            let a = stager_i.remove(root + "/one");
 
-            assert_eq!(a.is_ok(), false);
+            assert!(a.is_err());
         }
         #[test]
-        // * Adding a file to be stored in the staging storage successfully
+        // * Init of an empty and a non-empty working directory
         fn all_init() {
-            fs::create_dir(DVCS_HIDDEN);
-
-            fs::remove_dir_all("/tmp/dvcs_test/");
-            fs::create_dir("/tmp/dvcs_test/");
-            let stager_i = Stager::new(DVCS_HIDDEN, "/tmp/one").unwrap();
+            let (dvcs_hidden, root) = temp_repository("all_init");
+            let stager_i = Stager::new(&dvcs_hidden, &root).unwrap();
 
-            let b = stager_i.clone().init(String::from("/tmp/dvcs_test/"));
-            assert_eq!(b.is_ok(), true);
+            let b = stager_i.clone().init(root.clone());
+            assert!(b.is_ok());
 
-            let file = File::create("/tmp/dvcs_test/one.txt");
-            let b = stager_i.clone().init(String::from("/tmp/dvcs_test/"));
-            assert_eq!(b.is_ok(), true);
+            fs::write(root.clone() + "/one.txt", "").unwrap();
+            let b = stager_i.clone().init(root.clone());
+            assert!(b.is_ok());
+            assert!(stager_i.init(root + "/missing").is_err());
         }
     }
 }
@@ -0,0 +1,353 @@
+/// How a path differs between the repository version (HEAD), the staged version and the working directory
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FileStatus {
+    /// Staged but not in HEAD
+    StagedNew,
+    /// Staged contents differ from HEAD
+    StagedModified,
+    /// In HEAD but removed from staging
+    StagedDeleted,
+    /// Staged under a new path with the same contents a removed path had in HEAD
+    StagedRenamed { from: String },
+    /// Left by a merge that couldn't merge it and not staged or removed since
+    Unmerged,
+    /// Working directory contents differ from the staged contents
+    UnstagedModified,
+    /// Staged but missing from the working directory
+    UnstagedDeleted,
+    /// In the working directory but neither staged nor ignored
+    Untracked,
+}
+
+impl FileStatus {
+    /// Checks if the status is a change to be committed
+    pub fn is_staged(&self) -> bool {
+        matches!(
+            self,
+            FileStatus::StagedNew
+                | FileStatus::StagedModified
+                | FileStatus::StagedDeleted
+                | FileStatus::StagedRenamed { .. }
+        )
+    }
+
+    /// Private helper function getting the one letter code of the status used by the porcelain format
+    fn code(&self) -> char {
+        match self {
+            FileStatus::StagedNew => 'A',
+            FileStatus::StagedModified | FileStatus::UnstagedModified => 'M',
+            FileStatus::StagedDeleted | FileStatus::UnstagedDeleted => 'D',
+            FileStatus::StagedRenamed { .. } => 'R',
+            FileStatus::Unmerged => 'U',
+            FileStatus::Untracked => '?',
+        }
+    }
+
+    /// Private helper function getting the section of the human layout, staged, unmerged, unstaged or untracked
+    fn group(&self) -> usize {
+        match self {
+            FileStatus::Unmerged => 1,
+            FileStatus::UnstagedModified | FileStatus::UnstagedDeleted => 2,
+            FileStatus::Untracked => 3,
+            _ => 0,
+        }
+    }
+
+    /// Private helper function getting the label of the status used by the human layout
+    fn label(&self) -> &'static str {
+        match self {
+            FileStatus::StagedNew => "new file:",
+            FileStatus::StagedModified | FileStatus::UnstagedModified => "modified:",
+            FileStatus::StagedDeleted | FileStatus::UnstagedDeleted => "deleted:",
+            FileStatus::StagedRenamed { .. } => "renamed:",
+            FileStatus::Unmerged => "both modified:",
+            FileStatus::Untracked => "",
+        }
+    }
+}
+
+/// Status of one path, a path can have a staged and an unstaged status at the same time
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StatusEntry {
+    /// Index key of the path
+    pub path: String,
+    pub status: FileStatus,
+}
+
+/// Every path that differs between HEAD, the staged files and the working directory
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatusReport {
+    /// Entries sorted by path, then staged before unstaged
+    pub entries: Vec<StatusEntry>,
+}
+
+impl StatusReport {
+    /// Creates a report from entries in any order, staged deletions and additions of the same contents are paired up as renames
+    ///
+    /// #### Arguments
+    ///
+    /// * `entries` - Status of every path that changed
+    /// * `head_ids` - Blob id of the HEAD version of the staged deletions
+    /// * `staged_ids` - Blob id of the staged version of the staged additions
+    pub fn new(
+        mut entries: Vec<StatusEntry>,
+        head_ids: &[(String, String)],
+        staged_ids: &[(String, String)],
+    ) -> StatusReport {
+        entries.sort();
+        for (deleted, id) in head_ids {
+            let renamed = staged_ids.iter().find(|(added, added_id)| {
+                added_id == id
+                    && entries.contains(&StatusEntry {
+                        path: added.clone(),
+                        status: FileStatus::StagedNew,
+                    })
+            });
+            if let Some((added, _)) = renamed {
+                let deleted_entry = StatusEntry {
+                    path: deleted.clone(),
+                    status: FileStatus::StagedDeleted,
+                };
+                if !entries.contains(&deleted_entry) {
+                    continue;
+                }
+                entries.retain(|entry| *entry != deleted_entry);
+                for entry in entries.iter_mut() {
+                    if entry.path == *added && entry.status == FileStatus::StagedNew {
+                        entry.status = FileStatus::StagedRenamed {
+                            from: deleted.clone(),
+                        };
+                    }
+                }
+            }
+        }
+        entries.sort();
+        StatusReport { entries }
+    }
+
+    /// Checks if nothing changed and nothing is untracked
+    pub fn is_clean(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Formats the report grouped into changes to be committed, unmerged paths, changes not staged and untracked files
+    pub fn to_human(&self) -> String {
+        if self.is_clean() {
+            return "nothing to commit, working tree clean\n".to_string();
+        }
+        let mut output = String::new();
+        let titles = [
+            "Changes to be committed:",
+            "Unmerged paths:",
+            "Changes not staged for commit:",
+            "Untracked files:",
+        ];
+        for (group, title) in titles.iter().enumerate() {
+            let entries: Vec<&StatusEntry> = self
+                .entries
+                .iter()
+                .filter(|entry| entry.status.group() == group)
+                .collect();
+            if entries.is_empty() {
+                continue;
+            }
+            if !output.is_empty() {
+                output.push('\n');
+            }
+            output.push_str(title);
+            output.push('\n');
+            for entry in entries {
+                let path = match &entry.status {
+                    FileStatus::StagedRenamed { from } => format!("{} -> {}", from, entry.path),
+                    _ => entry.path.clone(),
+                };
+                match entry.status {
+                    FileStatus::Untracked => output.push_str(&format!("\t{}\n", path)),
+                    FileStatus::Unmerged => {
+                        output.push_str(&format!("\t{:<17}{}\n", entry.status.label(), path))
+                    }
+                    _ => output.push_str(&format!("\t{:<12}{}\n", entry.status.label(), path)),
+                }
+            }
+        }
+        if !self.entries.iter().any(|entry| entry.status.is_staged()) {
+            output.push_str("\nno changes added to commit (use \"dvcs add\")\n");
+        }
+        output
+    }
+
+    /// Formats the report one path per line as `XY <path>`, X is the staged status and Y the unstaged one (`A` new, `M` modified, `D` deleted, `R` renamed, ` ` unchanged), unmerged paths are `UU <path>`, untracked paths `?? <path>` and renames `R  <from> -> <path>`
+    ///
+    /// The format doesn't change between versions, paths with quotes, backslashes or control characters are quoted C style
+    pub fn to_porcelain(&self) -> String {
+        let mut output = String::new();
+        let mut untracked = String::new();
+        let mut position = 0;
+        while position < self.entries.len() {
+            let path = &self.entries[position].path;
+            let statuses: Vec<&FileStatus> = self.entries[position..]
+                .iter()
+                .take_while(|entry| entry.path == *path)
+                .map(|entry| &entry.status)
+                .collect();
+            position += statuses.len();
+
+            if statuses.contains(&&FileStatus::Unmerged) {
+                output.push_str(&format!("UU {}\n", quote_path(path)));
+                continue;
+            }
+            // * A path removed from staging but still on disk is both a staged deletion and untracked
+            if statuses.contains(&&FileStatus::Untracked) {
+                untracked.push_str(&format!("?? {}\n", quote_path(path)));
+            }
+            let staged = statuses.iter().find(|status| status.is_staged());
+            let unstaged = statuses
+                .iter()
+                .find(|status| !status.is_staged() && ***status != FileStatus::Untracked);
+            if staged.is_none() && unstaged.is_none() {
+                continue;
+            }
+            let name = match staged {
+                Some(FileStatus::StagedRenamed { from }) => {
+                    format!("{} -> {}", quote_path(from), quote_path(path))
+                }
+                _ => quote_path(path),
+            };
+            output.push_str(&format!(
+                "{}{} {}\n",
+                staged.map_or(' ', |status| status.code()),
+                unstaged.map_or(' ', |status| status.code()),
+                name
+            ));
+        }
+        output + &untracked
+    }
+}
+
+/// Private helper function quoting a path for the porcelain format if it has characters that would break a line based parser
+fn quote_path(path: &str) -> String {
+    if !path
+        .chars()
+        .any(|c| c == '"' || c == '\\' || c.is_control())
+    {
+        return path.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in path.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\{:03o}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, status: FileStatus) -> StatusEntry {
+        StatusEntry {
+            path: path.to_string(),
+            status,
+        }
+    }
+
+    #[test]
+    fn test_report_formats() {
+        let report = StatusReport::new(
+            vec![
+                entry("z.txt", FileStatus::Untracked),
+                entry("b.txt", FileStatus::UnstagedModified),
+                entry("b.txt", FileStatus::StagedModified),
+                entry("a.txt", FileStatus::StagedNew),
+                entry("c.txt", FileStatus::UnstagedDeleted),
+                entry("old.txt", FileStatus::StagedDeleted),
+                entry("new.txt", FileStatus::StagedNew),
+            ],
+            &[("old.txt".to_string(), "1".repeat(40))],
+            &[
+                ("a.txt".to_string(), "2".repeat(40)),
+                ("new.txt".to_string(), "1".repeat(40)),
+            ],
+        );
+        assert_eq!(
+            report.to_porcelain(),
+            "A  a.txt\nMM b.txt\n D c.txt\nR  old.txt -> new.txt\n?? z.txt\n"
+        );
+        assert_eq!(
+            report.to_human(),
+            "Changes to be committed:\n\
+             \tnew file:   a.txt\n\
+             \tmodified:   b.txt\n\
+             \trenamed:    old.txt -> new.txt\n\
+             \n\
+             Changes not staged for commit:\n\
+             \tmodified:   b.txt\n\
+             \tdeleted:    c.txt\n\
+             \n\
+             Untracked files:\n\
+             \tz.txt\n"
+        );
+    }
+
+    #[test]
+    // * A committed file removed from staging but kept on disk shows the staged deletion next to the untracked file
+    fn test_porcelain_removed_but_untracked() {
+        let report = StatusReport::new(
+            vec![
+                entry("a.txt", FileStatus::StagedDeleted),
+                entry("a.txt", FileStatus::Untracked),
+            ],
+            &[("a.txt".to_string(), "1".repeat(40))],
+            &[],
+        );
+        assert_eq!(report.to_porcelain(), "D  a.txt\n?? a.txt\n");
+        assert!(report.to_human().contains("\tdeleted:    a.txt\n"));
+    }
+
+    #[test]
+    // * Paths a merge couldn't merge get a section of their own
+    fn test_unmerged_paths() {
+        let report = StatusReport::new(
+            vec![
+                entry("a.txt", FileStatus::Unmerged),
+                entry("b.txt", FileStatus::StagedModified),
+            ],
+            &[],
+            &[],
+        );
+        assert_eq!(report.to_porcelain(), "UU a.txt\nM  b.txt\n");
+        assert_eq!(
+            report.to_human(),
+            "Changes to be committed:\n\
+             \tmodified:   b.txt\n\
+             \n\
+             Unmerged paths:\n\
+             \tboth modified:   a.txt\n"
+        );
+    }
+
+    #[test]
+    fn test_clean_report_and_quoting() {
+        assert!(StatusReport::default().is_clean());
+        assert_eq!(
+            StatusReport::default().to_human(),
+            "nothing to commit, working tree clean\n"
+        );
+        let report = StatusReport::new(
+            vec![entry("tab\there \"q\".txt", FileStatus::Untracked)],
+            &[],
+            &[],
+        );
+        assert_eq!(report.to_porcelain(), "?? \"tab\\there \\\"q\\\".txt\"\n");
+        assert!(report
+            .to_human()
+            .ends_with("no changes added to commit (use \"dvcs add\")\n"));
+    }
+}
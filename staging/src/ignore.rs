@@ -0,0 +1,413 @@
+use dvcs_error::DvcsError;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Name of the pattern files that can be put in any folder of the working directory
+pub const IGNORE_FILE_NAME: &str = ".dvcsignore";
+
+/// Single pattern of an ignore file, written like a gitignore pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IgnoreRule {
+    /// File the pattern was read from
+    pub source: String,
+    /// Line of the pattern in its file (1 based)
+    pub line: usize,
+    /// Pattern as it was written, without trailing spaces
+    pub pattern: String,
+    /// Folder (index key) the pattern is relative to, empty for the working directory
+    base: String,
+    /// `!` patterns re-include what an earlier pattern ignored
+    negated: bool,
+    /// Patterns ending with `/` only match folders
+    directory_only: bool,
+    /// Patterns with a `/` before their end match the path from `base`, others match the name at any depth
+    anchored: bool,
+    glob: String,
+}
+
+impl IgnoreRule {
+    /// Parses one line of an ignore file, None for blank lines and comments
+    ///
+    /// #### Arguments
+    ///
+    /// * `text` - Line of the ignore file
+    /// * `base` - Index key of the folder the pattern is relative to, empty for the working directory
+    /// * `source` - File the line was read from
+    /// * `line` - Line number (1 based)
+    pub fn parse(text: &str, base: &str, source: &str, line: usize) -> Option<IgnoreRule> {
+        let mut pattern = text.trim_end_matches('\r');
+        // * Trailing spaces are dropped unless they are escaped with a backslash
+        while pattern.ends_with(' ') && !pattern.ends_with("\\ ") {
+            pattern = &pattern[..pattern.len() - 1];
+        }
+        if pattern.is_empty() || pattern.starts_with('#') {
+            return None;
+        }
+
+        let (negated, mut glob) = match pattern.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, pattern),
+        };
+        let directory_only = glob.ends_with('/');
+        glob = glob.trim_end_matches('/');
+        let anchored = glob.contains('/');
+        glob = glob.trim_start_matches('/');
+        if glob.is_empty() {
+            return None;
+        }
+        Some(IgnoreRule {
+            source: source.to_string(),
+            line,
+            pattern: pattern.to_string(),
+            base: base.to_string(),
+            negated,
+            directory_only,
+            anchored,
+            glob: glob.to_string(),
+        })
+    }
+
+    /// Checks if the rule re-includes the paths it matches instead of ignoring them
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+
+    /// Checks if the rule matches an index key
+    pub fn matches(&self, key: &str, is_dir: bool) -> bool {
+        if self.directory_only && !is_dir {
+            return false;
+        }
+        let relative = if self.base.is_empty() {
+            key
+        } else {
+            match key.strip_prefix(&(self.base.clone() + "/")) {
+                Some(relative) => relative,
+                None => return false,
+            }
+        };
+        if self.anchored {
+            let pattern: Vec<&str> = self.glob.split('/').collect();
+            let path: Vec<&str> = relative.split('/').collect();
+            match_segments(&pattern, &path)
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            match_name(
+                &self.glob.chars().collect::<Vec<char>>(),
+                &name.chars().collect::<Vec<char>>(),
+            )
+        }
+    }
+}
+
+impl fmt::Display for IgnoreRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.source, self.line, self.pattern)
+    }
+}
+
+/// Private helper function matching pattern segments against path segments, `**` matches any number of folders
+fn match_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => {
+            // * A trailing `**` matches everything inside the folder but not the folder itself
+            if rest.is_empty() {
+                return !path.is_empty();
+            }
+            (0..=path.len()).any(|skip| match_segments(rest, &path[skip..]))
+        }
+        Some((segment, rest)) => match path.split_first() {
+            Some((name, path_rest)) => {
+                match_name(
+                    &segment.chars().collect::<Vec<char>>(),
+                    &name.chars().collect::<Vec<char>>(),
+                ) && match_segments(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+/// Private helper function matching a single file name against a wildcard pattern with `*`, `?`, `[...]` and `\` escapes
+fn match_name(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| match_name(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && match_name(&pattern[1..], &name[1..]),
+        Some('[') => match (name.first(), match_class(&pattern[1..])) {
+            (Some(c), Some((members, negated, length))) => {
+                class_contains(&members, *c) != negated
+                    && match_name(&pattern[length + 1..], &name[1..])
+            }
+            // * An unclosed `[` is an ordinary character
+            (Some('['), None) => match_name(&pattern[1..], &name[1..]),
+            _ => false,
+        },
+        Some('\\') if pattern.len() > 1 => {
+            name.first() == Some(&pattern[1]) && match_name(&pattern[2..], &name[1..])
+        }
+        Some(c) => name.first() == Some(c) && match_name(&pattern[1..], &name[1..]),
+    }
+}
+
+/// Private helper function reading a `[...]` class (after the `[`), returns its members, if it is negated and how many characters it takes up
+fn match_class(pattern: &[char]) -> Option<(Vec<char>, bool, usize)> {
+    let negated = matches!(pattern.first(), Some('!') | Some('^'));
+    let start = usize::from(negated);
+    // * A `]` right after the opening is part of the class
+    let end = pattern
+        .iter()
+        .enumerate()
+        .skip(start + 1)
+        .find(|(_, c)| **c == ']')
+        .map(|(position, _)| position)?;
+    Some((pattern[start..end].to_vec(), negated, end + 1))
+}
+
+/// Private helper function checking if a character is in the members of a class, `a-z` members are ranges
+fn class_contains(members: &[char], c: char) -> bool {
+    let mut position = 0;
+    while position < members.len() {
+        if position + 2 < members.len() && members[position + 1] == '-' {
+            if members[position] <= c && c <= members[position + 2] {
+                return true;
+            }
+            position += 3;
+        } else {
+            if members[position] == c {
+                return true;
+            }
+            position += 1;
+        }
+    }
+    false
+}
+
+/// Ignore patterns of a working directory, read from the `.dvcsignore` files of its folders, the repository exclude file and the global ignore file
+///
+/// Like gitignore, patterns in deeper folders win over patterns higher up, which win over the exclude file and then the global file, and within a file the last matching pattern wins. Paths inside an ignored folder can't be re-included
+#[derive(Debug, Clone)]
+pub struct Ignore {
+    working_directory: PathBuf,
+    exclude: Vec<IgnoreRule>,
+    global: Vec<IgnoreRule>,
+    /// `.dvcsignore` rules by folder key, read the first time a path in the folder is checked
+    per_directory: HashMap<String, Vec<IgnoreRule>>,
+}
+
+impl Ignore {
+    /// Loads the ignore patterns of a repository, with the global ignore file from `global_ignore_file`
+    ///
+    /// #### Arguments
+    ///
+    /// * `dvcs_hidden` - Path to the DVCS hidden folder, its `info/exclude` file holds patterns that aren't shared
+    /// * `working_directory` - Path to the working directory
+    pub fn new(dvcs_hidden: &str, working_directory: &str) -> Result<Ignore, DvcsError> {
+        Self::with_global(dvcs_hidden, working_directory, Self::global_ignore_file())
+    }
+
+    /// Loads the ignore patterns of a repository with a given global ignore file, None to not use one
+    pub fn with_global(
+        dvcs_hidden: &str,
+        working_directory: &str,
+        global: Option<PathBuf>,
+    ) -> Result<Ignore, DvcsError> {
+        let exclude_path = Path::new(dvcs_hidden).join("info").join("exclude");
+        let global = match global {
+            Some(path) => read_rules(&path, "", &path.display().to_string())?,
+            None => vec![],
+        };
+        Ok(Ignore {
+            working_directory: PathBuf::from(working_directory),
+            exclude: read_rules(&exclude_path, "", &exclude_path.display().to_string())?,
+            global,
+            per_directory: HashMap::new(),
+        })
+    }
+
+    /// Gets the global ignore file, `DVCS_GLOBAL_IGNORE` if it is set (empty for none), otherwise `$XDG_CONFIG_HOME/dvcs/ignore` or `$HOME/.config/dvcs/ignore`
+    pub fn global_ignore_file() -> Option<PathBuf> {
+        if let Ok(path) = env::var("DVCS_GLOBAL_IGNORE") {
+            return if path.is_empty() {
+                None
+            } else {
+                Some(PathBuf::from(path))
+            };
+        }
+        let config = match env::var("XDG_CONFIG_HOME") {
+            Ok(config) if !config.is_empty() => PathBuf::from(config),
+            _ => PathBuf::from(env::var("HOME").ok()?).join(".config"),
+        };
+        Some(config.join("dvcs").join("ignore"))
+    }
+
+    /// Checks if an index key is ignored
+    pub fn is_ignored(&mut self, key: &str, is_dir: bool) -> Result<bool, DvcsError> {
+        Ok(self
+            .matching_rule(key, is_dir)?
+            .is_some_and(|rule| !rule.is_negated()))
+    }
+
+    /// Finds the rule deciding if an index key is ignored, a negated rule means the path was re-included, None if no rule matches
+    pub fn matching_rule(
+        &mut self,
+        key: &str,
+        is_dir: bool,
+    ) -> Result<Option<IgnoreRule>, DvcsError> {
+        if key == "." {
+            return Ok(None);
+        }
+        // * Paths inside an ignored folder are ignored by the folder's rule
+        let components: Vec<&str> = key.split('/').collect();
+        for end in 1..components.len() {
+            let parent = components[..end].join("/");
+            if let Some(rule) = self.last_match(&parent, true)? {
+                if !rule.is_negated() {
+                    return Ok(Some(rule));
+                }
+            }
+        }
+        self.last_match(key, is_dir)
+    }
+
+    /// Private helper function finding the rule with the highest priority matching a key, without looking at its folders
+    fn last_match(&mut self, key: &str, is_dir: bool) -> Result<Option<IgnoreRule>, DvcsError> {
+        let components: Vec<&str> = key.split('/').collect();
+        let folders: Vec<String> = (0..components.len())
+            .map(|end| components[..end].join("/"))
+            .collect();
+        for folder in &folders {
+            self.load_directory(folder)?;
+        }
+        let sources = folders
+            .iter()
+            .rev()
+            .map(|folder| &self.per_directory[folder])
+            .chain([&self.exclude, &self.global]);
+        for rules in sources {
+            if let Some(rule) = rules.iter().rev().find(|rule| rule.matches(key, is_dir)) {
+                return Ok(Some(rule.clone()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Private helper function reading the `.dvcsignore` file of a folder if it wasn't read yet
+    fn load_directory(&mut self, folder: &str) -> Result<(), DvcsError> {
+        if !self.per_directory.contains_key(folder) {
+            let source = if folder.is_empty() {
+                IGNORE_FILE_NAME.to_string()
+            } else {
+                format!("{}/{}", folder, IGNORE_FILE_NAME)
+            };
+            let rules = read_rules(&self.working_directory.join(&source), folder, &source)?;
+            self.per_directory.insert(folder.to_string(), rules);
+        }
+        Ok(())
+    }
+}
+
+/// Private helper function reading the rules of an ignore file, a missing file has none
+fn read_rules(path: &Path, base: &str, source: &str) -> Result<Vec<IgnoreRule>, DvcsError> {
+    let contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        // * A folder with the name of an ignore file isn't one
+        Err(_) if path.is_dir() => return Ok(vec![]),
+        Err(e) => return Err(DvcsError::io(path, e)),
+    };
+    Ok(String::from_utf8_lossy(&contents)
+        .lines()
+        .enumerate()
+        .filter_map(|(number, text)| IgnoreRule::parse(text, base, source, number + 1))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str) -> IgnoreRule {
+        IgnoreRule::parse(pattern, "", IGNORE_FILE_NAME, 1).unwrap()
+    }
+
+    #[test]
+    fn test_patterns() {
+        assert!(IgnoreRule::parse("# comment", "", "", 1).is_none());
+        assert!(IgnoreRule::parse("   ", "", "", 1).is_none());
+
+        // names match at any depth, anchored patterns only from their folder
+        assert!(rule("*.log").matches("a/b/debug.log", false));
+        assert!(!rule("*.log").matches("a/log", false));
+        assert!(rule("/build").matches("build", true));
+        assert!(!rule("/build").matches("src/build", true));
+        assert!(rule("doc/*.txt").matches("doc/notes.txt", false));
+        assert!(!rule("doc/*.txt").matches("doc/sub/notes.txt", false));
+
+        // `**` matches any number of folders
+        assert!(rule("**/logs").matches("logs", true));
+        assert!(rule("**/logs").matches("a/b/logs", true));
+        assert!(rule("a/**/b").matches("a/b", true));
+        assert!(rule("a/**/b").matches("a/x/y/b", true));
+        assert!(rule("a/**").matches("a/x", false));
+        assert!(!rule("a/**").matches("a", true));
+
+        // directory only patterns, classes and escapes
+        assert!(rule("target/").matches("target", true));
+        assert!(!rule("target/").matches("target", false));
+        assert!(rule("file[0-9].txt").matches("file7.txt", false));
+        assert!(!rule("file[!0-9].txt").matches("file7.txt", false));
+        assert!(rule("\\#name").matches("#name", false));
+        assert!(rule("?.rs").matches("a.rs", false));
+        assert!(!rule("?.rs").matches("ab.rs", false));
+
+        // patterns in a folder's ignore file only match inside it
+        let nested = IgnoreRule::parse("*.tmp", "src", "src/.dvcsignore", 3).unwrap();
+        assert!(nested.matches("src/a/b.tmp", false));
+        assert!(!nested.matches("b.tmp", false));
+        assert_eq!(nested.to_string(), "src/.dvcsignore:3:*.tmp");
+    }
+
+    #[test]
+    fn test_ignore_files() {
+        let root = std::env::temp_dir().join("dvcs_ignore_files");
+        let _ = fs::remove_dir_all(&root);
+        let dvcs_hidden = root.join(".dvcs_hidden");
+        fs::create_dir_all(dvcs_hidden.join("info")).unwrap();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join(".dvcsignore"), "*.log\n!keep.log\ntarget/\n").unwrap();
+        fs::write(root.join("src").join(".dvcsignore"), "!debug.log\n*.tmp\n").unwrap();
+        fs::write(dvcs_hidden.join("info").join("exclude"), "secret.txt\n").unwrap();
+        let global = root.join("global_ignore");
+        fs::write(&global, "*.swp\n*.tmp\n").unwrap();
+
+        let mut ignore = Ignore::with_global(
+            dvcs_hidden.to_str().unwrap(),
+            root.to_str().unwrap(),
+            Some(global),
+        )
+        .unwrap();
+        assert!(ignore.is_ignored("debug.log", false).unwrap());
+        assert!(!ignore.is_ignored("keep.log", false).unwrap());
+        assert!(ignore.is_ignored("secret.txt", false).unwrap());
+        assert!(ignore.is_ignored("a.swp", false).unwrap());
+        assert!(!ignore.is_ignored("main.rs", false).unwrap());
+
+        // the deeper file wins, and the rule that matched is reported
+        assert!(!ignore.is_ignored("src/debug.log", false).unwrap());
+        let rule = ignore.matching_rule("src/a.tmp", false).unwrap().unwrap();
+        assert_eq!(rule.to_string(), "src/.dvcsignore:2:*.tmp");
+
+        // files in an ignored folder can't be re-included
+        assert!(ignore.is_ignored("target/keep.log", false).unwrap());
+        let rule = ignore
+            .matching_rule("target/keep.log", false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(rule.to_string(), ".dvcsignore:3:target/");
+    }
+}
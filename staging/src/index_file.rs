@@ -0,0 +1,321 @@
+use crate::staging_storage::{FileStat, StagedComparison, StagedData};
+use dvcs_error::DvcsError;
+use std::collections::HashMap;
+use storage_hiding::repository_storage::create_sha_1_hex;
+
+/// Name of the binary index file in the hidden folder
+pub const INDEX_FILE_NAME: &str = "index";
+
+/// Name of the JSON index written by older versions, it is migrated to the binary index the first time it is read
+pub const LEGACY_INDEX_FILE_NAME: &str = "index.json";
+
+/// First bytes of every index file
+pub const INDEX_MAGIC: &[u8; 4] = b"DVCI";
+
+/// Version of the index format written by `encode`
+pub const INDEX_VERSION: u32 = 1;
+
+/// Length of a binary sha1 hash, used for the ids and the trailing checksum
+const HASH_LENGTH: usize = 20;
+
+// * Slots of an entry that are present
+const WORKING_DIRECTORY: u8 = 1;
+const STAGING: u8 = 2;
+const REPOSITORY_VERSION: u8 = 4;
+// * Not a slot, the path is left unmerged by a merge
+const UNMERGED: u8 = 8;
+
+// * Flags of a slot
+const READ_ONLY: u8 = 1;
+const IS_FILE: u8 = 2;
+const EXECUTABLE: u8 = 4;
+
+/// Serializes the index as `<magic> <version> <entry count>`, the entries sorted by path and a sha1 checksum of everything before it
+///
+/// Every entry is its path (length prefixed), a byte telling which slots follow and if the path is unmerged, then for each slot (working directory, staging, repository version) a flags byte, the binary id and the stat data. Numbers are big endian
+pub fn encode(index: &HashMap<String, StagedComparison>) -> Result<Vec<u8>, DvcsError> {
+    let mut keys: Vec<&String> = index.keys().collect();
+    keys.sort();
+
+    let mut bytes = INDEX_MAGIC.to_vec();
+    bytes.extend_from_slice(&INDEX_VERSION.to_be_bytes());
+    bytes.extend_from_slice(&(keys.len() as u32).to_be_bytes());
+    for key in keys {
+        let comparison = &index[key];
+        bytes.extend_from_slice(&(key.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(key.as_bytes());
+        let slots = [
+            (WORKING_DIRECTORY, &comparison.working_directory),
+            (STAGING, &comparison.staging),
+            (REPOSITORY_VERSION, &comparison.repository_version),
+        ];
+        let unmerged = match comparison.unmerged {
+            true => UNMERGED,
+            false => 0,
+        };
+        bytes.push(
+            slots
+                .iter()
+                .filter(|(_, data)| data.is_some())
+                .fold(unmerged, |present, (slot, _)| present | slot),
+        );
+        for data in slots.iter().filter_map(|(_, data)| data.as_ref()) {
+            encode_data(&mut bytes, key, data)?;
+        }
+    }
+    let checksum = hex::decode(create_sha_1_hex(&bytes))
+        .map_err(|e| DvcsError::IndexCorrupt(e.to_string()))?;
+    bytes.extend_from_slice(&checksum);
+    Ok(bytes)
+}
+
+/// Reads an index written by `encode`, fails if the checksum doesn't match or the file is cut short
+pub fn decode(bytes: &[u8]) -> Result<HashMap<String, StagedComparison>, DvcsError> {
+    if bytes.len() < INDEX_MAGIC.len() + 8 + HASH_LENGTH {
+        return Err(corrupt("the file is too short"));
+    }
+    let (contents, checksum) = bytes.split_at(bytes.len() - HASH_LENGTH);
+    if create_sha_1_hex(contents) != hex::encode(checksum) {
+        return Err(corrupt("the checksum doesn't match"));
+    }
+
+    let mut reader = Reader { bytes: contents };
+    if reader.take(INDEX_MAGIC.len())? != INDEX_MAGIC {
+        return Err(corrupt("it is not an index file"));
+    }
+    let version = reader.u32()?;
+    if version != INDEX_VERSION {
+        return Err(corrupt(&format!("version {} is not supported", version)));
+    }
+    let count = reader.u32()?;
+
+    let mut index = HashMap::new();
+    let mut previous: Option<String> = None;
+    for _ in 0..count {
+        let length = reader.u32()? as usize;
+        let key = String::from_utf8(reader.take(length)?.to_vec())
+            .map_err(|_| corrupt("a path is not valid UTF-8"))?;
+        // * Sorted paths also rule out duplicates
+        if previous.as_ref().is_some_and(|previous| *previous >= key) {
+            return Err(corrupt("the entries are not sorted"));
+        }
+        let present = reader.u8()?;
+        let mut read_slot = |slot: u8| -> Result<Option<StagedData>, DvcsError> {
+            match present & slot {
+                0 => Ok(None),
+                _ => decode_data(&mut reader, &key).map(Some),
+            }
+        };
+        let comparison = StagedComparison {
+            working_directory: read_slot(WORKING_DIRECTORY)?,
+            staging: read_slot(STAGING)?,
+            repository_version: read_slot(REPOSITORY_VERSION)?,
+            unmerged: present & UNMERGED != 0,
+        };
+        index.insert(key.clone(), comparison);
+        previous = Some(key);
+    }
+    if !reader.bytes.is_empty() {
+        return Err(corrupt("there is data after the last entry"));
+    }
+    Ok(index)
+}
+
+/// Private helper function writing one slot of an entry
+fn encode_data(bytes: &mut Vec<u8>, key: &str, data: &StagedData) -> Result<(), DvcsError> {
+    let mut flags = 0;
+    if data.read_only {
+        flags |= READ_ONLY;
+    }
+    if data.is_file {
+        flags |= IS_FILE;
+    }
+    if data.executable {
+        flags |= EXECUTABLE;
+    }
+    bytes.push(flags);
+    match hex::decode(&data.sha1) {
+        Ok(id) if id.len() == HASH_LENGTH => bytes.extend_from_slice(&id),
+        _ => {
+            return Err(DvcsError::IndexCorrupt(format!(
+                "{} has an invalid id {}",
+                key, data.sha1
+            )))
+        }
+    }
+    let stat = &data.stat;
+    bytes.extend_from_slice(&stat.mtime_seconds.to_be_bytes());
+    bytes.extend_from_slice(&stat.mtime_nanoseconds.to_be_bytes());
+    bytes.extend_from_slice(&stat.ctime_seconds.to_be_bytes());
+    bytes.extend_from_slice(&stat.ctime_nanoseconds.to_be_bytes());
+    bytes.extend_from_slice(&stat.size.to_be_bytes());
+    bytes.extend_from_slice(&stat.inode.to_be_bytes());
+    bytes.extend_from_slice(&stat.device.to_be_bytes());
+    Ok(())
+}
+
+/// Private helper function reading one slot of an entry, the path isn't written again for every slot
+fn decode_data(reader: &mut Reader, key: &str) -> Result<StagedData, DvcsError> {
+    let flags = reader.u8()?;
+    let sha1 = hex::encode(reader.take(HASH_LENGTH)?);
+    let stat = FileStat {
+        mtime_seconds: reader.u64()? as i64,
+        mtime_nanoseconds: reader.u32()?,
+        ctime_seconds: reader.u64()? as i64,
+        ctime_nanoseconds: reader.u32()?,
+        size: reader.u64()?,
+        inode: reader.u64()?,
+        device: reader.u64()?,
+    };
+    Ok(StagedData {
+        path: key.to_string(),
+        read_only: flags & READ_ONLY != 0,
+        is_file: flags & IS_FILE != 0,
+        executable: flags & EXECUTABLE != 0,
+        sha1,
+        stat,
+    })
+}
+
+/// Private helper function for an index file that can't be read
+fn corrupt(reason: &str) -> DvcsError {
+    DvcsError::IndexCorrupt(format!("Index file can't be read, {}", reason))
+}
+
+/// Private helper reading big endian numbers from the front of the index
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], DvcsError> {
+        if self.bytes.len() < length {
+            return Err(corrupt("the file is cut short"));
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, DvcsError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, DvcsError> {
+        let mut number = [0; 4];
+        number.copy_from_slice(self.take(4)?);
+        Ok(u32::from_be_bytes(number))
+    }
+
+    fn u64(&mut self) -> Result<u64, DvcsError> {
+        let mut number = [0; 8];
+        number.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(number))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(key: &str, contents: &[u8], is_file: bool) -> StagedData {
+        StagedData {
+            path: key.to_string(),
+            read_only: !is_file,
+            is_file,
+            executable: is_file,
+            sha1: create_sha_1_hex(contents),
+            stat: FileStat {
+                mtime_seconds: -5,
+                mtime_nanoseconds: 7,
+                ctime_seconds: 1_700_000_000,
+                ctime_nanoseconds: 999_999_999,
+                size: contents.len() as u64,
+                inode: u64::MAX,
+                device: 3,
+            },
+        }
+    }
+
+    #[test]
+    // * Encoding and decoding gives back the same index, whatever order the entries were in
+    fn test_round_trip() {
+        let mut index = HashMap::new();
+        index.insert(
+            "folder 1/b.txt".to_string(),
+            StagedComparison {
+                working_directory: Some(data("folder 1/b.txt", b"new", true)),
+                staging: Some(data("folder 1/b.txt", b"old", true)),
+                repository_version: None,
+                unmerged: false,
+            },
+        );
+        index.insert(
+            "ä.txt".to_string(),
+            StagedComparison {
+                working_directory: None,
+                staging: None,
+                repository_version: Some(data("ä.txt", b"", false)),
+                unmerged: true,
+            },
+        );
+        let bytes = encode(&index).unwrap();
+        assert_eq!(&bytes[..4], INDEX_MAGIC);
+        assert_eq!(bytes[4..12], [0, 0, 0, 1, 0, 0, 0, 2]);
+
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(
+            format!("{:?}", decoded["ä.txt"]),
+            format!("{:?}", index["ä.txt"])
+        );
+        assert_eq!(
+            format!("{:?}", decoded["folder 1/b.txt"]),
+            format!("{:?}", index["folder 1/b.txt"])
+        );
+        assert!(decode(&encode(&HashMap::new()).unwrap())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    // * Damaged, cut short and unknown files are reported instead of read as an empty index
+    fn test_corrupt_index() {
+        let mut index = HashMap::new();
+        index.insert(
+            "file.txt".to_string(),
+            StagedComparison {
+                staging: Some(data("file.txt", b"file", true)),
+                ..Default::default()
+            },
+        );
+        let bytes = encode(&index).unwrap();
+
+        let mut flipped = bytes.clone();
+        flipped[20] ^= 1;
+        assert!(matches!(decode(&flipped), Err(DvcsError::IndexCorrupt(_))));
+        assert!(matches!(
+            decode(&bytes[..bytes.len() - 1]),
+            Err(DvcsError::IndexCorrupt(_))
+        ));
+        assert!(matches!(decode(b""), Err(DvcsError::IndexCorrupt(_))));
+        assert!(matches!(decode(b"{}"), Err(DvcsError::IndexCorrupt(_))));
+
+        // * A newer version is refused even with a valid checksum
+        let mut newer = bytes[..bytes.len() - HASH_LENGTH].to_vec();
+        newer[7] = 2;
+        newer.extend(hex::decode(create_sha_1_hex(&newer)).unwrap());
+        assert!(
+            matches!(decode(&newer), Err(DvcsError::IndexCorrupt(reason)) if reason.contains("version 2"))
+        );
+
+        // * Ids are stored binary so they have to be sha1 hashes
+        index
+            .get_mut("file.txt")
+            .unwrap()
+            .staging
+            .as_mut()
+            .unwrap()
+            .sha1 = "cached".to_string();
+        assert!(encode(&index).is_err());
+    }
+}
@@ -1,33 +1,187 @@
+pub mod ignore;
+pub mod index_file;
+
 pub mod staging_storage {
-    use serde::{Deserialize, Serialize};
-    use serde_json;
-    use sha1::{Digest, Sha1};
-    use std::borrow::Borrow;
+    use crate::ignore::{Ignore, IgnoreRule};
+    use crate::index_file::{self, INDEX_FILE_NAME, LEGACY_INDEX_FILE_NAME};
+    use dvcs_error::DvcsError;
+    use serde::{Deserialize, Deserializer, Serialize};
     use std::collections::HashMap;
+    use std::fs;
     use std::fs::Metadata;
-    use std::fs::{self, File};
-    use std::io::{self, Read};
-    use std::path::Path;
-    use std::time::SystemTime;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::time::UNIX_EPOCH;
+    use storage_hiding::repository_lock::RepositoryLock;
+    use storage_hiding::repository_storage::{
+        create_sha_1_hex, create_sha_1_hex_from_file, write_atomic, RepositoryStorage,
+    };
+    use storage_hiding::tree_objects::{EntryMode, Tree};
 
     /// Structure that keeps relevant metadata and the sha1 hash of the file/folder
     #[derive(Debug, Serialize, Deserialize, Clone)]
     pub struct StagedData {
         pub path: String,
-        // pub modified: String,
-        // pub created: String,
-        // pub accessed: String,
-        pub read_only: String,
-        pub is_file: String,
+        #[serde(deserialize_with = "legacy_flag")]
+        pub read_only: bool,
+        #[serde(deserialize_with = "legacy_flag")]
+        pub is_file: bool,
+        /// Set for files with an executable bit, only recorded on unix
+        #[serde(default)]
+        pub executable: bool,
         pub sha1: String,
+        /// Stat data when the file was hashed, indexes written before it was recorded get the default and are hashed again
+        #[serde(default)]
+        pub stat: FileStat,
+    }
+
+    /// Private helper function reading the `"true"`/`"false"` strings of flags in index.json files written by older versions
+    fn legacy_flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Flag {
+            Bool(bool),
+            Text(String),
+        }
+        Ok(match Flag::deserialize(deserializer)? {
+            Flag::Bool(flag) => flag,
+            Flag::Text(text) => text == "true",
+        })
+    }
+
+    /// Checks if a file has an executable bit set, files are never executable on other platforms
+    #[cfg(unix)]
+    fn is_executable(metadata: &Metadata) -> bool {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+    }
+
+    /// Checks if a file has an executable bit set, files are never executable on other platforms
+    #[cfg(not(unix))]
+    fn is_executable(_metadata: &Metadata) -> bool {
+        false
+    }
+
+    /// Sets or clears the executable bits of a file (for everyone who can read it), does nothing on other platforms
+    #[cfg(unix)]
+    pub fn set_executable(file_path: &str, executable: bool) -> Result<(), DvcsError> {
+        use std::os::unix::fs::PermissionsExt;
+        let mut permissions = fs::metadata(file_path)
+            .map_err(|e| DvcsError::io(file_path, e))?
+            .permissions();
+        let mode = permissions.mode();
+        let mode = match executable {
+            true => mode | (mode & 0o444) >> 2,
+            false => mode & !0o111,
+        };
+        if mode == permissions.mode() {
+            return Ok(());
+        }
+        permissions.set_mode(mode);
+        fs::set_permissions(file_path, permissions).map_err(|e| DvcsError::io(file_path, e))
+    }
+
+    /// Sets or clears the executable bits of a file (for everyone who can read it), does nothing on other platforms
+    #[cfg(not(unix))]
+    pub fn set_executable(_file_path: &str, _executable: bool) -> Result<(), DvcsError> {
+        Ok(())
+    }
+
+    /// Stat data of a file, if none of it changed the contents are assumed unchanged and the file isn't hashed again
+    #[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct FileStat {
+        pub mtime_seconds: i64,
+        pub mtime_nanoseconds: u32,
+        pub ctime_seconds: i64,
+        pub ctime_nanoseconds: u32,
+        pub size: u64,
+        pub inode: u64,
+        pub device: u64,
+    }
+
+    impl FileStat {
+        /// Gets the stat data from the metadata of a file
+        #[cfg(unix)]
+        pub fn from_metadata(metadata: &Metadata) -> FileStat {
+            use std::os::unix::fs::MetadataExt;
+            FileStat {
+                mtime_seconds: metadata.mtime(),
+                mtime_nanoseconds: metadata.mtime_nsec() as u32,
+                ctime_seconds: metadata.ctime(),
+                ctime_nanoseconds: metadata.ctime_nsec() as u32,
+                size: metadata.size(),
+                inode: metadata.ino(),
+                device: metadata.dev(),
+            }
+        }
+
+        /// Gets the stat data from the metadata of a file, there is no inode or device so only the times and size are compared
+        #[cfg(not(unix))]
+        pub fn from_metadata(metadata: &Metadata) -> FileStat {
+            let since_epoch = |time: std::io::Result<std::time::SystemTime>| {
+                time.ok()
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .unwrap_or_default()
+            };
+            let modified = since_epoch(metadata.modified());
+            let created = since_epoch(metadata.created());
+            FileStat {
+                mtime_seconds: modified.as_secs() as i64,
+                mtime_nanoseconds: modified.subsec_nanos(),
+                ctime_seconds: created.as_secs() as i64,
+                ctime_nanoseconds: created.subsec_nanos(),
+                size: metadata.len(),
+                inode: 0,
+                device: 0,
+            }
+        }
+
+        /// Private helper function getting the modification time in an order that can be compared
+        fn mtime(&self) -> (i64, u32) {
+            (self.mtime_seconds, self.mtime_nanoseconds)
+        }
+    }
+
+    /// Area of an index entry, which version of a path its data describes
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum StagingArea {
+        /// The file as it is on disk
+        WorkingDirectory,
+        /// The version that will be committed, its contents are in the object database
+        Staging,
+        /// The version of the checked out commit (HEAD)
+        RepositoryVersion,
     }
 
     /// Structure that holds metadata for working directory and repository snapshot as well as the files that are being staged. This structure can be used to compare the versions of the file at different snapshots
-    #[derive(Debug, Serialize, Deserialize, Clone)]
+    #[derive(Debug, Serialize, Deserialize, Clone, Default)]
     pub struct StagedComparison {
         pub working_directory: Option<StagedData>,
         pub staging: Option<StagedData>,
         pub repository_version: Option<StagedData>,
+        /// Set by a merge that couldn't merge the path, it is cleared once the path is staged or removed again
+        #[serde(default)]
+        pub unmerged: bool,
+    }
+
+    impl StagedComparison {
+        /// Gets the data of one area
+        pub fn area(&self, area: StagingArea) -> Option<&StagedData> {
+            match area {
+                StagingArea::WorkingDirectory => self.working_directory.as_ref(),
+                StagingArea::Staging => self.staging.as_ref(),
+                StagingArea::RepositoryVersion => self.repository_version.as_ref(),
+            }
+        }
+
+        /// Gets the data of one area to replace it
+        pub fn area_mut(&mut self, area: StagingArea) -> &mut Option<StagedData> {
+            match area {
+                StagingArea::WorkingDirectory => &mut self.working_directory,
+                StagingArea::Staging => &mut self.staging,
+                StagingArea::RepositoryVersion => &mut self.repository_version,
+            }
+        }
     }
 
     /// Staging storage hiding module
@@ -45,6 +199,11 @@ pub mod staging_storage {
         HashMap<Path, Metadata of working directory, meta data of staged files, and meta data of current version from repository>
         */
         index: HashMap<String, StagedComparison>,
+        /**
+        Modification time of the index file when it was last read or written, files changed at or after it can't be trusted by their stat data (racy clean)
+        */
+        #[serde(skip)]
+        index_mtime: (i64, u32),
     }
 
     impl Staging {
@@ -56,41 +215,86 @@ pub mod staging_storage {
         /// * `working_directory` - A string that holds the path to the working directory folder
         ///
         /// ```
-        pub fn new(dvcs_hidden: &str, working_directory: &str) -> Result<Staging, String> {
+        pub fn new(dvcs_hidden: &str, working_directory: &str) -> Result<Staging, DvcsError> {
             // * Check if index file exists, otherwise create it
             match Path::new(&dvcs_hidden).try_exists() {
                 // * Repository exist has error
-                Err(_) => return Err("Could not read DVCS hidden file".to_string()),
-                Ok(false) => return Err("Could not find working directory".to_string()),
+                Err(e) => Err(DvcsError::io(dvcs_hidden, e)),
+                Ok(false) => Err(DvcsError::NotARepository(PathBuf::from(dvcs_hidden))),
                 Ok(true) => {
                     // * Creating index file if it doesn't exist already
-                    let index_path = &(dvcs_hidden.to_owned() + "/index.json");
-                    match Path::new(&index_path).try_exists() {
-                        Err(_) => return Err("Could not read DVCS hidden file".to_string()),
-                        // * Create new index file if not created already
-                        Ok(false) => {
-                            let update = Self::recreate_index_file(dvcs_hidden);
-                            if update.is_err() {
-                                return Err(update.unwrap_err());
+                    let index_path = Self::index_path(dvcs_hidden);
+                    let exists = Path::new(&index_path)
+                        .try_exists()
+                        .map_err(|e| DvcsError::io(&index_path, e))?;
+
+                    // * Read index file and load the staging index structure
+                    let mut staging = Staging {
+                        dvcs_hidden: dvcs_hidden.to_string(),
+                        working_directory: working_directory.to_string(),
+                        index: Self::read_from_staging_file(dvcs_hidden)?,
+                        index_mtime: Self::index_mtime(dvcs_hidden),
+                    };
+                    // * Create new index file if not created already, an index.json of an older version is migrated to it
+                    if !exists {
+                        staging.migrate_legacy_entries()?;
+                        staging.write_to_staging_file()?;
+                        let legacy_path = dvcs_hidden.to_owned() + "/" + LEGACY_INDEX_FILE_NAME;
+                        match fs::remove_file(&legacy_path) {
+                            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                                return Err(DvcsError::io(&legacy_path, e));
                             }
+                            _ => {}
                         }
-                        // * Index file exists, ignore
-                        _ => {}
                     }
+                    Ok(staging)
+                }
+            }
+        }
 
-                    // * Read index file and load the staging index structure
-                    // * Returning successful staging structure if read succussfully, otherwise returns new staging
-                    return Self::read_from_staging_file(dvcs_hidden.to_string())
-                        .and_then(|retrieved_file| {
-                            return Ok(Staging {
-                                dvcs_hidden: dvcs_hidden.to_string(),
-                                working_directory: working_directory.to_string(),
-                                index: retrieved_file,
-                            });
-                        })
-                        .or_else(|err| Err(err));
+        /// Private helper function moving the entries read from the index.json of an older version to the keys and objects the index uses now
+        ///
+        /// Older versions keyed entries by the path as it was typed and never stored the staged contents. Every entry is keyed again (see `index_key`) and loses its stat data so it is hashed again, a staged file is stored from the working directory if it still has the staged contents and is no longer staged otherwise
+        fn migrate_legacy_entries(&mut self) -> Result<(), DvcsError> {
+            if self.index.is_empty() {
+                return Ok(());
+            }
+            let storage = RepositoryStorage::new(&self.dvcs_hidden)?;
+            for (path, mut comparison) in std::mem::take(&mut self.index) {
+                // * Paths that were never inside the working directory can't be tracked
+                let key = match self.index_key(&path) {
+                    Ok(key) => key,
+                    Err(_) => continue,
+                };
+                for area in [
+                    StagingArea::WorkingDirectory,
+                    StagingArea::Staging,
+                    StagingArea::RepositoryVersion,
+                ] {
+                    if let Some(data) = comparison.area_mut(area) {
+                        data.path = key.clone();
+                        data.stat = FileStat::default();
+                    }
                 }
-            };
+                let missing = comparison
+                    .staging
+                    .as_ref()
+                    .filter(|staged| staged.is_file && !storage.exists(&staged.sha1))
+                    .map(|staged| staged.sha1.clone());
+                if let Some(sha1) = missing {
+                    let working_path = self.working_path(&key);
+                    let unchanged = Path::new(&working_path).is_file()
+                        && create_sha_1_hex_from_file(&working_path)? == sha1;
+                    match unchanged {
+                        true => {
+                            storage.put_file(&working_path)?;
+                        }
+                        false => comparison.staging = None,
+                    }
+                }
+                self.index.insert(key, comparison);
+            }
+            Ok(())
         }
 
         /*         pub fn clone(&self) -> Staging {
@@ -100,102 +304,179 @@ pub mod staging_storage {
             index: self.index.clone(),}
         }*/
 
-        pub fn get_index(self) -> HashMap<String, StagedComparison> {
-            self.index
+        /// Gets the index, keys are paths relative to the working directory
+        pub fn get_index(&self) -> &HashMap<String, StagedComparison> {
+            &self.index
         }
 
-        /// Updates the status of working directory and repository files that are previously added in order to get most recent snapshot of the tracked file - will get most recent metadata/or of both the working directory and staged. If a file is removed from the working directory, will replace that value to None
-        pub fn update_staged_files(&mut self) -> Result<(), String> {
-            let values: Vec<StagedComparison> = self.index.clone().into_values().collect();
-            let update: (Result<(), String>, Option<&StagedComparison>) =
-                values.iter().fold((Ok(()), None), |acc, val| {
-                    let staging = val.staging.as_ref();
-                    // * Will update staged data and working directory (file removed will replace working directory only with None, thus you can check if a staged file has been deleted)
-                    if staging.is_some() {
-                        if self
-                            .add_staged_data(&staging.unwrap().path, 1, true)
-                            .is_ok()
-                        //                            && self
-                        //                                .add_staged_data(&staging.unwrap().path, 0, false)
-                        //                                .is_ok()
-                        {
-                            return (self.write_to_staging_file(), Some(val));
-                        }
-                    }
-                    return (acc.0, Some(val));
-                });
+        /// Gets the path to the working directory
+        pub fn get_working_directory(&self) -> &str {
+            &self.working_directory
+        }
+
+        /// Hashes the current contents of a file in the working directory, None if the file is missing or a folder
+        pub fn get_working_file_hash(&self, key: &str) -> Option<String> {
+            let file_path = self.working_path(key);
+            if !Path::new(&file_path).is_file() {
+                return None;
+            }
+            create_sha_1_hex_from_file(&file_path).ok()
+        }
+
+        /// Records the files written or deleted by a checkout, files on disk become the working directory, staged and repository version while deleted files are dropped from the index
+        ///
+        /// A checkout leaves any merge in progress, so no path stays unmerged
+        pub fn set_checked_out_files(&mut self, keys: &[String]) -> Result<(), DvcsError> {
+            for comparison in self.index.values_mut() {
+                comparison.unmerged = false;
+            }
+            for key in keys {
+                if Path::new(&self.working_path(key)).is_file() {
+                    self.add_staged_data(key, StagingArea::Staging, false)?;
+                    self.add_staged_data(key, StagingArea::WorkingDirectory, false)?;
+                    self.add_staged_data(key, StagingArea::RepositoryVersion, false)?;
+                } else {
+                    self.index.remove(key);
+                }
+            }
             self.write_to_staging_file()
         }
 
-        /// Function that deletes index file and creates new one to reduce writing conflict
-        pub fn recreate_index_file(dvcs_hidden: &str) -> Result<(), String> {
-            match Path::new(&dvcs_hidden).try_exists() {
-                // * Repository cannot be found with given dvcs hidden path
-                Err(_) => return Err("Could not find working directory files".to_string()),
-                Ok(_) => {
-                    // * Creating index file if it doesn't exist already
-                    let index_path = &(dvcs_hidden.to_owned() + "/index.json");
-                    match Path::new(index_path).try_exists() {
-                        Err(_) => return Err("Could not create index file".to_string()),
-                        // * If there is an index file, remove it
-                        Ok(true) => {
-                            if fs::remove_file(index_path).is_err() {
-                                return Err("Error creating new index file".to_string());
-                            }
-                        }
-                        // * If there isn't an index file, ignore
-                        Ok(false) => {}
-                    };
-                    // * Create new index file
-                    if File::create(index_path).is_err() {
-                        return Err("Could not create index file".to_string());
-                    }
-                    Ok(())
+        /// Records a commit of everything staged, the staged version of every file becomes its repository version and files removed from staging are dropped from the repository version
+        pub fn promote_staged_files(&mut self) -> Result<(), DvcsError> {
+            for comparison in self.index.values_mut() {
+                comparison.repository_version = comparison.staging.clone();
+            }
+            // * Entries with nothing left in them are forgotten
+            self.index.retain(|_, comparison| {
+                comparison.working_directory.is_some()
+                    || comparison.staging.is_some()
+                    || comparison.repository_version.is_some()
+            });
+            self.write_to_staging_file()
+        }
+
+        /// Marks paths a merge couldn't merge, they have to be staged or removed again before the merge can be committed
+        pub fn mark_unmerged(&mut self, keys: &[String]) -> Result<(), DvcsError> {
+            for key in keys {
+                self.index.entry(key.clone()).or_default().unmerged = true;
+            }
+            self.write_to_staging_file()
+        }
+
+        /// Lists the paths a merge couldn't merge that weren't staged or removed since, sorted by key
+        pub fn unmerged_paths(&self) -> Vec<String> {
+            let mut paths: Vec<String> = self
+                .index
+                .iter()
+                .filter(|(_, comparison)| comparison.unmerged)
+                .map(|(key, _)| key.clone())
+                .collect();
+            paths.sort();
+            paths
+        }
+
+        /// Updates the status of working directory and repository files that are previously added in order to get most recent snapshot of the tracked file - will get most recent metadata/or of both the working directory and staged. If a file is removed from the working directory, will replace that value to None
+        pub fn update_staged_files(&mut self) -> Result<(), DvcsError> {
+            let staged_keys: Vec<String> = self
+                .index
+                .iter()
+                .filter(|(_, comparison)| {
+                    comparison.staging.is_some() || comparison.repository_version.is_some()
+                })
+                .map(|(key, _)| key.clone())
+                .collect();
+            // * Will update working directory (file removed will replace working directory only with None, thus you can check if a staged file has been deleted)
+            for key in staged_keys {
+                // * Files with the stat data they had when they were last hashed aren't hashed again
+                if let Some(unchanged) = self.unchanged_data(&key) {
+                    self.index.entry(key).or_default().working_directory = Some(unchanged);
+                    continue;
                 }
+                self.add_staged_data(&key, StagingArea::WorkingDirectory, true)?;
             }
+            self.write_to_staging_file()
         }
 
-        /// Add file to staging structure
-        pub fn add_file_to_staging(&mut self, file_path: &str) -> Result<(), String> {
-            // * Path to file
-            // * Add data given metadata, filepath
-            match self.add_staged_data(file_path, 0, false) {
-                Ok(_) => {
-                    // * Check to see if writing to file was a success
-                    self.write_to_staging_file()
+        /// Private helper function getting the last hashed data of a file if its stat data didn't change since, None if it has to be hashed again
+        fn unchanged_data(&self, key: &str) -> Option<StagedData> {
+            let metadata = fs::metadata(self.working_path(key)).ok()?;
+            let stat = FileStat::from_metadata(&metadata);
+            // * Racy clean: a file changed in the same tick the index was written keeps its stat data, only files older than the index can be trusted
+            if stat.mtime() >= self.index_mtime {
+                return None;
+            }
+            let comparison = self.index.get(key)?;
+            [&comparison.working_directory, &comparison.staging]
+                .into_iter()
+                .flatten()
+                .find(|data| data.stat == stat && data.is_file == metadata.is_file())
+                .cloned()
+        }
+
+        /// Private helper function getting the modification time of the index file, files are never trusted by their stat data if it can't be read
+        fn index_mtime(dvcs_hidden: &str) -> (i64, u32) {
+            fs::metadata(Self::index_path(dvcs_hidden))
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or((0, 0), |since_epoch| {
+                    (since_epoch.as_secs() as i64, since_epoch.subsec_nanos())
+                })
+        }
+
+        /// Writes the tree of everything that is staged to the object database and returns the id of the root tree
+        pub fn write_tree(&self, storage: &RepositoryStorage) -> Result<String, DvcsError> {
+            let mut files = vec![];
+            for (key, comparison) in &self.index {
+                if let Some(staged) = &comparison.staging {
+                    // * Folders are implied by the paths of the files inside them
+                    if !staged.is_file {
+                        continue;
+                    }
+                    if !storage.exists(&staged.sha1) {
+                        return Err(DvcsError::ObjectMissing(staged.sha1.clone()));
+                    }
+                    let mode = match staged.executable {
+                        true => EntryMode::Executable,
+                        false => EntryMode::File,
+                    };
+                    files.push((key.clone(), mode, staged.sha1.clone()));
                 }
-                Err(_) => Err("File does not exist to add".to_string()),
             }
+            Tree::write_from_paths(storage, &files)
         }
 
-        /// Add file(s) to staging structure starting from directory
-        pub fn add_directory_to_staging(
-            &mut self,
-            kind: i32,
-            directory_path: &str,
-        ) -> Result<(), String> {
-            if let Ok(attributes) = fs::metadata(directory_path) {
-                if attributes.is_dir() {
-                    self.recursive_file_traversal(directory_path, kind);
-                    return self.write_to_staging_file();
-                } else if attributes.is_file() {
-                    return Err("Path must be to a directory".to_string());
+        /// Stages a file, or everything in a folder that isn't ignored, storing the contents in the object database so the staged version can be retrieved later
+        pub fn stage_path(&mut self, file_path: &str) -> Result<(), DvcsError> {
+            match fs::metadata(file_path) {
+                Ok(attributes) if attributes.is_dir() => {
+                    let mut ignore = self.ignore()?;
+                    self.recursive_file_traversal(file_path, StagingArea::Staging, &mut ignore)?;
                 }
+                Ok(_) => {
+                    let key = self.index_key(file_path)?;
+                    self.add_staged_data(&key, StagingArea::Staging, false)?;
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    return Err(DvcsError::PathNotFound(PathBuf::from(file_path)))
+                }
+                Err(e) => return Err(DvcsError::io(file_path, e)),
             }
-            Err("Directory path does not exist".to_string())
+            self.write_to_staging_file()
         }
 
         /// Remove file from staging structure
-        pub fn remove_file_from_staging(&mut self, file_path: &str) -> Result<(), String> {
-            match self.get_file_from_staging(file_path).to_owned() {
-                Ok(_) => {
-                    self.index.entry(file_path.to_string()).and_modify(|e| {
-                        e.staging = None; // * Save struct in file
-                    });
+        pub fn remove_file_from_staging(&mut self, file_path: &str) -> Result<(), DvcsError> {
+            let key = self.index_key(file_path)?;
+            match self.index.get_mut(&key) {
+                Some(comparison) => {
+                    comparison.staging = None;
+                    comparison.unmerged = false;
+                    // * Save struct in file
                     self.write_to_staging_file()
-                        .or_else(|_| return Err("Cannot find file to remove".to_string()))
                 }
-                Err(_) => Err("Cannot find file to remove".to_string()),
+                None => Err(Self::not_in_index(file_path)),
             }
         }
 
@@ -203,209 +484,234 @@ pub mod staging_storage {
         pub fn get_file_from_staging(
             &mut self,
             file_path: &str,
-        ) -> Result<(&String, &StagedComparison), String> {
-            let file = self.index.get_key_value(file_path);
-            match file {
-                Some(file) => Ok(file),
-                None => Err("No file found in staging index".to_string()),
-            }
+        ) -> Result<(&String, &StagedComparison), DvcsError> {
+            let key = self.index_key(file_path)?;
+            self.index
+                .get_key_value(&key)
+                .ok_or_else(|| Self::not_in_index(file_path))
+        }
+
+        /// Private helper function for paths the index doesn't know about
+        fn not_in_index(file_path: &str) -> DvcsError {
+            DvcsError::InvalidArgument(format!("Path {} is not in the staging index", file_path))
+        }
+
+        /// Records every file of the working directory that isn't ignored as its working directory version
+        pub fn refresh_working_copy(&mut self) -> Result<(), DvcsError> {
+            self.record_snapshot(StagingArea::WorkingDirectory)
+        }
+
+        /// Records every file of the working directory that isn't ignored as its repository version, for a working directory that matches the checked out commit
+        pub fn record_repository_snapshot(&mut self) -> Result<(), DvcsError> {
+            self.record_snapshot(StagingArea::RepositoryVersion)
         }
 
-        /// Sets the current version of the repository as the snapshot version for comparison in the index file
-        pub fn set_staging_snapshot(&mut self, kind: i32) -> Result<(), String> {
-            self.recursive_file_traversal(self.working_directory.clone().as_str(), kind);
+        /// Private helper function recording the whole working directory into one area
+        fn record_snapshot(&mut self, area: StagingArea) -> Result<(), DvcsError> {
+            let mut ignore = self.ignore()?;
+            let working_directory = self.working_directory.clone();
+            self.recursive_file_traversal(&working_directory, area, &mut ignore)?;
             self.write_to_staging_file()
         }
 
-        /// Private helper function to get nano seconds out of SystemTime structure
-        fn get_time_from_metadata(time: io::Result<SystemTime>) -> Result<String, String> {
-            if let Ok(nano_time) = time {
-                return Ok(nano_time
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .expect("File A thinks it was created before Epoch")
-                    .as_nanos()
-                    .to_string());
-            } else {
-                return Err("Could not get time from metadata".to_string());
+        /// Loads the ignore patterns of the working directory (`.dvcsignore` files, the exclude file in the DVCS hidden folder and the global ignore file)
+        pub fn ignore(&self) -> Result<Ignore, DvcsError> {
+            Ignore::new(&self.dvcs_hidden, &self.working_directory)
+        }
+
+        /// Finds the ignore rule deciding if a path is ignored, a negated rule means it was re-included, None if no rule matches
+        pub fn check_ignore(&self, file_path: &str) -> Result<Option<IgnoreRule>, DvcsError> {
+            let key = self.index_key(file_path)?;
+            self.ignore()?
+                .matching_rule(&key, Path::new(&self.working_path(&key)).is_dir())
+        }
+
+        /// Lists the files of the working directory that aren't staged, leaving out ignored files, sorted by key
+        pub fn untracked_files(&self) -> Result<Vec<String>, DvcsError> {
+            let mut ignore = self.ignore()?;
+            let mut untracked = vec![];
+            let mut pending = vec![self.working_directory.clone()];
+            while let Some(directory) = pending.pop() {
+                for (file_path, key, is_dir) in self.visible_entries(&directory, &mut ignore)? {
+                    if is_dir {
+                        pending.push(file_path);
+                        continue;
+                    }
+                    // * Files removed from staging are untracked again even if HEAD still has them
+                    let tracked = self
+                        .index
+                        .get(&key)
+                        .is_some_and(|comparison| comparison.staging.is_some());
+                    if !tracked {
+                        untracked.push(key);
+                    }
+                }
             }
+            untracked.sort();
+            Ok(untracked)
         }
 
         /// Private helper function to get all useful metadata from file for indexing
-        fn create_staged_data_struct(
-            metadata: Metadata,
-            sha1: String,
-            path: String,
-        ) -> Result<StagedData, String> {
-            return Ok(StagedData {
-                read_only: metadata.permissions().readonly().to_string(),
-                is_file: metadata.is_file().to_string(),
-                // modified,
-                // created,
-                // accessed,
+        fn create_staged_data_struct(metadata: Metadata, sha1: String, path: String) -> StagedData {
+            StagedData {
+                stat: FileStat::from_metadata(&metadata),
+                read_only: metadata.permissions().readonly(),
+                is_file: metadata.is_file(),
+                executable: is_executable(&metadata),
                 sha1,
                 path,
-            });
-            // let modified = Self::get_time_from_metadata(metadata.modified());
-            // let created = Self::get_time_from_metadata(metadata.created());
-            // let accessed = Self::get_time_from_metadata(metadata.accessed());
-            // if let Ok(accessed) = accessed {
-            //     if let Ok(created) = created {
-            //         if let Ok(modified) = modified {
-            //             return Ok(StagedData {
-            //                 read_only: metadata.permissions().readonly().to_string(),
-            //                 is_file: metadata.is_file().to_string(),
-            //                 modified,
-            //                 created,
-            //                 accessed,
-            //                 sha1,
-            //                 path,
-            //             });
-            //         }
-            //     }
-            // }
-            // return Err("Could not get metadata from file".to_string());
-        }
-
-        /// Private helper function to convert file contents to sha1 hex hash
-        fn create_sha_1_hex(file_path: &str) -> Result<String, String> {
-            // * Sha1 hash object into bytes
-            let mut hasher = Sha1::new();
-            if let Ok(mut file) = fs::File::open(file_path) {
-                io::copy(&mut file, &mut hasher);
-                let result = hasher.finalize();
-                // * Encode sha1 encryption into hex
-                return Ok(hex::encode(result));
-            } else {
-                return Err("Could not open file to hash".to_string());
             }
         }
 
-        /// Private helper function that creates the data being stored & puts it in the corresponding kind
+        /// Private helper function to convert file contents to sha1 hex hash, uses the object store hash so indexed files share ids with stored blobs
+        fn create_sha_1_hex(file_path: &str) -> Result<String, DvcsError> {
+            // * Folders have no contents of their own, they are indexed with the hash of empty contents
+            if Path::new(file_path).is_dir() {
+                return Ok(create_sha_1_hex(&[]));
+            }
+            create_sha_1_hex_from_file(file_path)
+        }
+
+        /// Private helper function that creates the data being stored & puts it in the corresponding area, files going to staging also get their contents stored in the object database so the staged version can be retrieved later
         fn add_staged_data(
             &mut self,
-            file_path: &str,
-            kind: i32,
+            key: &str,
+            area: StagingArea,
             add_nulls: bool,
-        ) -> Result<(), String> {
+        ) -> Result<(), DvcsError> {
+            let file_path = self.working_path(key);
             // * Get metadata
-            let attrib_result = fs::metadata(file_path.clone());
-            match attrib_result {
+            match fs::metadata(&file_path) {
                 Ok(metadata) => {
                     // * Creating hex sha1 hash of the contents
-                    match Self::create_sha_1_hex(file_path) {
-                        Ok(sha1_hex_encode) => {
-                            // * Creating the struct with file path, metadata and sha1 hashed contents
-                            if let Ok(created_data) = Self::create_staged_data_struct(
-                                metadata.clone(),
-                                sha1_hex_encode.clone(),
-                                file_path.to_string(),
-                            ) {
-                                self.index
-                                    .entry(file_path.to_string())
-                                    .and_modify(|e| match kind {
-                                        0 => e.staging = Some(created_data),            // 0 - staging
-                                        1 => e.working_directory = Some(created_data), // 1 - working directory
-                                        _ => e.repository_version = Some(created_data), // 2 - repository version
-                                    })
-                                    .or_insert({
-                                        let data = match Self::create_staged_data_struct(
-                                            metadata.clone(),
-                                            sha1_hex_encode.clone(),
-                                            file_path.to_string(),
-                                        ) {
-                                            Ok(data) => Some(data),
-                                            Err(_) => None,
-                                        };
-
-                                        match kind {
-                                            0 => StagedComparison {
-                                                working_directory: None,
-                                                staging: data,
-                                                repository_version: None,
-                                            },
-                                            1 => StagedComparison {
-                                                working_directory: data,
-                                                staging: None,
-                                                repository_version: None,
-                                            },
-                                            _ => StagedComparison {
-                                                working_directory: None,
-                                                staging: None,
-                                                repository_version: data,
-                                            },
-                                        }
-                                    });
-                                return Ok(());
-                            }
-                        }
-                        Err(err) => return Err(err),
+                    let sha1_hex_encode = if area == StagingArea::Staging && metadata.is_file() {
+                        RepositoryStorage::new(&self.dvcs_hidden)?.put_file(&file_path)?
+                    } else {
+                        Self::create_sha_1_hex(&file_path)?
+                    };
+                    // * Creating the struct with file path, metadata and sha1 hashed contents
+                    let created_data =
+                        Self::create_staged_data_struct(metadata, sha1_hex_encode, key.to_string());
+                    let comparison = self.index.entry(key.to_string()).or_default();
+                    *comparison.area_mut(area) = Some(created_data);
+                    // * Staging a path a merge couldn't merge marks it as fixed
+                    if area == StagingArea::Staging {
+                        comparison.unmerged = false;
                     }
                     Ok(())
                 }
                 // * If file cannot be found set the index as a blank
-                Err(_) => {
-                    println!("Add to staging");
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
                     if add_nulls {
-                        self.index
-                            .entry(file_path.to_string())
-                            .and_modify(|e| match kind {
-                                0 => e.staging = None,
-                                1 => e.working_directory = None,
-                                _ => e.repository_version = None,
-                            });
+                        if let Some(entry) = self.index.get_mut(key) {
+                            *entry.area_mut(area) = None;
+                        }
                         return Ok(());
                     }
-                    return Err("Could not add file to structure".to_string());
+                    Err(DvcsError::PathNotFound(PathBuf::from(file_path)))
                 }
+                Err(e) => Err(DvcsError::io(&file_path, e)),
             }
         }
 
-        ///  Private helper function to go through all files in repository minus the DVCS hidden folder
-        fn recursive_file_traversal(&mut self, starting_directory: &str, kind: i32) {
-            // * Check if starting directory exists
-            match Path::new(&starting_directory).try_exists() {
-                // * Error with opening file
-                Err(_) => return,
-                // * starting_directory not a valid path
-                Ok(false) => return,
-                Ok(true) => {
-                    // * Check starting_directory is a valid path
-                    match fs::read_dir(starting_directory) {
-                        Ok(file_entry) => {
-                            // * Loop through each entry in the directory
-                            file_entry.into_iter().for_each(|entry| {
-                                if let Ok(path) = entry {
-                                    let path_buffer = path.path();
-                                    let file_path = path_buffer.display().to_string();
-                                    // * Loops through all files, not the DVCS hidden one
-                                    if !file_path.contains(&self.dvcs_hidden) {
-                                        if let Ok(attributes) = fs::metadata(file_path.clone()) {
-                                            if attributes.is_dir() {
-                                                // * Recursive call of all files in directory
-                                                let new_directory = file_path.clone();
-                                                self.add_staged_data(&new_directory, kind, false);
-                                                // * Adds file to staging struct
-                                                Self::recursive_file_traversal(
-                                                    self,
-                                                    &new_directory,
-                                                    kind,
-                                                );
-                                            } else if attributes.is_file() {
-                                                // * Recursive call of all files in directory
-                                                self.add_staged_data(&file_path, kind, false);
-                                            }
-                                        }
-                                    }
-                                }
-                            })
-                        }
-                        Err(_) => return,
+        /// Gets the index key of a path, keys are `/` separated paths relative to the working directory so the same file is tracked once no matter how its path was written
+        pub fn index_key(&self, file_path: &str) -> Result<String, DvcsError> {
+            let working_directory = fs::canonicalize(&self.working_directory)
+                .map_err(|e| DvcsError::io(&self.working_directory, e))?;
+            let path = Path::new(file_path);
+            // * Files that don't exist (anymore) can't be canonicalized, resolve their parent folder instead
+            let absolute_path = match fs::canonicalize(path) {
+                Ok(absolute_path) => absolute_path,
+                Err(_) => {
+                    let parent = match path.parent() {
+                        Some(parent) if !parent.as_os_str().is_empty() => parent,
+                        _ => Path::new("."),
                     };
+                    match (fs::canonicalize(parent), path.file_name()) {
+                        (Ok(parent), Some(name)) => parent.join(name),
+                        _ => return Err(DvcsError::PathNotFound(path.to_path_buf())),
+                    }
+                }
+            };
+            match absolute_path.strip_prefix(&working_directory) {
+                Ok(relative_path) => {
+                    let key = relative_path
+                        .components()
+                        .map(|component| component.as_os_str().to_string_lossy().to_string())
+                        .collect::<Vec<String>>()
+                        .join("/");
+                    // * The working directory itself is tracked as "."
+                    if key.is_empty() {
+                        return Ok(".".to_string());
+                    }
+                    Ok(key)
+                }
+                Err(_) => Err(DvcsError::PathOutsideRepository(path.to_path_buf())),
+            }
+        }
+
+        /// Gets the path of an index key on disk
+        pub fn working_path(&self, key: &str) -> String {
+            Path::new(&self.working_directory)
+                .join(key)
+                .display()
+                .to_string()
+        }
+
+        /// Private helper function listing the files and folders of a directory that aren't ignored, as (path, index key, is folder), the DVCS hidden folder is always left out
+        fn visible_entries(
+            &self,
+            directory: &str,
+            ignore: &mut Ignore,
+        ) -> Result<Vec<(String, String, bool)>, DvcsError> {
+            let hidden_key = self.index_key(&self.dvcs_hidden).ok();
+            let entries = fs::read_dir(directory).map_err(|e| DvcsError::io(directory, e))?;
+            let mut visible = vec![];
+            for entry in entries {
+                let file_path = entry
+                    .map_err(|e| DvcsError::io(directory, e))?
+                    .path()
+                    .display()
+                    .to_string();
+                let attributes = match fs::metadata(&file_path) {
+                    Ok(attributes) => attributes,
+                    // * Removed while walking or a link to nothing, there is nothing to index
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(DvcsError::io(&file_path, e)),
+                };
+                if !attributes.is_dir() && !attributes.is_file() {
+                    continue;
+                }
+                let key = self.index_key(&file_path)?;
+                if Some(&key) == hidden_key.as_ref()
+                    || ignore.is_ignored(&key, attributes.is_dir())?
+                {
+                    continue;
+                }
+                visible.push((file_path, key, attributes.is_dir()));
+            }
+            Ok(visible)
+        }
+
+        ///  Private helper function to go through all files in repository minus the DVCS hidden folder and ignored paths
+        fn recursive_file_traversal(
+            &mut self,
+            starting_directory: &str,
+            area: StagingArea,
+            ignore: &mut Ignore,
+        ) -> Result<(), DvcsError> {
+            // * Loop through each entry in the directory
+            for (file_path, key, is_dir) in self.visible_entries(starting_directory, ignore)? {
+                self.add_staged_data(&key, area, false)?;
+                if is_dir {
+                    // * Adds folder to staging struct, then recursive call of all files in it
+                    self.recursive_file_traversal(&file_path, area, ignore)?;
                 }
             }
+            Ok(())
         }
 
-        /// Print staging snapshot (index.json)
+        /// Print staging snapshot (index)
         pub fn print_staging_snapshot(&self) {
             self.index.iter().for_each(|x| {
                 println!("PATH (key): {:?}", &x.0);
@@ -419,53 +725,44 @@ pub mod staging_storage {
             });
         }
 
-        /// Private helper function to delete and create index file
-        fn write_to_staging_file(&self) -> Result<(), String> {
-            if Self::recreate_index_file(&self.dvcs_hidden).is_ok() {
-                return match fs::File::create(self.dvcs_hidden.to_owned() + "/index.json") {
-                    Ok(file) => {
-                        return match serde_json::to_writer(file, &self.index) {
-                            Ok(_) => Ok(()), // * Successfully written to file
-                            Err(_) => Err("Could not write to index file".to_string()),
-                        };
-                    }
-                    Err(_) => Err("Could not recreate index file".to_string()),
-                };
-            }
-            return Err("Could not find index file".to_string());
+        /// Private helper function getting the path of the index file
+        fn index_path(dvcs_hidden: &str) -> String {
+            dvcs_hidden.to_owned() + "/" + INDEX_FILE_NAME
+        }
+
+        /// Private helper function to write the index struct to the binary index file, holding the repository lock so another process can't write it at the same time
+        fn write_to_staging_file(&mut self) -> Result<(), DvcsError> {
+            let contents = index_file::encode(&self.index)?;
+            let _lock = RepositoryLock::acquire(&self.dvcs_hidden)?;
+            write_atomic(Self::index_path(&self.dvcs_hidden), &contents)?;
+            self.index_mtime = Self::index_mtime(&self.dvcs_hidden);
+            Ok(())
         }
 
-        /// Private helper function to read index struct from file
+        /// Private helper function to read index struct from file, without a binary index the index.json of an older version is read
         fn read_from_staging_file(
-            path: String,
-        ) -> Result<HashMap<String, StagedComparison>, String> {
-            // * Open the json file
-            match File::open(path + "/index.json") {
-                Ok(index_file) => {
-                    let mut contents = String::new();
-                    // * Creating string from contents in index file
-                    index_file
-                        .borrow()
-                        .read_to_string(&mut contents)
-                        .or_else(|_| {
-                            return Err("No content found in staging file".to_string());
-                        })
-                        .unwrap();
-                    // * Check if file is empty, create empty hashmap
-                    if contents.to_string().len() == 0 {
-                        return Ok(HashMap::new());
-                    }
-                    // * Otherwise, return structure created from file, TODO: safe way to check if this works?
-                    if let Ok(deserialized) = serde_json::from_str(&contents.to_string()) {
-                        return Ok(deserialized);
-                    } else {
-                        return Err("Could not deserialize index file".to_string());
-                    }
-                }
-                Err(_) => {
-                    return Err("Could not open index file".to_string());
+            dvcs_hidden: &str,
+        ) -> Result<HashMap<String, StagedComparison>, DvcsError> {
+            let index_path = Self::index_path(dvcs_hidden);
+            match fs::read(&index_path) {
+                Ok(contents) => return index_file::decode(&contents),
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(DvcsError::io(&index_path, e))
                 }
+                Err(_) => {}
             }
+            let legacy_path = dvcs_hidden.to_owned() + "/" + LEGACY_INDEX_FILE_NAME;
+            let contents = match fs::read_to_string(&legacy_path) {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+                Err(e) => return Err(DvcsError::io(&legacy_path, e)),
+            };
+            // * Check if file is empty, create empty hashmap
+            if contents.is_empty() {
+                return Ok(HashMap::new());
+            }
+            // * Otherwise, return structure created from file
+            serde_json::from_str(&contents).map_err(|e| DvcsError::IndexCorrupt(e.to_string()))
         }
     }
 
@@ -480,91 +777,93 @@ pub mod staging_storage {
         fn update_staged_files() {
             let mut staging = Staging::new("./src/repo", "./src/working-directory").unwrap();
             let update = staging.update_staged_files();
-            assert_eq!(update.is_ok(), true);
+            assert!(update.is_ok());
         }
 
         #[test]
         // * Adding a file to be stored in the staging storage successfully
-        fn test_add_file_to_staging_success() {
+        fn test_stage_file_success() {
             let mut staging = Staging::new("./src/repo", "./src/working-directory").unwrap();
-            let file = staging.add_file_to_staging("./src/working-directory/file.txt");
-            assert_eq!(file.is_ok(), true);
+            let file = staging.stage_path("./src/working-directory/file.txt");
+            assert!(file.is_ok());
         }
 
         #[test]
         // * Adding a file to be stored in the staging storage successfully
-        fn test_add_directory_to_staging_success() {
+        fn test_stage_directory_success() {
             let mut staging = Staging::new("./src/repo", "./src/working-directory").unwrap();
-            let file = staging.add_directory_to_staging(0, "./src/working-directory/folder 1");
-            assert_eq!(file.is_ok(), true);
+            let file = staging.stage_path("./src/working-directory/folder 1");
+            assert!(file.is_ok());
         }
 
         #[test]
-        // * Adding a file to be stored in the staging storage failure, not a directory given
-        fn test_add_directory_to_staging_fail() {
+        // * Adding a folder to be stored in the staging storage failure, it doesn't exist
+        fn test_stage_directory_fail() {
             let mut staging = Staging::new("./src/repo", "./src/working-directory").unwrap();
-            // * Path to file given, will fail
-            let file = staging.add_directory_to_staging(0, "./src/working-directory/test1.txt");
-            assert_eq!(file.is_err(), true);
+            let file = staging.stage_path("./src/working-directory/folder 2");
+            assert!(matches!(file, Err(DvcsError::PathNotFound(_))));
         }
 
         #[test]
         // * Adding file to be stored in staging storage unsuccessfully because it doesn’t exist
-        fn test_add_file_to_staging_fail() {
+        fn test_stage_file_fail() {
             let mut staging = Staging::new("./src/repo", "./src/working-directory").unwrap();
-            let file = staging.add_file_to_staging("./src/working-directory/folder 1/test2.xyz123");
-            println!("{:?}", file);
-            assert_eq!(file.is_err(), true);
+            let file = staging.stage_path("./src/working-directory/folder 1/test2.xyz123");
+            assert!(matches!(file, Err(DvcsError::PathNotFound(_))));
         }
 
         #[test]
         // *  Remove file from the staging successfully
         fn test_remove_file_from_staging_success() {
             let mut staging = Staging::new("./src/repo", "./src/working-directory").unwrap();
-            staging.add_file_to_staging("./src/working-directory/folder 1/test2.txt");
+            staging
+                .stage_path("./src/working-directory/folder 1/test2.txt")
+                .unwrap();
             let file =
                 staging.remove_file_from_staging("./src/working-directory/folder 1/test2.txt");
             //staging.print_staging_snapshot();
-            assert_eq!(file.is_ok(), true);
+            assert!(file.is_ok());
         }
 
         #[test]
         // *  Successfully set the repository version of the snapshot
-        fn test_set_staging_snapshot_repository() {
+        fn test_record_repository_snapshot() {
             let mut staging = Staging::new("./src/repo", "./src/working-directory");
-            let set = staging.as_mut().unwrap().set_staging_snapshot(2); // 2 = repository version
-            assert_eq!(set.is_ok(), true);
+            let set = staging.as_mut().unwrap().record_repository_snapshot();
+            assert!(set.is_ok());
         }
 
         #[test]
         // *  Successfully set the working directory version of the snapshot
-        fn test_set_staging_snapshot_working_directory() {
+        fn test_refresh_working_copy() {
             let mut staging = Staging::new("./src/repo", "./src/working-directory");
-            let set = staging.as_mut().unwrap().set_staging_snapshot(1); // 1 = working directory
-            assert_eq!(set.is_ok(), true);
+            let set = staging.as_mut().unwrap().refresh_working_copy();
+            assert!(set.is_ok());
         }
 
         #[test]
         // * Get staging structure
         fn test_get_staging_struct() {
             let staging = Staging::new("./src/repo", "./src/working-directory");
-            assert_eq!(staging.is_ok(), true);
+            assert!(staging.is_ok());
         }
 
         #[test]
         // * Get staging structure, failure - repo doesn't exist
         fn test_get_staging_struct_fail() {
             let staging = Staging::new("./src/repo123", "./src/working-directory12312313");
-            assert_eq!(staging.is_err(), true);
+            assert!(matches!(staging, Err(DvcsError::NotARepository(_))));
         }
 
         #[test]
         // * Get file from staging structure
         fn get_file_from_staging() {
             let mut staging = Staging::new("./src/repo", "./src/working-directory").unwrap();
-            staging.add_file_to_staging("./src/working-directory/folder 1/test2.txt");
+            staging
+                .stage_path("./src/working-directory/folder 1/test2.txt")
+                .unwrap();
             let file = staging.get_file_from_staging("./src/working-directory/folder 1/test2.txt");
-            assert_eq!(file.is_ok(), true);
+            assert!(file.is_ok());
         }
 
         #[test]
@@ -572,7 +871,247 @@ pub mod staging_storage {
         fn get_file_from_staging_fail() {
             let mut staging = Staging::new("./src/repo", "./src/working-directory").unwrap();
             let file = staging.get_file_from_staging("./src/working-directory/folder 1/test2.xyz");
-            assert_eq!(file.is_err(), true);
+            assert!(file.is_err());
+        }
+
+        // * Creates a repository with its own DVCS hidden folder and working directory in the temp folder
+        fn temp_repository(name: &str) -> (String, String) {
+            let root = std::env::temp_dir().join(format!("dvcs_staging_{}", name));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join(".dvcs_hidden")).unwrap();
+            fs::create_dir_all(root.join("folder 1")).unwrap();
+            fs::write(root.join("file.txt"), "file").unwrap();
+            fs::write(root.join("folder 1").join("test2.txt"), "test2").unwrap();
+            (
+                root.join(".dvcs_hidden").display().to_string(),
+                root.display().to_string(),
+            )
+        }
+
+        #[test]
+        // * The same file is indexed once, relative to the working directory, no matter how its path is written
+        fn test_index_keys_relative_to_working_directory() {
+            let (dvcs_hidden, working_directory) = temp_repository("index_keys");
+            let mut staging = Staging::new(&dvcs_hidden, &working_directory).unwrap();
+            staging
+                .stage_path(&(working_directory.clone() + "/folder 1/test2.txt"))
+                .unwrap();
+            staging
+                .stage_path(&(working_directory.clone() + "/folder 1/../folder 1/test2.txt"))
+                .unwrap();
+            let index = staging.get_index();
+            assert_eq!(index.len(), 1);
+            assert_eq!(
+                index
+                    .get("folder 1/test2.txt")
+                    .unwrap()
+                    .staging
+                    .as_ref()
+                    .unwrap()
+                    .path,
+                "folder 1/test2.txt"
+            );
+        }
+
+        #[test]
+        // * Files outside of the working directory can't be staged
+        fn test_add_file_outside_working_directory_fail() {
+            let (dvcs_hidden, working_directory) = temp_repository("outside_working_directory");
+            let mut staging = Staging::new(&dvcs_hidden, &working_directory).unwrap();
+            let file = staging.stage_path("./Cargo.toml");
+            assert!(matches!(file, Err(DvcsError::PathOutsideRepository(_))));
+        }
+
+        #[test]
+        // * Write the tree of the staged files, the staged contents are stored in the object database
+        fn test_write_tree() {
+            let (dvcs_hidden, working_directory) = temp_repository("write_tree");
+            let mut staging = Staging::new(&dvcs_hidden, &working_directory).unwrap();
+            staging
+                .stage_path(&(working_directory.clone() + "/file.txt"))
+                .unwrap();
+            staging
+                .stage_path(&(working_directory.clone() + "/folder 1/test2.txt"))
+                .unwrap();
+            // * Changes made after staging don't end up in the tree
+            fs::write(working_directory.clone() + "/file.txt", "changed").unwrap();
+
+            let storage = RepositoryStorage::new(&dvcs_hidden).unwrap();
+            let tree_id = staging.write_tree(&storage).unwrap();
+            let files = Tree::flatten(&storage, &tree_id).unwrap();
+            assert_eq!(
+                files.keys().collect::<Vec<&String>>(),
+                vec!["file.txt", "folder 1/test2.txt"]
+            );
+            assert_eq!(
+                storage.get(&files.get("file.txt").unwrap().id).unwrap(),
+                b"file".to_vec()
+            );
+            // * Writing the same staged files again gives the same tree
+            assert_eq!(staging.write_tree(&storage).unwrap(), tree_id);
+        }
+
+        #[test]
+        // * Ignored files and folders are left out of snapshots and directory adds, but not out of the index when they were already tracked
+        fn test_ignored_files() {
+            let (dvcs_hidden, working_directory) = temp_repository("ignored_files");
+            fs::create_dir_all(working_directory.clone() + "/target/debug").unwrap();
+            fs::write(working_directory.clone() + "/target/debug/app", "binary").unwrap();
+            fs::write(working_directory.clone() + "/folder 1/notes.log", "log").unwrap();
+            fs::write(
+                working_directory.clone() + "/.dvcsignore",
+                "target/\n*.log\n",
+            )
+            .unwrap();
+            let mut staging = Staging::new(&dvcs_hidden, &working_directory).unwrap();
+
+            staging.refresh_working_copy().unwrap();
+            let index = staging.get_index();
+            assert!(index.contains_key("folder 1/test2.txt"));
+            assert!(index.contains_key(".dvcsignore"));
+            assert!(!index.contains_key("target"));
+            assert!(!index.contains_key("target/debug/app"));
+            assert!(!index.contains_key("folder 1/notes.log"));
+
+            staging
+                .stage_path(&(working_directory.clone() + "/folder 1"))
+                .unwrap();
+            assert_eq!(
+                staging.untracked_files().unwrap(),
+                vec![".dvcsignore", "file.txt"]
+            );
+            let rule = staging
+                .check_ignore(&(working_directory.clone() + "/target/debug/app"))
+                .unwrap()
+                .unwrap();
+            assert_eq!(rule.to_string(), ".dvcsignore:1:target/");
+            assert!(staging
+                .check_ignore(&(working_directory + "/file.txt"))
+                .unwrap()
+                .is_none());
+        }
+
+        #[test]
+        // * Files are only hashed again when their stat data changed or when they are racily clean
+        fn test_stat_data_cache() {
+            use std::time::{Duration, SystemTime};
+            let (dvcs_hidden, working_directory) = temp_repository("stat_data_cache");
+            let file_path = working_directory.clone() + "/file.txt";
+            let set_modified = |time: SystemTime| {
+                fs::File::options()
+                    .write(true)
+                    .open(&file_path)
+                    .unwrap()
+                    .set_modified(time)
+                    .unwrap()
+            };
+            let fake_cached_hash = |staging: &mut Staging| {
+                let entry = staging.index.get_mut("file.txt").unwrap();
+                entry.working_directory.as_mut().unwrap().sha1 = "c".repeat(40);
+            };
+            let working_hash = |staging: &Staging| {
+                staging.get_index()["file.txt"]
+                    .working_directory
+                    .as_ref()
+                    .unwrap()
+                    .sha1
+                    .clone()
+            };
+            let mut staging = Staging::new(&dvcs_hidden, &working_directory).unwrap();
+
+            // * Unchanged file older than the index keeps its cached hash
+            set_modified(SystemTime::now() - Duration::from_secs(60));
+            staging.stage_path(&file_path).unwrap();
+            staging.update_staged_files().unwrap();
+            fake_cached_hash(&mut staging);
+            staging.update_staged_files().unwrap();
+            assert_eq!(working_hash(&staging), "c".repeat(40));
+
+            // * Changed contents change the stat data
+            fs::write(&file_path, "changed").unwrap();
+            set_modified(SystemTime::now() - Duration::from_secs(60));
+            staging.update_staged_files().unwrap();
+            assert_eq!(working_hash(&staging), create_sha_1_hex(b"changed"));
+
+            // * Modified at or after the index write, the stat data can't be trusted
+            set_modified(SystemTime::now() + Duration::from_secs(60));
+            staging.stage_path(&file_path).unwrap();
+            staging.update_staged_files().unwrap();
+            fake_cached_hash(&mut staging);
+            staging.update_staged_files().unwrap();
+            assert_eq!(working_hash(&staging), create_sha_1_hex(b"changed"));
+        }
+
+        #[test]
+        // * An index.json of an older version is read once and replaced by the binary index, its entries are keyed again and their staged contents stored
+        fn test_migrate_legacy_index() {
+            let (dvcs_hidden, working_directory) = temp_repository("migrate_legacy_index");
+            // * Older versions keyed entries by the path as it was passed in, like the paths of the files found in the working directory
+            let legacy_entry = |path: &str, contents: &[u8]| {
+                let path = working_directory.clone() + "/" + path;
+                format!(
+                    r#""{0}":{{"working_directory":null,"staging":{{"path":"{0}","read_only":"false","is_file":"true","sha1":"{1}"}},"repository_version":null}}"#,
+                    path,
+                    create_sha_1_hex(contents)
+                )
+            };
+            fs::write(
+                dvcs_hidden.clone() + "/index.json",
+                format!(
+                    "{{{},{},{}}}",
+                    legacy_entry("file.txt", b"file"),
+                    legacy_entry("folder 1/test2.txt", b"changed since"),
+                    r#""/outside/a.txt":{"working_directory":null,"staging":null,"repository_version":null}"#
+                ),
+            )
+            .unwrap();
+            let staging = Staging::new(&dvcs_hidden, &working_directory).unwrap();
+            let mut keys: Vec<&String> = staging.get_index().keys().collect();
+            keys.sort();
+            assert_eq!(keys, vec!["file.txt", "folder 1/test2.txt"]);
+            let staged = staging.get_index()["file.txt"].staging.clone().unwrap();
+            assert!(staged.is_file && !staged.read_only);
+            assert_eq!(staged.path, "file.txt");
+            assert_eq!(staged.stat, FileStat::default());
+            assert_eq!(
+                RepositoryStorage::new(&dvcs_hidden)
+                    .unwrap()
+                    .get(&staged.sha1)
+                    .unwrap(),
+                b"file".to_vec()
+            );
+            // * Staged contents that are gone can't be committed, the file is no longer staged
+            assert!(staging.get_index()["folder 1/test2.txt"].staging.is_none());
+            assert!(!Path::new(&(dvcs_hidden.clone() + "/index.json")).exists());
+            assert!(fs::read(dvcs_hidden.clone() + "/index")
+                .unwrap()
+                .starts_with(b"DVCI"));
+
+            // * A damaged index is an error instead of an empty index
+            fs::write(dvcs_hidden.clone() + "/index", b"DVCI").unwrap();
+            assert!(matches!(
+                Staging::new(&dvcs_hidden, &working_directory),
+                Err(DvcsError::IndexCorrupt(_))
+            ));
+        }
+
+        #[test]
+        fn test_promote_staged_files() {
+            let (dvcs_hidden, working_directory) = temp_repository("promote_staged_files");
+            let mut staging = Staging::new(&dvcs_hidden, &working_directory).unwrap();
+            let file_path = working_directory.clone() + "/file.txt";
+            staging.stage_path(&file_path).unwrap();
+            staging.promote_staged_files().unwrap();
+            let comparison = staging.get_index().get("file.txt").unwrap();
+            assert_eq!(
+                comparison.repository_version.as_ref().unwrap().sha1,
+                comparison.staging.as_ref().unwrap().sha1
+            );
+
+            // * Committing a removal forgets the file
+            staging.remove_file_from_staging(&file_path).unwrap();
+            staging.promote_staged_files().unwrap();
+            assert!(staging.get_index().get("file.txt").is_none());
         }
     }
 }
@@ -0,0 +1,338 @@
+use crate::repository_storage::{create_sha_1_hex, RepositoryStorage};
+use dvcs_error::DvcsError;
+use std::env;
+use std::time::SystemTime;
+
+/// Who made a commit and when, the timestamp is in seconds since the unix epoch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    pub timestamp: u64,
+}
+
+impl Signature {
+    /// Creates a signature for the current time, new lines, `<` and `>` are taken out of the name and the email as they would end the signature line early
+    pub fn now(name: &str, email: &str) -> Signature {
+        Signature {
+            name: Self::clean(name),
+            email: Self::clean(email),
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0),
+        }
+    }
+
+    /// Creates a signature for the current time from `DVCS_AUTHOR_NAME` and `DVCS_AUTHOR_EMAIL`, falling back to the user running the process
+    pub fn from_environment() -> Signature {
+        // * A name that is nothing but characters `now` takes out counts as not set
+        let name = env::var("DVCS_AUTHOR_NAME")
+            .ok()
+            .filter(|name| !Self::clean(name).is_empty())
+            .or_else(|| env::var("USER").ok())
+            .or_else(|| env::var("USERNAME").ok())
+            .unwrap_or_else(|| "unknown".to_string());
+        let email = env::var("DVCS_AUTHOR_EMAIL").unwrap_or_default();
+        Signature::now(&name, &email)
+    }
+
+    /// Formats the timestamp as a UTC date, `YYYY-MM-DD HH:MM:SS`
+    pub fn date(&self) -> String {
+        let (days, seconds) = (self.timestamp / 86_400, self.timestamp % 86_400);
+        // * Days since the epoch to a proleptic Gregorian date, counted in 400 year eras starting on March 1st
+        let days = days as i64 + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+        format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            year,
+            month,
+            day,
+            seconds / 3_600,
+            seconds % 3_600 / 60,
+            seconds % 60
+        )
+    }
+
+    /// Private helper function taking the characters a signature can't hold out of a name or an email
+    fn clean(text: &str) -> String {
+        text.chars()
+            .filter(|c| !matches!(c, '\n' | '\r' | '<' | '>'))
+            .collect::<String>()
+            .trim()
+            .to_string()
+    }
+
+    /// Serializes the signature as `<name> <<email>> <timestamp>`
+    fn serialize(&self) -> String {
+        format!("{} <{}> {}", self.name, self.email, self.timestamp)
+    }
+
+    /// Parses a serialized signature, None if it is malformed
+    fn parse(signature: &str) -> Option<Signature> {
+        let (name, rest) = signature.split_once(" <")?;
+        let (email, timestamp) = rest.split_once("> ")?;
+        Some(Signature {
+            name: name.to_string(),
+            email: email.to_string(),
+            timestamp: timestamp.parse().ok()?,
+        })
+    }
+}
+
+/// Parses a UTC date written as `YYYY-MM-DD`, `YYYY-MM-DD HH:MM:SS` (or with a `T` between the date and the time) or as seconds since the unix epoch, None if it is malformed
+pub fn parse_date(date: &str) -> Option<u64> {
+    let date = date.trim();
+    if !date.is_empty() && date.chars().all(|c| c.is_ascii_digit()) {
+        return date.parse().ok();
+    }
+    let (day, time) = match date.split_once([' ', 'T']) {
+        Some((day, time)) => (day, time),
+        None => (date, "00:00:00"),
+    };
+    let numbers = |text: &str, separator: char| -> Option<Vec<i64>> {
+        text.split(separator)
+            .map(|number| number.parse().ok())
+            .collect()
+    };
+    let (day, time) = (numbers(day, '-')?, numbers(time, ':')?);
+    let (year, month, day) = match day[..] {
+        [year, month, day]
+            if (1..=12).contains(&month) && (1..=days_in_month(year, month)).contains(&day) =>
+        {
+            (year, month, day)
+        }
+        _ => return None,
+    };
+    let seconds = match time[..] {
+        [hours, minutes, seconds] if hours < 24 && minutes < 60 && seconds < 60 => {
+            hours * 3_600 + minutes * 60 + seconds
+        }
+        _ => return None,
+    };
+    // * Inverse of `Signature::date`, months are counted from March so the leap day is last
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    u64::try_from(days * 86_400 + seconds).ok()
+}
+
+/// Private helper function getting the number of days of a month (1 to 12) in the Gregorian calendar
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Commit object, a snapshot (root tree) with its parents, who made it and why
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub author: Signature,
+    pub committer: Signature,
+    pub message: String,
+}
+
+impl Commit {
+    /// Serializes the commit as header lines followed by a blank line and the message
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut serialized = format!("tree {}\n", self.tree);
+        for parent in &self.parents {
+            serialized.push_str(&format!("parent {}\n", parent));
+        }
+        serialized.push_str(&format!("author {}\n", self.author.serialize()));
+        serialized.push_str(&format!("committer {}\n", self.committer.serialize()));
+        serialized.push('\n');
+        serialized.push_str(&self.message);
+        serialized.into_bytes()
+    }
+
+    /// Parses a serialized commit
+    pub fn deserialize(contents: &[u8]) -> Result<Commit, DvcsError> {
+        // * The id of an object is the hash of its contents
+        let corrupt = |reason: String| DvcsError::ObjectCorrupt {
+            id: create_sha_1_hex(contents),
+            reason,
+        };
+        let text = match std::str::from_utf8(contents) {
+            Ok(text) => text,
+            Err(_) => return Err(corrupt("commit is not valid utf-8".to_string())),
+        };
+        let (headers, message) = match text.split_once("\n\n") {
+            Some(split) => split,
+            None => return Err(corrupt("malformed commit".to_string())),
+        };
+        let mut tree = None;
+        let mut parents = vec![];
+        let mut author = None;
+        let mut committer = None;
+        for header in headers.lines() {
+            let parsed = match header.split_once(' ') {
+                Some(("tree", id)) => {
+                    tree = Some(id.to_string());
+                    true
+                }
+                Some(("parent", id)) => {
+                    parents.push(id.to_string());
+                    true
+                }
+                Some(("author", signature)) => {
+                    author = Signature::parse(signature);
+                    author.is_some()
+                }
+                Some(("committer", signature)) => {
+                    committer = Signature::parse(signature);
+                    committer.is_some()
+                }
+                _ => false,
+            };
+            if !parsed {
+                return Err(corrupt(format!("malformed commit header {}", header)));
+            }
+        }
+        match (tree, author, committer) {
+            (Some(tree), Some(author), Some(committer)) => Ok(Commit {
+                tree,
+                parents,
+                author,
+                committer,
+                message: message.to_string(),
+            }),
+            _ => Err(corrupt(
+                "commit is missing its tree, author or committer".to_string(),
+            )),
+        }
+    }
+
+    /// Stores the commit in the object database and returns its id, the hash of its serialized form
+    pub fn write(&self, storage: &RepositoryStorage) -> Result<String, DvcsError> {
+        storage.put(&self.serialize())
+    }
+
+    /// Reads the commit stored under an id
+    pub fn read(storage: &RepositoryStorage, id: &str) -> Result<Commit, DvcsError> {
+        Commit::deserialize(&storage.get(id)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn test_commit(message: &str, parents: Vec<String>) -> Commit {
+        let signature = Signature {
+            name: "Ada Lovelace".to_string(),
+            email: "ada@example.com".to_string(),
+            timestamp: 1670000000,
+        };
+        Commit {
+            tree: "a".repeat(40),
+            parents,
+            author: signature.clone(),
+            committer: signature,
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        // merge commits keep all of their parents in order, messages can span lines
+        let commit = test_commit(
+            "Merge branch\n\nwith a longer description\n",
+            vec!["b".repeat(40), "c".repeat(40)],
+        );
+        assert_eq!(Commit::deserialize(&commit.serialize()).unwrap(), commit);
+
+        // root commits have no parents
+        let commit = test_commit("Initial commit", vec![]);
+        assert_eq!(Commit::deserialize(&commit.serialize()).unwrap(), commit);
+    }
+
+    #[test]
+    fn test_signature_date() {
+        let mut signature = test_commit("", vec![]).author;
+        assert_eq!(signature.date(), "2022-12-02 16:53:20");
+        signature.timestamp = 0;
+        assert_eq!(signature.date(), "1970-01-01 00:00:00");
+        // * Leap day
+        signature.timestamp = 951_782_400;
+        assert_eq!(signature.date(), "2000-02-29 00:00:00");
+    }
+
+    #[test]
+    // * A name or email with a new line or angle brackets would make the commit unreadable
+    fn test_signature_cleaned() {
+        let signature = Signature::now("Ada\nparent x <Lovelace>", " ada@example.com>\r\n");
+        assert_eq!(signature.name, "Adaparent x Lovelace");
+        assert_eq!(signature.email, "ada@example.com");
+        let commit = Commit {
+            author: signature.clone(),
+            committer: signature,
+            ..test_commit("Signed", vec![])
+        };
+        assert_eq!(Commit::deserialize(&commit.serialize()).unwrap(), commit);
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("2022-12-02 16:53:20"), Some(1670000000));
+        assert_eq!(parse_date("2022-12-02T16:53:20"), Some(1670000000));
+        assert_eq!(parse_date("2000-02-29"), Some(951_782_400));
+        assert_eq!(parse_date("1670000000"), Some(1670000000));
+        assert_eq!(parse_date("1969-12-31"), None);
+        assert_eq!(parse_date("2022-13-01"), None);
+        // * The day has to be in the month
+        assert_eq!(parse_date("2022-02-31"), None);
+        assert_eq!(parse_date("2022-04-31"), None);
+        assert_eq!(parse_date("2023-02-29"), None);
+        assert_eq!(parse_date("1900-02-29"), None);
+        assert!(parse_date("2024-02-29").is_some());
+        assert!(parse_date("2022-01-31").is_some());
+        assert_eq!(parse_date("yesterday"), None);
+    }
+
+    #[test]
+    fn test_same_message_different_ids() {
+        let path = std::env::temp_dir().join("dvcs_commit_objects_same_message_different_ids");
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        let storage = RepositoryStorage::new(path.to_str().unwrap()).unwrap();
+
+        // two commits with the same message but different parents don't collide
+        let first = test_commit("Fix", vec![]).write(&storage).unwrap();
+        let second = test_commit("Fix", vec![first.clone()])
+            .write(&storage)
+            .unwrap();
+        assert_ne!(first, second);
+        assert_eq!(
+            Commit::read(&storage, &second).unwrap().parents,
+            vec![first]
+        );
+    }
+
+    #[test]
+    fn test_deserialize_malformed() {
+        assert!(Commit::deserialize(b"tree abc\n\nmessage").is_err());
+        assert!(Commit::deserialize(b"not a commit").is_err());
+    }
+}
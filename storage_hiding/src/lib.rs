@@ -1,23 +1,161 @@
-#[warn(unused_imports)]
-use std::collections::HashMap;
+pub mod commit_objects;
+pub mod object_transfer;
+pub mod repository_lock;
+pub mod tree_objects;
 
 pub mod repository_storage {
 
+    use dvcs_error::DvcsError;
+    use sha1::{Digest, Sha1};
     use std::collections::HashMap;
+    use std::fs::{self, File};
+    use std::io::{self, Write};
+    use std::path::{Path, PathBuf};
 
+    /// Name of the folder inside the DVCS hidden folder that holds the object database
+    pub const OBJECTS_FOLDER: &str = "objects";
+
+    /// Content-addressed object database kept under `<dvcs_hidden>/objects`
+    ///
+    /// Every object is stored once, keyed by the sha1 hex of its contents (the same hash the staging index records for files),
+    /// and fanned out into sub folders named after the first two hex characters of the id
     pub struct RepositoryStorage {
+        // path to the objects folder inside the DVCS hidden folder
+        objects_path: PathBuf,
+
         // map to store the repository structure (keys are file paths and values are file contents)
         structure: HashMap<String, String>,
     }
 
+    /// Converts contents to the sha1 hex hash used as an object id
+    pub fn create_sha_1_hex(contents: &[u8]) -> String {
+        let mut hasher = Sha1::new();
+        hasher.update(contents);
+        hex::encode(hasher.finalize())
+    }
+
+    /// Converts the contents of a file to the sha1 hex hash used as an object id without loading it in memory
+    pub fn create_sha_1_hex_from_file(file_path: &str) -> Result<String, DvcsError> {
+        let mut hasher = Sha1::new();
+        File::open(file_path)
+            .and_then(|mut file| io::copy(&mut file, &mut hasher))
+            .map_err(|e| DvcsError::io(file_path, e))?;
+        Ok(hex::encode(hasher.finalize()))
+    }
+
+    /// Replaces the contents of a file so that a crash leaves either the old or the new contents, never a truncated file
+    ///
+    /// The contents are written to a temporary file next to it, flushed to disk and renamed over the file
+    pub fn write_atomic(path: impl AsRef<Path>, contents: &[u8]) -> Result<(), DvcsError> {
+        let path = path.as_ref();
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temporary_path =
+            path.with_file_name(format!("{}.{}.tmp", file_name, std::process::id()));
+        let written = File::create(&temporary_path)
+            .and_then(|mut file| file.write_all(contents).and_then(|_| file.sync_all()))
+            .and_then(|_| fs::rename(&temporary_path, path));
+        if let Err(e) = written {
+            // * Nothing to clean up if the temporary file was never created
+            let _ = fs::remove_file(&temporary_path);
+            return Err(DvcsError::io(&temporary_path, e));
+        }
+        // * The rename itself is only durable once the folder is flushed too
+        #[cfg(unix)]
+        if let Some(folder) = path
+            .parent()
+            .filter(|folder| !folder.as_os_str().is_empty())
+        {
+            File::open(folder)
+                .and_then(|folder| folder.sync_all())
+                .map_err(|e| DvcsError::io(folder, e))?;
+        }
+        Ok(())
+    }
+
+    /// Checks that an id looks like a sha1 hex hash so it can't be used to escape the objects folder
+    fn is_valid_id(id: &str) -> bool {
+        id.len() == 40 && id.chars().all(|c| c.is_ascii_hexdigit())
+    }
+
     impl RepositoryStorage {
-        // creates a new instance of the RepositoryStorage
-        pub fn new() -> RepositoryStorage {
-            RepositoryStorage {
-                structure: HashMap::new(),
+        /// Opens the object database inside the DVCS hidden folder, creating the objects folder if it doesn't exist yet
+        ///
+        /// #### Arguments
+        ///
+        /// * `dvcs_hidden` - A string that holds the path to the DVCS hidden folder (respository folder)
+        pub fn new(dvcs_hidden: &str) -> Result<RepositoryStorage, DvcsError> {
+            match Path::new(dvcs_hidden).try_exists() {
+                Err(e) => Err(DvcsError::io(dvcs_hidden, e)),
+                Ok(false) => Err(DvcsError::NotARepository(PathBuf::from(dvcs_hidden))),
+                Ok(true) => {
+                    let objects_path = Path::new(dvcs_hidden).join(OBJECTS_FOLDER);
+                    fs::create_dir_all(&objects_path)
+                        .map_err(|e| DvcsError::io(&objects_path, e))?;
+                    Ok(RepositoryStorage {
+                        objects_path,
+                        structure: HashMap::new(),
+                    })
+                }
+            }
+        }
+
+        /// Stores contents in the object database and returns their id, storing the same contents twice is a no-op
+        pub fn put(&self, contents: &[u8]) -> Result<String, DvcsError> {
+            let id = create_sha_1_hex(contents);
+            if self.exists(&id) {
+                return Ok(id);
+            }
+            let fan_out = self.objects_path.join(&id[..2]);
+            fs::create_dir_all(&fan_out).map_err(|e| DvcsError::io(&fan_out, e))?;
+            // * A half written object never shows up under its id
+            write_atomic(fan_out.join(&id[2..]), contents)?;
+            Ok(id)
+        }
+
+        /// Stores the contents of a file in the object database and returns their id
+        pub fn put_file(&self, file_path: &str) -> Result<String, DvcsError> {
+            match fs::read(file_path) {
+                Ok(contents) => self.put(&contents),
+                Err(e) => Err(DvcsError::io(file_path, e)),
             }
         }
 
+        /// Retrieves the contents stored under an id, failing if the object is missing or corrupt
+        pub fn get(&self, id: &str) -> Result<Vec<u8>, DvcsError> {
+            if !is_valid_id(id) {
+                return Err(DvcsError::InvalidArgument(format!(
+                    "Invalid object id {}",
+                    id
+                )));
+            }
+            match fs::read(self.object_path(id)) {
+                Ok(contents) => {
+                    // * Contents must still hash to their id, otherwise the object was damaged on disk
+                    if create_sha_1_hex(&contents) != id {
+                        return Err(DvcsError::ObjectCorrupt {
+                            id: id.to_string(),
+                            reason: "contents don't match the id".to_string(),
+                        });
+                    }
+                    Ok(contents)
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    Err(DvcsError::ObjectMissing(id.to_string()))
+                }
+                Err(e) => Err(DvcsError::io(self.object_path(id), e)),
+            }
+        }
+
+        /// Checks if an object with the given id is stored in the object database
+        pub fn exists(&self, id: &str) -> bool {
+            is_valid_id(id) && self.object_path(id).is_file()
+        }
+
+        /// Private helper function to get the fanned out path of an object
+        fn object_path(&self, id: &str) -> PathBuf {
+            self.objects_path.join(&id[..2]).join(&id[2..])
+        }
+
         // sees the differences between the current repository structure and a previous snapshot
         pub fn see_diff(&self, snapshot: &HashMap<String, String>) -> HashMap<String, String> {
             let mut diff = HashMap::new();
@@ -50,42 +188,48 @@ pub mod repository_storage {
         pub fn set_repo_snapshot(&mut self, snapshot: HashMap<String, String>) {
             self.structure = snapshot;
         }
-
-        fn main() {
-            // create a new instance of the RepositoryStorage
-            let mut repo_storage = RepositoryStorage::new();
-
-            // create the current repository structure
-            let mut current_structure = HashMap::new();
-            current_structure.insert("README.md".to_string(), "Hello, world!".to_string());
-            current_structure.insert("src/main.rs".to_string(), "fn main() {}".to_string());
-            repo_storage.set_repo_snapshot(current_structure);
-
-            // create the previous snapshot of the repository structure
-            let mut prev_snapshot = HashMap::new();
-            prev_snapshot.insert("README.md".to_string(), "Hello, world!".to_string());
-            prev_snapshot.insert("src/main.rs".to_string(), "fn main() {}".to_string());
-
-            // see the differences between the current repository structure and the previous snapshot
-            let diff = repo_storage.see_diff(&prev_snapshot);
-
-            // print the diff
-            for (path, content) in diff {
-                println!("{}: {}", path, content);
-            }
-        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::repository_storage::RepositoryStorage;
+    use crate::repository_storage::{create_sha_1_hex, write_atomic, RepositoryStorage};
+    use dvcs_error::DvcsError;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::PathBuf;
+
+    // * Every test gets its own DVCS hidden folder so tests can run in parallel
+    fn test_dvcs_hidden(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("dvcs_storage_hiding_{}", name));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_write_atomic() {
+        // replacing a file leaves the new contents and no temporary file next to it
+        let dvcs_hidden = test_dvcs_hidden("write_atomic");
+        let path = dvcs_hidden.join("repo.json");
+        write_atomic(&path, b"old").unwrap();
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(fs::read_dir(&dvcs_hidden).unwrap().count(), 1);
+
+        // a folder in the way fails without touching it
+        fs::create_dir_all(dvcs_hidden.join("index")).unwrap();
+        assert!(write_atomic(dvcs_hidden.join("index"), b"index").is_err());
+        assert!(dvcs_hidden.join("index").is_dir());
+        assert_eq!(fs::read_dir(&dvcs_hidden).unwrap().count(), 2);
+    }
 
     #[test]
     fn test_see_diff() {
         // create a new instance of the RepositoryStorage
-        let mut repo_storage = RepositoryStorage::new();
+        let dvcs_hidden = test_dvcs_hidden("see_diff");
+        let mut repo_storage = RepositoryStorage::new(dvcs_hidden.to_str().unwrap()).unwrap();
 
         // create the current repository structure
         let mut current_structure = HashMap::new();
@@ -112,7 +256,8 @@ mod tests {
     #[test]
     fn test_get_repo_structure() {
         // create a new instance of the RepositoryStorage
-        let mut repo_storage = RepositoryStorage::new();
+        let dvcs_hidden = test_dvcs_hidden("get_repo_structure");
+        let mut repo_storage = RepositoryStorage::new(dvcs_hidden.to_str().unwrap()).unwrap();
 
         // create the current repository structure
         let mut current_structure = HashMap::new();
@@ -133,7 +278,8 @@ mod tests {
     #[test]
     fn test_set_repo_snapshot() {
         // create a new instance of the RepositoryStorage
-        let mut repo_storage = RepositoryStorage::new();
+        let dvcs_hidden = test_dvcs_hidden("set_repo_snapshot");
+        let mut repo_storage = RepositoryStorage::new(dvcs_hidden.to_str().unwrap()).unwrap();
 
         // create the previous snapshot of the repository structure
         let mut prev_snapshot = HashMap::new();
@@ -156,7 +302,8 @@ mod tests {
     #[test]
     fn test_get_repo_structure_empty_repo() {
         // create a new instance of the RepositoryStorage
-        let repo_storage = RepositoryStorage::new();
+        let dvcs_hidden = test_dvcs_hidden("get_repo_structure_empty_repo");
+        let repo_storage = RepositoryStorage::new(dvcs_hidden.to_str().unwrap()).unwrap();
 
         // get the current repository structure
         let structure = repo_storage.get_repo_structure();
@@ -164,4 +311,77 @@ mod tests {
         // check if the structure is empty
         assert!(structure.is_empty());
     }
+
+    #[test]
+    fn test_put_and_get_object() {
+        let dvcs_hidden = test_dvcs_hidden("put_and_get_object");
+        let storage = RepositoryStorage::new(dvcs_hidden.to_str().unwrap()).unwrap();
+
+        // store the contents and read them back using the returned id
+        let id = storage.put(b"Hello, world!").unwrap();
+        assert_eq!(id, create_sha_1_hex(b"Hello, world!"));
+        assert!(storage.exists(&id));
+        assert_eq!(storage.get(&id).unwrap(), b"Hello, world!".to_vec());
+
+        // check the object was fanned out by the first two characters of its id
+        assert!(dvcs_hidden
+            .join("objects")
+            .join(&id[..2])
+            .join(&id[2..])
+            .is_file());
+    }
+
+    #[test]
+    fn test_put_file_matches_staging_hash() {
+        let dvcs_hidden = test_dvcs_hidden("put_file_matches_staging_hash");
+        let storage = RepositoryStorage::new(dvcs_hidden.to_str().unwrap()).unwrap();
+        let file_path = dvcs_hidden.join("file.txt");
+        fs::write(&file_path, "fn main() {}").unwrap();
+
+        // the id of a stored file is the same hash that gets recorded for it in the staging index
+        let id = storage.put_file(file_path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            id,
+            repository_storage::create_sha_1_hex_from_file(file_path.to_str().unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_get_missing_object() {
+        let dvcs_hidden = test_dvcs_hidden("get_missing_object");
+        let storage = RepositoryStorage::new(dvcs_hidden.to_str().unwrap()).unwrap();
+
+        // neither unknown ids nor paths pretending to be ids can be read
+        assert!(!storage.exists(&create_sha_1_hex(b"never stored")));
+        assert!(matches!(
+            storage.get(&create_sha_1_hex(b"never stored")),
+            Err(DvcsError::ObjectMissing(_))
+        ));
+        assert!(storage.get("../../index.json").is_err());
+    }
+
+    #[test]
+    fn test_get_corrupt_object() {
+        let dvcs_hidden = test_dvcs_hidden("get_corrupt_object");
+        let storage = RepositoryStorage::new(dvcs_hidden.to_str().unwrap()).unwrap();
+        let id = storage.put(b"original").unwrap();
+
+        // damage the object on disk
+        fs::write(
+            dvcs_hidden.join("objects").join(&id[..2]).join(&id[2..]),
+            "tampered",
+        )
+        .unwrap();
+        assert!(matches!(
+            storage.get(&id),
+            Err(DvcsError::ObjectCorrupt { .. })
+        ));
+    }
+
+    #[test]
+    fn test_new_storage_missing_dvcs_hidden() {
+        let dvcs_hidden = std::env::temp_dir().join("dvcs_storage_hiding_does_not_exist");
+        let _ = fs::remove_dir_all(&dvcs_hidden);
+        assert!(RepositoryStorage::new(dvcs_hidden.to_str().unwrap()).is_err());
+    }
 }
@@ -0,0 +1,278 @@
+use crate::commit_objects::Commit;
+use crate::repository_storage::RepositoryStorage;
+use crate::tree_objects::{EntryMode, Tree};
+use dvcs_error::DvcsError;
+use std::collections::HashSet;
+
+/// Objects reachable from some commits that another repository doesn't have yet
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MissingObjects {
+    /// Missing commits, every commit comes after its parents
+    pub commits: Vec<String>,
+    /// Every missing object (commits, trees and blobs), each object comes after the objects it points to
+    pub objects: Vec<String>,
+}
+
+/// Lists the objects reachable from `tips` that `has` says are missing
+///
+/// A commit or tree the other repository has is not walked into, its history is expected to be there too
+///
+/// #### Arguments
+///
+/// * `storage` - Object database the objects are read from
+/// * `tips` - Ids of the commits everything reachable from is wanted
+/// * `has` - Checks if the other repository already has an object
+pub fn missing_objects(
+    storage: &RepositoryStorage,
+    tips: &[String],
+    has: impl Fn(&str) -> bool,
+) -> Result<MissingObjects, DvcsError> {
+    let mut missing = MissingObjects::default();
+    let mut seen = HashSet::new();
+    // * (commit, parents already listed), a commit is listed once all of its parents are
+    let mut pending: Vec<(String, bool)> = tips.iter().map(|tip| (tip.clone(), false)).collect();
+    while let Some((commit_id, parents_listed)) = pending.pop() {
+        if parents_listed {
+            let commit = Commit::read(storage, &commit_id)?;
+            missing_tree(storage, &commit.tree, &has, &mut seen, &mut missing.objects)?;
+            missing.objects.push(commit_id.clone());
+            missing.commits.push(commit_id);
+            continue;
+        }
+        if has(&commit_id) || !seen.insert(commit_id.clone()) {
+            continue;
+        }
+        pending.push((commit_id.clone(), true));
+        for parent in Commit::read(storage, &commit_id)?.parents.into_iter().rev() {
+            pending.push((parent, false));
+        }
+    }
+    Ok(missing)
+}
+
+/// Lists the objects reachable from `tips` that a repository having the commits `haves` is missing, when only the commit ids of the other repository are known
+///
+/// The trees and blobs of the commits the missing history starts from are known to the other repository too, so unchanged files and folders aren't listed again
+///
+/// #### Arguments
+///
+/// * `storage` - Object database the objects are read from
+/// * `tips` - Ids of the commits everything reachable from is wanted
+/// * `haves` - Ids of commits the other repository has, ids this object database doesn't have are ignored
+pub fn objects_to_send(
+    storage: &RepositoryStorage,
+    tips: &[String],
+    haves: &HashSet<String>,
+) -> Result<MissingObjects, DvcsError> {
+    let haves: HashSet<String> = haves
+        .iter()
+        .filter(|id| storage.exists(id))
+        .cloned()
+        .collect();
+    let commits = missing_objects(storage, tips, |id| haves.contains(id))?.commits;
+    let mut known = haves.clone();
+    for commit_id in &commits {
+        for parent in Commit::read(storage, commit_id)?.parents {
+            if haves.contains(&parent) {
+                let tree = Commit::read(storage, &parent)?.tree;
+                tree_objects(storage, &tree, &mut known)?;
+            }
+        }
+    }
+    missing_objects(storage, tips, |id| known.contains(id))
+}
+
+/// Checks that every object reachable from `tips` is stored, so received commits can't point at trees, blobs or parents that never arrived, returns the commits it walked with every commit after its parents
+///
+/// #### Arguments
+///
+/// * `storage` - Object database the objects have to be in
+/// * `tips` - Ids of the commits to check from
+/// * `complete` - Checks if a commit was already stored with its whole history, the walk stops there
+pub fn check_connected(
+    storage: &RepositoryStorage,
+    tips: &[String],
+    complete: impl Fn(&str) -> bool,
+) -> Result<Vec<String>, DvcsError> {
+    // * Commits and trees are read while walking, so only blobs can still be missing
+    let reachable = missing_objects(storage, tips, complete)?;
+    match reachable.objects.iter().find(|id| !storage.exists(id)) {
+        Some(id) => Err(DvcsError::ObjectMissing(id.clone())),
+        None => Ok(reachable.commits),
+    }
+}
+
+/// Copies the objects reachable from `tips` that `to` doesn't have, returns the commits `complete` doesn't know with every commit after its parents
+///
+/// #### Arguments
+///
+/// * `from` - Object database the objects are read from
+/// * `to` - Object database the objects are copied to
+/// * `tips` - Ids of the commits everything reachable from is copied
+/// * `complete` - Checks if `to` has a commit with its whole history, the walk stops there. Other objects `to` has are walked into, a copy that failed halfway could have left them without the objects they point to
+pub fn copy_objects(
+    from: &RepositoryStorage,
+    to: &RepositoryStorage,
+    tips: &[String],
+    complete: impl Fn(&str) -> bool,
+) -> Result<Vec<String>, DvcsError> {
+    let missing = missing_objects(from, tips, complete)?;
+    for id in missing.objects.iter().filter(|id| !to.exists(id)) {
+        // * get checks the contents still match the id, so a damaged object isn't copied
+        to.put(&from.get(id)?)?;
+    }
+    Ok(missing.commits)
+}
+
+/// Private helper function listing the missing objects of a tree, subtrees and blobs first
+fn missing_tree(
+    storage: &RepositoryStorage,
+    tree_id: &str,
+    has: &impl Fn(&str) -> bool,
+    seen: &mut HashSet<String>,
+    objects: &mut Vec<String>,
+) -> Result<(), DvcsError> {
+    if has(tree_id) || !seen.insert(tree_id.to_string()) {
+        return Ok(());
+    }
+    for entry in Tree::read(storage, tree_id)?.entries() {
+        if entry.mode == EntryMode::Directory {
+            missing_tree(storage, &entry.id, has, seen, objects)?;
+        } else if !has(&entry.id) && seen.insert(entry.id.clone()) {
+            objects.push(entry.id.clone());
+        }
+    }
+    objects.push(tree_id.to_string());
+    Ok(())
+}
+
+/// Private helper function adding a tree with every subtree and blob in it to a set of ids
+fn tree_objects(
+    storage: &RepositoryStorage,
+    tree_id: &str,
+    known: &mut HashSet<String>,
+) -> Result<(), DvcsError> {
+    if !known.insert(tree_id.to_string()) {
+        return Ok(());
+    }
+    for entry in Tree::read(storage, tree_id)?.entries() {
+        if entry.mode == EntryMode::Directory {
+            tree_objects(storage, &entry.id, known)?;
+        } else {
+            known.insert(entry.id.clone());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commit_objects::Signature;
+    use std::fs;
+
+    fn test_storage(name: &str) -> RepositoryStorage {
+        let path = std::env::temp_dir().join(format!("dvcs_object_transfer_{}", name));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        RepositoryStorage::new(path.to_str().unwrap()).unwrap()
+    }
+
+    fn commit(storage: &RepositoryStorage, files: &[(&str, &str)], parents: Vec<String>) -> String {
+        let files: Vec<(String, EntryMode, String)> = files
+            .iter()
+            .map(|(path, contents)| {
+                let id = storage.put(contents.as_bytes()).unwrap();
+                (path.to_string(), EntryMode::File, id)
+            })
+            .collect();
+        let signature = Signature {
+            name: "Ada".to_string(),
+            email: "ada@example.com".to_string(),
+            timestamp: 1670000000,
+        };
+        Commit {
+            tree: Tree::write_from_paths(storage, &files).unwrap(),
+            parents,
+            author: signature.clone(),
+            committer: signature,
+            message: "message".to_string(),
+        }
+        .write(storage)
+        .unwrap()
+    }
+
+    #[test]
+    // * Only the objects the other side is missing are copied, and the history is complete afterwards
+    fn test_copy_objects() {
+        let (from, to) = (test_storage("from"), test_storage("to"));
+        let first = commit(&from, &[("src/a.txt", "a")], vec![]);
+        let second = commit(
+            &from,
+            &[("src/a.txt", "a"), ("b.txt", "b")],
+            vec![first.clone()],
+        );
+
+        assert_eq!(
+            copy_objects(&from, &to, std::slice::from_ref(&first), |id| to.exists(id)).unwrap(),
+            vec![first.clone()]
+        );
+        let missing =
+            missing_objects(&from, std::slice::from_ref(&second), |id| to.exists(id)).unwrap();
+        assert_eq!(missing.commits, vec![second.clone()]);
+        // * The new root tree, b.txt and the commit, src is the same subtree as before
+        assert_eq!(missing.objects.len(), 3);
+        assert_eq!(missing.objects.last(), Some(&second));
+
+        assert_eq!(
+            copy_objects(&from, &to, std::slice::from_ref(&second), |id| to
+                .exists(id))
+            .unwrap(),
+            vec![second.clone()]
+        );
+        let tree = Commit::read(&to, &second).unwrap().tree;
+        assert_eq!(Tree::flatten(&to, &tree).unwrap().len(), 2);
+        assert!(
+            copy_objects(&from, &to, std::slice::from_ref(&second), |id| to
+                .exists(id))
+            .unwrap()
+            .is_empty()
+        );
+
+        // * A commit stored without its tree isn't complete, what it points to is still copied
+        let partial = test_storage("partial");
+        partial.put(&from.get(&second).unwrap()).unwrap();
+        assert_eq!(
+            copy_objects(&from, &partial, std::slice::from_ref(&second), |_| false).unwrap(),
+            vec![first, second.clone()]
+        );
+        assert!(check_connected(&partial, &[second], |_| false).is_ok());
+    }
+
+    #[test]
+    // * Knowing only the commits of the other side, the unchanged parts of their trees aren't sent
+    fn test_objects_to_send() {
+        let storage = test_storage("send");
+        let first = commit(&storage, &[("src/a.txt", "a")], vec![]);
+        let second = commit(
+            &storage,
+            &[("src/a.txt", "a"), ("b.txt", "b")],
+            vec![first.clone()],
+        );
+        let tips = std::slice::from_ref(&second);
+
+        let everything = objects_to_send(&storage, tips, &HashSet::new()).unwrap();
+        assert_eq!(everything.commits, vec![first.clone(), second.clone()]);
+        assert_eq!(everything.objects.len(), 7);
+
+        let haves: HashSet<String> = [first, "0".repeat(64)].into_iter().collect();
+        let missing = objects_to_send(&storage, tips, &haves).unwrap();
+        assert_eq!(missing.commits, vec![second.clone()]);
+        assert_eq!(missing.objects.len(), 3);
+        let haves = [second.clone()].into_iter().collect();
+        assert!(objects_to_send(&storage, tips, &haves)
+            .unwrap()
+            .objects
+            .is_empty());
+    }
+}
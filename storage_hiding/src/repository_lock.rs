@@ -0,0 +1,130 @@
+use dvcs_error::DvcsError;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+
+/// Name of the lock file created inside the DVCS hidden folder while a process writes to the repository
+pub const LOCK_FILE_NAME: &str = "dvcs.lock";
+
+/// Lock files this process holds and how many guards share each of them
+static HELD: Mutex<Vec<(PathBuf, usize)>> = Mutex::new(Vec::new());
+
+/// Exclusive lock on a repository, held until the guard is dropped
+///
+/// The lock is a `dvcs.lock` file created with the PID of the process that holds it, so another dvcs process can't write the index or
+/// the repository file at the same time. Locking the same repository again in the same process shares the lock instead of failing
+#[derive(Debug)]
+pub struct RepositoryLock {
+    path: PathBuf,
+}
+
+impl RepositoryLock {
+    /// Locks the repository of a DVCS hidden folder, fails with `RepositoryLocked` if another process holds the lock
+    ///
+    /// #### Arguments
+    ///
+    /// * `dvcs_hidden` - A string that holds the path to the DVCS hidden folder (respository folder)
+    pub fn acquire(dvcs_hidden: &str) -> Result<RepositoryLock, DvcsError> {
+        // * The same folder can be written many ways, the lock is shared by its canonical path
+        let folder = fs::canonicalize(dvcs_hidden).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => DvcsError::NotARepository(PathBuf::from(dvcs_hidden)),
+            _ => DvcsError::io(dvcs_hidden, e),
+        })?;
+        let path = folder.join(LOCK_FILE_NAME);
+
+        let mut held = HELD.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some((_, count)) = held.iter_mut().find(|(held_path, _)| *held_path == path) {
+            *count += 1;
+            return Ok(RepositoryLock { path });
+        }
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                // * The lock is taken once the file exists, the PID only tells the user who holds it
+                let _ = writeln!(file, "{}", process::id());
+                held.push((path.clone(), 1));
+                Ok(RepositoryLock { path })
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                Err(DvcsError::RepositoryLocked {
+                    pid: Self::holder(&path),
+                    path,
+                })
+            }
+            Err(e) => Err(DvcsError::io(&path, e)),
+        }
+    }
+
+    /// Gets the path of the lock file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Private helper function reading the PID written in a lock file, None while it is still being written or if it was edited
+    fn holder(path: &Path) -> Option<u32> {
+        fs::read_to_string(path).ok()?.trim().parse().ok()
+    }
+}
+
+impl Drop for RepositoryLock {
+    fn drop(&mut self) {
+        let mut held = HELD.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(position) = held.iter().position(|(path, _)| *path == self.path) {
+            held[position].1 -= 1;
+            // * The last guard of the process removes the lock file
+            if held[position].1 == 0 {
+                held.remove(position);
+                let _ = fs::remove_file(&self.path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dvcs_hidden(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("dvcs_storage_hiding_{}", name));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    #[test]
+    // * The lock is shared inside a process, removed with its last guard and refused while another process holds it
+    fn test_repository_lock() {
+        let dvcs_hidden = test_dvcs_hidden("repository_lock");
+        let lock_path = dvcs_hidden.join(LOCK_FILE_NAME);
+        let relative = dvcs_hidden
+            .join("..")
+            .join("dvcs_storage_hiding_repository_lock");
+
+        let first = RepositoryLock::acquire(dvcs_hidden.to_str().unwrap()).unwrap();
+        let second = RepositoryLock::acquire(relative.to_str().unwrap()).unwrap();
+        assert_eq!(
+            fs::read_to_string(&lock_path).unwrap(),
+            format!("{}\n", process::id())
+        );
+        drop(first);
+        assert!(lock_path.exists());
+        drop(second);
+        assert!(!lock_path.exists());
+
+        // * Lock file left by another process
+        fs::write(&lock_path, "4242\n").unwrap();
+        let locked = RepositoryLock::acquire(dvcs_hidden.to_str().unwrap()).unwrap_err();
+        assert!(matches!(
+            locked,
+            DvcsError::RepositoryLocked {
+                pid: Some(4242),
+                ..
+            }
+        ));
+        assert!(locked
+            .to_string()
+            .starts_with("repository is locked by PID 4242"));
+        assert!(lock_path.exists());
+    }
+}
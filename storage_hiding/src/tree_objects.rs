@@ -0,0 +1,454 @@
+use crate::repository_storage::{create_sha_1_hex, RepositoryStorage};
+use dvcs_error::DvcsError;
+use std::collections::BTreeMap;
+
+/// Kind of object a tree entry points to, written in the tree as a git style mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryMode {
+    File,
+    Executable,
+    Directory,
+}
+
+impl EntryMode {
+    /// Mode string written in the serialized tree
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EntryMode::File => "100644",
+            EntryMode::Executable => "100755",
+            EntryMode::Directory => "040000",
+        }
+    }
+
+    /// Parses the mode string written in a serialized tree
+    pub fn parse(mode: &str) -> Option<EntryMode> {
+        match mode {
+            "100644" => Some(EntryMode::File),
+            "100755" => Some(EntryMode::Executable),
+            "040000" => Some(EntryMode::Directory),
+            _ => None,
+        }
+    }
+}
+
+/// Single entry of a tree, either a blob (file contents) or a nested subtree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeEntry {
+    pub name: String,
+    pub mode: EntryMode,
+    pub id: String,
+}
+
+/// File that differs between two trees, `None` on the side where the file doesn't exist
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeChange {
+    pub path: String,
+    pub old: Option<TreeEntry>,
+    pub new: Option<TreeEntry>,
+}
+
+/// Snapshot of one directory, entries are kept sorted by name so the same directory always produces the same id
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Tree {
+    entries: Vec<TreeEntry>,
+}
+
+impl Tree {
+    /// Creates a tree from its entries, sorting them by name
+    pub fn new(mut entries: Vec<TreeEntry>) -> Tree {
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Tree { entries }
+    }
+
+    /// Entries of the tree sorted by name
+    pub fn entries(&self) -> &[TreeEntry] {
+        &self.entries
+    }
+
+    /// Gets the entry with the given name in this directory
+    pub fn get(&self, name: &str) -> Option<&TreeEntry> {
+        self.entries
+            .binary_search_by(|entry| entry.name.as_str().cmp(name))
+            .ok()
+            .map(|position| &self.entries[position])
+    }
+
+    /// Serializes the tree as one `<mode> <id>\t<name>` line per entry
+    pub fn serialize(&self) -> Vec<u8> {
+        self.entries
+            .iter()
+            .map(|entry| format!("{} {}\t{}\n", entry.mode.as_str(), entry.id, entry.name))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    /// Parses a serialized tree
+    pub fn deserialize(contents: &[u8]) -> Result<Tree, DvcsError> {
+        // * The id of an object is the hash of its contents
+        let corrupt = |reason: String| DvcsError::ObjectCorrupt {
+            id: create_sha_1_hex(contents),
+            reason,
+        };
+        let text = match std::str::from_utf8(contents) {
+            Ok(text) => text,
+            Err(_) => return Err(corrupt("tree is not valid utf-8".to_string())),
+        };
+        let mut entries = vec![];
+        for line in text.lines() {
+            // * <mode> <id>\t<name>
+            let (mode, id, name) = match line
+                .split_once('\t')
+                .and_then(|(header, name)| Some((header.split_once(' ')?, name)))
+            {
+                Some(((mode, id), name)) => (mode, id, name),
+                None => return Err(corrupt(format!("malformed tree entry {}", line))),
+            };
+            let mode = match EntryMode::parse(mode) {
+                Some(mode) => mode,
+                None => return Err(corrupt(format!("unknown tree entry mode {}", mode))),
+            };
+            // * Trees can come from another repository, a name must not lead out of its folder when checked out
+            if !valid_entry_name(name) {
+                return Err(corrupt(format!("invalid tree entry name {:?}", name)));
+            }
+            entries.push(TreeEntry {
+                name: name.to_string(),
+                mode,
+                id: id.to_string(),
+            });
+        }
+        Ok(Tree::new(entries))
+    }
+
+    /// Stores the tree in the object database and returns its id
+    pub fn write(&self, storage: &RepositoryStorage) -> Result<String, DvcsError> {
+        storage.put(&self.serialize())
+    }
+
+    /// Reads the tree stored under an id
+    pub fn read(storage: &RepositoryStorage, id: &str) -> Result<Tree, DvcsError> {
+        Tree::deserialize(&storage.get(id)?)
+    }
+
+    /// Builds and stores the nested trees for a list of `/` separated file paths and returns the id of the root tree
+    ///
+    /// #### Arguments
+    ///
+    /// * `storage` - Object database the trees are written to
+    /// * `files` - Path, mode and blob id of every file in the snapshot
+    pub fn write_from_paths(
+        storage: &RepositoryStorage,
+        files: &[(String, EntryMode, String)],
+    ) -> Result<String, DvcsError> {
+        // * Group every file under its top level folder, the rest of the path is handled by the subtree
+        let mut blobs: Vec<TreeEntry> = vec![];
+        let mut folders: BTreeMap<String, Vec<(String, EntryMode, String)>> = BTreeMap::new();
+        for (path, mode, id) in files {
+            match path.split_once('/') {
+                Some((folder, rest)) => folders.entry(folder.to_string()).or_default().push((
+                    rest.to_string(),
+                    *mode,
+                    id.clone(),
+                )),
+                None => blobs.push(TreeEntry {
+                    name: path.clone(),
+                    mode: *mode,
+                    id: id.clone(),
+                }),
+            }
+        }
+        for (folder, files) in folders {
+            blobs.push(TreeEntry {
+                name: folder,
+                mode: EntryMode::Directory,
+                id: Tree::write_from_paths(storage, &files)?,
+            });
+        }
+        Tree::new(blobs).write(storage)
+    }
+
+    /// Reads a tree and all of its subtrees into a map of `/` separated file path to file entry
+    pub fn flatten(
+        storage: &RepositoryStorage,
+        id: &str,
+    ) -> Result<BTreeMap<String, TreeEntry>, DvcsError> {
+        let mut files = BTreeMap::new();
+        Self::flatten_into(storage, id, "", &mut files)?;
+        Ok(files)
+    }
+
+    /// Private helper function to recursively collect the files of a tree under a path prefix
+    fn flatten_into(
+        storage: &RepositoryStorage,
+        id: &str,
+        prefix: &str,
+        files: &mut BTreeMap<String, TreeEntry>,
+    ) -> Result<(), DvcsError> {
+        for entry in Tree::read(storage, id)?.entries {
+            let path = prefix.to_string() + &entry.name;
+            if entry.mode == EntryMode::Directory {
+                Self::flatten_into(storage, &entry.id, &(path + "/"), files)?;
+            } else {
+                files.insert(path, entry);
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets the id of the blob or subtree at a `/` separated path inside a tree
+    pub fn find(
+        storage: &RepositoryStorage,
+        id: &str,
+        path: &str,
+    ) -> Result<Option<TreeEntry>, DvcsError> {
+        let mut current = TreeEntry {
+            name: String::new(),
+            mode: EntryMode::Directory,
+            id: id.to_string(),
+        };
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            if current.mode != EntryMode::Directory {
+                return Ok(None);
+            }
+            match Tree::read(storage, &current.id)?.get(name) {
+                Some(entry) => current = entry.clone(),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(current))
+    }
+
+    /// Lists the files that differ between two trees
+    ///
+    /// Subtrees with the same id are skipped without being read, so unchanged directories cost nothing
+    pub fn changed_paths(
+        storage: &RepositoryStorage,
+        old: Option<&str>,
+        new: Option<&str>,
+    ) -> Result<Vec<TreeChange>, DvcsError> {
+        let mut changes = vec![];
+        Self::changed_paths_into(storage, old, new, "", &mut changes)?;
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(changes)
+    }
+
+    /// Private helper function to recursively compare two trees under a path prefix
+    fn changed_paths_into(
+        storage: &RepositoryStorage,
+        old: Option<&str>,
+        new: Option<&str>,
+        prefix: &str,
+        changes: &mut Vec<TreeChange>,
+    ) -> Result<(), DvcsError> {
+        if old == new {
+            return Ok(());
+        }
+        let old_tree = match old {
+            Some(id) => Tree::read(storage, id)?,
+            None => Tree::default(),
+        };
+        let new_tree = match new {
+            Some(id) => Tree::read(storage, id)?,
+            None => Tree::default(),
+        };
+        let mut names: Vec<&String> = old_tree
+            .entries
+            .iter()
+            .chain(new_tree.entries.iter())
+            .map(|entry| &entry.name)
+            .collect();
+        names.sort();
+        names.dedup();
+        for name in names {
+            let old_entry = old_tree.get(name);
+            let new_entry = new_tree.get(name);
+            if old_entry == new_entry {
+                continue;
+            }
+            let path = prefix.to_string() + name;
+            // * Split each side into its subtree and its file so a file replaced by a folder shows up as both
+            let subtree = |entry: Option<&TreeEntry>| {
+                entry
+                    .filter(|entry| entry.mode == EntryMode::Directory)
+                    .map(|entry| entry.id.clone())
+            };
+            let file = |entry: Option<&TreeEntry>| {
+                entry
+                    .filter(|entry| entry.mode != EntryMode::Directory)
+                    .cloned()
+            };
+            let (old_subtree, new_subtree) = (subtree(old_entry), subtree(new_entry));
+            if old_subtree.is_some() || new_subtree.is_some() {
+                Self::changed_paths_into(
+                    storage,
+                    old_subtree.as_deref(),
+                    new_subtree.as_deref(),
+                    &(path.clone() + "/"),
+                    changes,
+                )?;
+            }
+            let (old_file, new_file) = (file(old_entry), file(new_entry));
+            if old_file != new_file {
+                changes.push(TreeChange {
+                    path,
+                    old: old_file,
+                    new: new_file,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Private helper function checking a tree entry name is a single file or folder name, and not the dvcs hidden folder
+fn valid_entry_name(name: &str) -> bool {
+    !(name.is_empty()
+        || name == "."
+        || name == ".."
+        || name == ".dvcs_hidden"
+        || name.contains(['/', '\\', '\0']))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // * Every test gets its own object database so tests can run in parallel
+    fn test_storage(name: &str) -> RepositoryStorage {
+        let path = std::env::temp_dir().join(format!("dvcs_tree_objects_{}", name));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        RepositoryStorage::new(path.to_str().unwrap()).unwrap()
+    }
+
+    fn file(
+        storage: &RepositoryStorage,
+        path: &str,
+        contents: &str,
+    ) -> (String, EntryMode, String) {
+        (
+            path.to_string(),
+            EntryMode::File,
+            storage.put(contents.as_bytes()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let tree = Tree::new(vec![
+            TreeEntry {
+                name: "src".to_string(),
+                mode: EntryMode::Directory,
+                id: "a".repeat(40),
+            },
+            TreeEntry {
+                name: "README.md".to_string(),
+                mode: EntryMode::File,
+                id: "b".repeat(40),
+            },
+        ]);
+        // entries are sorted by name no matter the order they were given in
+        assert_eq!(tree.entries()[0].name, "README.md");
+        assert_eq!(Tree::deserialize(&tree.serialize()).unwrap(), tree);
+    }
+
+    #[test]
+    // * A crafted tree naming a path outside of its folder can't be read
+    fn test_deserialize_invalid_names() {
+        let id = "a".repeat(40);
+        for name in [
+            "../escape.txt",
+            "..",
+            ".",
+            "",
+            "/etc/x",
+            "a\\b",
+            "a\0b",
+            ".dvcs_hidden",
+        ] {
+            let contents = format!("100644 {}\t{}\n", id, name);
+            assert!(
+                matches!(
+                    Tree::deserialize(contents.as_bytes()),
+                    Err(DvcsError::ObjectCorrupt { .. })
+                ),
+                "{:?} was accepted",
+                name
+            );
+        }
+        let contents = format!("100644 {}\t.dvcs_ignore\n", id);
+        assert!(Tree::deserialize(contents.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_write_from_paths_is_deterministic() {
+        let storage = test_storage("write_from_paths_is_deterministic");
+        let files = vec![
+            file(&storage, "README.md", "Hello, world!"),
+            file(&storage, "src/main.rs", "fn main() {}"),
+            file(&storage, "src/folder 1/lib.rs", "pub fn hello() {}"),
+        ];
+        let mut reversed = files.clone();
+        reversed.reverse();
+
+        // the same snapshot always gets the same id
+        let id = Tree::write_from_paths(&storage, &files).unwrap();
+        assert_eq!(Tree::write_from_paths(&storage, &reversed).unwrap(), id);
+
+        // nested folders are stored as subtrees
+        let root = Tree::read(&storage, &id).unwrap();
+        assert_eq!(root.get("src").unwrap().mode, EntryMode::Directory);
+        let flattened = Tree::flatten(&storage, &id).unwrap();
+        assert_eq!(
+            flattened.keys().collect::<Vec<&String>>(),
+            vec!["README.md", "src/folder 1/lib.rs", "src/main.rs"]
+        );
+        assert_eq!(
+            Tree::find(&storage, &id, "src/main.rs")
+                .unwrap()
+                .unwrap()
+                .id,
+            files[1].2
+        );
+        assert!(Tree::find(&storage, &id, "src/other.rs").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_changed_paths() {
+        let storage = test_storage("changed_paths");
+        let old = Tree::write_from_paths(
+            &storage,
+            &[
+                file(&storage, "README.md", "Hello, world!"),
+                file(&storage, "docs/guide.md", "guide"),
+                file(&storage, "src/main.rs", "fn main() {}"),
+            ],
+        )
+        .unwrap();
+        let new = Tree::write_from_paths(
+            &storage,
+            &[
+                file(&storage, "README.md", "Hello, world!"),
+                file(&storage, "docs/guide.md", "guide"),
+                file(&storage, "src/main.rs", "fn main() { run() }"),
+                file(&storage, "src/lib.rs", "pub fn run() {}"),
+            ],
+        )
+        .unwrap();
+
+        // unchanged directories have the same subtree id
+        let old_docs = Tree::find(&storage, &old, "docs").unwrap().unwrap();
+        let new_docs = Tree::find(&storage, &new, "docs").unwrap().unwrap();
+        assert_eq!(old_docs.id, new_docs.id);
+
+        let changes = Tree::changed_paths(&storage, Some(&old), Some(&new)).unwrap();
+        let paths: Vec<&String> = changes.iter().map(|change| &change.path).collect();
+        assert_eq!(paths, vec!["src/lib.rs", "src/main.rs"]);
+        assert!(changes[0].old.is_none());
+        assert!(changes[1].old.is_some() && changes[1].new.is_some());
+        assert!(Tree::changed_paths(&storage, Some(&old), Some(&old))
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -1,25 +1,26 @@
+pub mod repository;
+
 pub mod user_feedback {
 
     use colored::Colorize;
+    use dvcs_error::DvcsError;
     use rand::prelude::*;
     use std::io;
     use std::io::prelude::*;
 
     pub fn display_all_errors(errors: Vec<String>) -> bool {
-        //errors to iterator
-        let error_inter: Vec<String> = errors.into_iter().collect();
         let mut rng = rand::thread_rng();
 
         //display errors
-        error_inter.iter().for_each(|error: &String| {
+        errors.iter().for_each(|error: &String| {
             println!("{}", error.truecolor(rng.gen(), rng.gen(), rng.gen()))
         });
-        return true;
+        true
     }
 
     pub fn display_first_error(errors: Vec<String>) -> bool {
         println!("{}", errors[0].red());
-        return true;
+        true
     }
 
     pub fn format_error_alt(errors: Vec<String>) -> bool {
@@ -39,398 +40,1390 @@ pub mod user_feedback {
 
         errors.chunks(3).for_each(|error| {
             error
-                .to_vec()
                 .iter()
                 .for_each(|error| println!("{}", error.truecolor(rng.gen(), rng.gen(), rng.gen())));
             pause();
         });
-        return true;
+        true
+    }
+
+    /// Prints an error of a command to stderr
+    pub fn display_error(error: &DvcsError) {
+        eprintln!("{} {}", "error:".red().bold(), error);
+    }
+
+    /// Exit code of a command that failed with an error, 2 is shared with the usage errors of the command line parser
+    ///
+    /// | Code | Errors |
+    /// |------|--------|
+    /// | 1 | anything else (nothing to commit, branch exists or not merged, merge conflicts, push rejected) |
+    /// | 2 | invalid argument |
+    /// | 3 | not a repository, or a bare repository for a command that needs a working directory |
+    /// | 4 | path not found or outside of the repository |
+    /// | 5 | conflict with local changes |
+    /// | 6 | revision or branch not found, or abbreviated id ambiguous |
+    /// | 7 | corrupt index, repository file or objects |
+    /// | 8 | file system or network error |
+    /// | 9 | repository locked by another process |
+    pub fn exit_code(error: &DvcsError) -> u8 {
+        match error {
+            DvcsError::InvalidArgument(_) => 2,
+            DvcsError::NotARepository(_) | DvcsError::BareRepository(_) => 3,
+            DvcsError::PathNotFound(_) | DvcsError::PathOutsideRepository(_) => 4,
+            DvcsError::Conflict { .. } => 5,
+            DvcsError::RevisionNotFound(_)
+            | DvcsError::AmbiguousRevision { .. }
+            | DvcsError::BranchNotFound(_) => 6,
+            DvcsError::IndexCorrupt(_)
+            | DvcsError::RepositoryCorrupt(_)
+            | DvcsError::ObjectMissing(_)
+            | DvcsError::ObjectCorrupt { .. } => 7,
+            DvcsError::Io { .. } | DvcsError::Remote(_) => 8,
+            DvcsError::RepositoryLocked { .. } => 9,
+            DvcsError::NothingToCommit
+            | DvcsError::MergeConflict(_)
+            | DvcsError::PushRejected { .. }
+            | DvcsError::BranchExists(_)
+            | DvcsError::BranchNotMerged(_) => 1,
+        }
     }
 }
 
 pub mod user_interaction {
 
-    use crate::user_feedback::{display_all_errors, display_first_error, format_error_alt};
-    use repo::RepositoryController;
-    use stager;
-    use stager::stager::Stager;
-    use std::clone;
+    use crate::repository::Repository;
+    use crate::user_feedback::display_error;
+    use dvcs_error::DvcsError;
+    use repo::log::{Graph, LogEntry, LogOptions};
+    use repo::merge::MergeOutcome;
+    use repo::network::{Server, DEFAULT_PORT};
+    use repo::remote::{clone_repository, location, Location, PushMode, RefUpdate};
+    use repo::{Head, RepositoryController};
+    use stager::stager::{DiffTarget, Stager};
+    use std::collections::HashMap;
+    use std::fs;
     use std::path::{Path, PathBuf};
-    use std::{collections::HashMap, fs};
-    use storage_hiding::repository_storage::{self, RepositoryStorage};
-
-    pub fn loop_find(current_path: String) -> Option<(String, String)> {
-        let mut found = "".to_string();
-        let mut found_parent = "".to_string();
-
-        let mut head = fs::canonicalize(Path::new(&current_path));
-
-        if (Path::new(&current_path).parent().unwrap().is_dir()
-            && Path::new(&current_path).is_file())
-        {
-            head = Ok(Path::new(&current_path).parent().unwrap().to_path_buf());
-        }
-
-        while Path::new(&head.as_ref().unwrap().to_string_lossy().to_string())
-            .parent()
-            .is_some()
-        {
-            match fs::read_dir(&head.as_ref().unwrap().to_string_lossy().to_string()) {
-                Ok(file_entry) => {
-                    // * Loop through each entry in the directory
-                    for entry in file_entry {
-                        if let Ok(path) = entry {
-                            let path_buffer = path.path();
-
-                            if let Ok(attributes) = fs::metadata(path_buffer.clone()) {
-                                if attributes.is_dir() {
-                                    let name = path_buffer
-                                        .file_name()
-                                        .unwrap()
-                                        .to_string_lossy()
-                                        .to_string();
-                                    if name == ".dvcs_hidden" {
-                                        found = fs::canonicalize(path.path())
-                                            .unwrap()
-                                            .to_string_lossy()
-                                            .to_string();
-
-                                        found_parent =
-                                            fs::canonicalize(path.path().parent().unwrap())
-                                                .unwrap()
-                                                .to_string_lossy()
-                                                .to_string();
-
-                                        break;
-                                    }
-                                } else {
-                                }
-                            } else {
-                            }
-                        } else {
-                        }
-                    }
-                    head = fs::canonicalize(
-                        &head
-                            .as_ref()
-                            .clone()
-                            .unwrap()
-                            .parent()
-                            .unwrap()
-                            .to_string_lossy()
-                            .to_string(),
-                    );
-                }
-                Err(_) => {
-                    break;
-                }
-            };
-            if found != "".to_string() {
-                return Some((found, found_parent));
-            } else {
-                return None;
+
+    /// Private helper function checking a path exists before a command uses it
+    fn check_exists(file_path: &str) -> Result<(), DvcsError> {
+        match Path::new(file_path).try_exists() {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(DvcsError::PathNotFound(PathBuf::from(file_path))),
+            Err(e) => Err(DvcsError::io(file_path, e)),
+        }
+    }
+
+    /// Serves the repositories in a folder to `dvcs://` clients until the process is stopped, on localhost unless another address is given
+    pub fn serve_in(path: String, listen: Option<String>) -> Result<(), DvcsError> {
+        check_exists(&path)?;
+        let listen = listen.unwrap_or_else(|| format!("127.0.0.1:{}", DEFAULT_PORT));
+        let server = Server::bind(&listen, &path)?;
+        println!(
+            "Serving the repositories in {} on dvcs://{}/",
+            path,
+            server.local_address()?
+        );
+        server.run(display_error)
+    }
+
+    /// Creates a repository in a folder, a bare repository (see `RepositoryController::init_bare`) has no working directory to stage files from
+    pub fn init_in(file_path: String, bare: bool) -> Result<(), DvcsError> {
+        check_exists(&file_path)?;
+        if bare {
+            RepositoryController::init_bare(&file_path)?;
+            println!("Initialized empty bare dvcs repository in {}", file_path);
+            return Ok(());
+        }
+        // * create hidden directory
+        let dvcs_hidden = file_path.clone() + "/.dvcs_hidden";
+        fs::create_dir_all(&dvcs_hidden).map_err(|e| DvcsError::io(&dvcs_hidden, e))?;
+
+        Stager::new(&dvcs_hidden, &file_path)?.init(file_path.clone())?;
+        println!("Initialized empty dvcs repository in {}", file_path);
+        Ok(())
+    }
+
+    /// Clones a repository from a path or a url, without a destination the clone goes in a folder named after the source
+    pub fn clone_in(source: String, destination: Option<String>) -> Result<(), DvcsError> {
+        let destination = match destination {
+            Some(destination) => destination,
+            None => location(&source)?.repository_name().ok_or_else(|| {
+                DvcsError::InvalidArgument(format!(
+                    "Can't name the clone of {}, give a destination",
+                    source
+                ))
+            })?,
+        };
+        println!("Cloning into '{}'...", destination);
+        let repository = clone_repository(&source, &destination)?;
+        match repository.current_branch() {
+            Some(branch) if repository.head_commit().is_some() => {
+                println!("Checked out branch '{}'", branch)
             }
+            _ => println!("warning: You appear to have cloned an empty repository."),
         }
-        if found != "".to_string() {
-            return Some((found, found_parent));
-        } else {
-            return None;
+        Ok(())
+    }
+
+    pub fn diff_in(
+        repository: &Repository,
+        file_path: String,
+        head: String,
+        context: usize,
+    ) -> Result<(), DvcsError> {
+        check_exists(&file_path)?;
+        // * Empty head compares against the staged version, revisions are resolved by the repository (see `RepositoryController::resolve_diff`)
+        let target = match head.as_str() {
+            "" | "--cached" | "--staged" => DiffTarget::parse(&head),
+            expression => match repository.controller()?.resolve_diff(expression)? {
+                (old, None) => DiffTarget::CommitToWorking(old),
+                (old, Some(new)) => DiffTarget::Commits(old, new),
+            },
+        };
+        let diff = repository.stager()?.diff(&file_path, target, context)?;
+        print!("{}", diff);
+        Ok(())
+    }
+
+    /// Prints the staged, unstaged and untracked changes, `porcelain` prints the stable `XY <path>` format for scripts instead
+    pub fn status_in(
+        repository: &Repository,
+        file_path: String,
+        porcelain: bool,
+    ) -> Result<(), DvcsError> {
+        check_exists(&file_path)?;
+        let report = repository.stager()?.status(file_path)?;
+        if porcelain {
+            print!("{}", report.to_porcelain());
+            return Ok(());
+        }
+        match repository.controller()?.head() {
+            Head::Branch(name) => println!("On branch {}\n", name),
+            Head::Detached(commit_id) => println!("HEAD detached at {}\n", short_id(commit_id)),
         }
+        print!("{}", report.to_human());
+        Ok(())
     }
 
-    pub fn init_in(file_path: String) -> bool {
-        //let res = Path::new(&file_path).try_exists().unwrap_or_else(|_| false);
-        match Path::new(&file_path).try_exists().unwrap_or_else(|_| false) {
-            true => {
-                // * create hidden directory
-                std::fs::create_dir_all(file_path.clone() + "/.dvcs_hidden");
+    /// Removes a file from staging, files deleted from the working directory can still be removed
+    pub fn remove_in(repository: &Repository, file_path: String) -> Result<(), DvcsError> {
+        repository.stager()?.remove(file_path)
+    }
 
-                let fp = file_path.clone();
-                let fp_2 = file_path.clone();
+    /// Stages a file or every file of a folder, nothing is printed if it succeeds
+    pub fn add_in(repository: &Repository, file_path: String) -> Result<(), DvcsError> {
+        // * A bare repository is refused before the path is looked at
+        let stager = repository.stager()?;
+        check_exists(&file_path)?;
+        stager.add(file_path)
+    }
 
-                let stager_i = Stager::new(&(fp + "/.dvcs_hidden"), &file_path.clone());
+    /// Prints every line of a file with the short id, author and date of the commit that last changed it
+    pub fn blame_in(
+        repository: &Repository,
+        file_path: String,
+        revision: String,
+    ) -> Result<(), DvcsError> {
+        required(&file_path, "No file specified")?;
+        let key = repository.repository_path(&file_path)?;
+        let revision = if revision.is_empty() {
+            None
+        } else {
+            Some(revision.as_str())
+        };
+        let lines = repository.controller()?.blame(&key, revision)?;
+        // * Authors are padded to the longest one so the lines start in the same column
+        let width = lines
+            .iter()
+            .map(|line| line.author.name.chars().count())
+            .max()
+            .unwrap_or(0);
+        let number_width = lines.len().to_string().len();
+        for line in lines {
+            println!(
+                "{} ({:<width$} {} {:>number_width$}) {}",
+                short_id(&line.commit_id),
+                line.author.name,
+                line.author.date(),
+                line.line_number,
+                line.line,
+            );
+        }
+        Ok(())
+    }
 
-                let init_res = stager_i.unwrap().init(file_path);
-                if init_res.is_err() {
-                    let t_er = init_res.unwrap_err();
-                    display_first_error(vec![t_er]);
-                    return false;
-                }
-                println!("Initialized empty dvcs repository in {}", &fp_2.clone());
-                return true;
-            }
-            false => {
-                //println!("Error!");
-                //println!("Which error function do you want to use?");
-                //read console input
-                //match input to error function
-
-                let t_er = "file path: ".to_owned() + &file_path.to_string() + " does not exists!";
-                display_all_errors(vec![t_er]);
-                return false;
-
-                // let errchoice = std::io::stdin();
-                // println!("\n1. Display first error\n2. Display all errors\n3. Display errors in chunks\n");
-                // let mut input = String::new();
-                // let errfn = errchoice.read_line(&mut input).unwrap();
-
-                // let t_er = "file path: ".to_owned() + &file_path.to_string() + " does not exists!";
-                // match errfn {
-                //     1 => {
-                //         //display first error
-                //         display_first_error(vec![t_er]);
-                //         return false;
-                //     }
-                //     2 => {
-                //         //display all errors
-                //         display_all_errors(vec![t_er]);
-                //         return false;
-                //     }
-                //     3 => {
-                //         //display errors in chunks
-                //         format_error_alt(vec![t_er]);
-                //         return false;
-                //     }
-                //     _ => {
-                //         //display first error
-                //         display_all_errors(vec![t_er]);
-                //         return false;
-                //     }
-                // }
-            }
-        }
-    }
-
-    pub fn diff_in(file_path: String, head: String) -> bool {
-        let res: bool = Path::new(&file_path).try_exists().unwrap_or_else(|_| false);
-        match res {
-            true => {
-                println!("File exists, diffing...");
-
-                let fp1 = file_path.clone();
-                let dvcs_hidden = loop_find(fp1);
-                if dvcs_hidden.is_none() {
-                    println!("not a git repository (or any of the parent directories)");
-                    return false;
-                }
+    /// How `log_in` prints every commit
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum LogStyle {
+        /// Id, merged parents, author, date and the indented message
+        Full,
+        /// Short id and first line of the message
+        Oneline,
+        /// Template of `LogEntry::format`
+        Format(String),
+    }
 
-                let fp = file_path.clone();
+    /// Prints the commits reachable from the revisions of the options, `graph` draws the commit graph on their left
+    ///
+    /// The paths of the options are paths from the current folder, they don't have to exist anymore
+    pub fn log_in(
+        repository: &Repository,
+        mut options: LogOptions,
+        style: LogStyle,
+        graph: bool,
+    ) -> Result<(), DvcsError> {
+        if !options.paths.is_empty() {
+            options.paths = options
+                .paths
+                .iter()
+                .map(|path| repository.repository_path(path))
+                .collect::<Result<_, _>>()?;
+        }
+        // * The lanes of the graph lead from a commit to its parents, so they have to come after all of their children
+        options.topological = graph;
+        let mut log = repository.controller()?.log(options)?;
+        let mut drawing = Graph::default();
+        let mut next = log.next().transpose()?;
+        while let Some(entry) = next {
+            let skipped = log.take_skipped();
+            next = log.next().transpose()?;
+            let mut lines = log_lines(&entry, &style);
+            // * Full commits are separated by an empty line
+            if style == LogStyle::Full && next.is_some() {
+                lines.push(String::new());
+            }
+            if !graph {
+                for line in lines {
+                    println!("{}", line);
+                }
+                continue;
+            }
+            for (commit_id, parents) in skipped {
+                drawing.skip(&commit_id, &parents);
+            }
+            let row = drawing.draw(&entry.id, &entry.commit.parents);
+            for line in row.before {
+                println!("{}", line);
+            }
+            for (position, line) in lines.iter().enumerate() {
+                let lanes = if position == 0 {
+                    &row.commit
+                } else {
+                    &row.padding
+                };
+                println!("{}", format!("{} {}", lanes, line).trim_end());
+            }
+            for line in row.after {
+                println!("{}", line);
+            }
+        }
+        Ok(())
+    }
 
-                let stager_i = Stager::new(
-                    loop_find(file_path.clone()).unwrap().0.as_str(),
-                    loop_find(file_path.clone()).unwrap().1.as_str(),
-                );
-                if stager_i.is_err() {
-                    let t_er = stager_i.unwrap_err();
-                    display_first_error(vec![t_er]);
-                    return false;
+    /// Private helper function formatting a commit of the log in a style
+    fn log_lines(entry: &LogEntry, style: &LogStyle) -> Vec<String> {
+        let text = match style {
+            LogStyle::Oneline => entry.format("%h %s"),
+            LogStyle::Format(template) => entry.format(template),
+            LogStyle::Full => {
+                let mut text = format!("commit {}\n", entry.id);
+                if entry.commit.parents.len() > 1 {
+                    let parents: Vec<&str> = entry
+                        .commit
+                        .parents
+                        .iter()
+                        .map(|parent| short_id(parent))
+                        .collect();
+                    text.push_str(&format!("Merge: {}\n", parents.join(" ")));
                 }
-                stager_i.unwrap().diff(file_path, head);
-                return true;
-            }
-            false => {
-                //println!("Error!");
-                //println!("Which error function do you want to use?");
-                //read console input
-                //match input to error function
-
-                let t_er = "file path: ".to_owned() + &file_path.to_string() + " does not exists!";
-                display_all_errors(vec![t_er]);
-                return false;
-
-                // let errchoice = std::io::stdin();
-                // println!("\n1. Display first error\n2. Display all errors\n3. Display errors in chunks\n");
-                // let mut input = String::new();
-                // let errfn = errchoice.read_line(&mut input).unwrap();
-
-                // let t_er = "file path: ".to_owned() + &file_path.to_string() + " does not exists!";
-                // match errfn {
-                //     1 => {
-                //         //display first error
-                //         display_first_error(vec![t_er]);
-                //         return false;
-                //     }
-                //     2 => {
-                //         //display all errors
-                //         display_all_errors(vec![t_er]);
-                //         return false;
-                //     }
-                //     3 => {
-                //         //display errors in chunks
-                //         format_error_alt(vec![t_er]);
-                //         return false;
-                //     }
-                //     _ => {
-                //         //display first error
-                //         display_all_errors(vec![t_er]);
-                //         return false;
-                //     }
-                // }
-            }
-        }
-    }
-    pub fn status_in(file_path: String) -> bool {
-        let res: bool = Path::new(&file_path).try_exists().unwrap_or_else(|_| false);
-        match res {
-            true => {
-                println!("File exists, status...");
-
-                let path = file_path.clone();
-                let mut found = String::from("");
-                //find_hidden_dvcs_folder(path, found);
-
-                let fp1 = file_path.clone();
-                let dvcs_hidden = loop_find(fp1);
-                if dvcs_hidden.is_none() {
-                    println!("not a git repository (or any of the parent directories)");
-                    return false;
+                text.push_str(&entry.format("Author: %an <%ae>%nDate:   %ad%n"));
+                for line in entry.commit.message.lines() {
+                    text.push_str(&format!("\n    {}", line));
                 }
+                text
+            }
+        };
+        text.lines()
+            .map(|line| line.trim_end().to_string())
+            .collect()
+    }
+
+    /// Explains which ignore rule decides if a path is ignored, printed as `<file>:<line>:<pattern>\t<path>`, the path doesn't have to exist
+    pub fn check_ignore_in(repository: &Repository, file_path: String) -> Result<(), DvcsError> {
+        match repository.stager()?.check_ignore(&file_path)? {
+            Some(rule) if rule.is_negated() => {
+                println!("{}\t{} (not ignored)", rule, file_path)
+            }
+            Some(rule) => println!("{}\t{}", rule, file_path),
+            None => println!("{} is not ignored, no rule matches it", file_path),
+        }
+        Ok(())
+    }
 
-                let fp = file_path.clone();
+    /// Private helper function for arguments that can't be empty
+    fn required(value: &str, message: &str) -> Result<(), DvcsError> {
+        if value.trim().is_empty() {
+            return Err(DvcsError::InvalidArgument(message.to_string()));
+        }
+        Ok(())
+    }
 
-                let stager_i = Stager::new(
-                    loop_find(file_path.clone()).unwrap().0.as_str(),
-                    loop_find(file_path.clone()).unwrap().1.as_str(),
-                );
+    pub fn checkout_in(
+        repository: &Repository,
+        commit_id: String,
+        force: bool,
+    ) -> Result<(), DvcsError> {
+        required(&commit_id, "No commit specified")?;
+        let (mut staging, mut repo) = (repository.staging()?, repository.controller()?);
+        // * Checking out a commit detaches HEAD so new commits don't move a branch that points elsewhere
+        let commit_id = repo.detach_head(&mut staging, &commit_id, force)?;
+        println!("HEAD is now at {}", short_id(&commit_id));
+        Ok(())
+    }
 
-                if stager_i.is_err() {
-                    let t_er = stager_i.unwrap_err();
-                    display_first_error(vec![t_er]);
-                    return false;
-                }
-                let status_res = stager_i.unwrap().status(file_path);
+    /// Commits everything staged and moves the current branch to the new commit
+    pub fn commit_in(repository: &Repository, message: String) -> Result<(), DvcsError> {
+        required(&message, "No commit message, use commit -m <message>")?;
+        let (mut staging, mut repo) = (repository.staging()?, repository.controller()?);
+        let commit_id = repo.commit_staged(&mut staging, &message)?;
+        let branch = repo.current_branch().unwrap_or("detached HEAD").to_string();
+        println!(
+            "[{} {}] {}",
+            branch,
+            short_id(&commit_id),
+            message.lines().next().unwrap_or("")
+        );
+        Ok(())
+    }
 
-                if status_res.is_ok() {
-                    print!("{}", status_res.unwrap());
-                    return true;
+    /// Lists the branches with their tips when `name` is empty, otherwise creates a branch at `start` (or HEAD when empty)
+    pub fn branch_in(
+        repository: &Repository,
+        name: String,
+        start: String,
+    ) -> Result<(), DvcsError> {
+        let mut repo = repository.controller()?;
+        if name.is_empty() {
+            if let Head::Detached(commit_id) = repo.head() {
+                println!("* (HEAD detached at {})", short_id(commit_id));
+            }
+            for (branch, tip) in repo.list_branches() {
+                let marker = if repo.current_branch() == Some(branch.as_str()) {
+                    "*"
                 } else {
-                    println!("Error! File path empty");
-                    return false;
-                }
+                    " "
+                };
+                println!("{} {} {}", marker, branch, short_id(&tip));
             }
-            false => {
-                //println!("Error!");
-                //println!("Which error function do you want to use?");
-                //read console input
-                //match input to error function
-
-                let t_er = "file path: ".to_owned() + &file_path.to_string() + " does not exists!";
-                display_all_errors(vec![t_er]);
-                return false;
-            } //     let errchoice = std::io::stdin();
-              //     println!("\n1. Display first error\n2. Display all errors\n3. Display errors in chunks\n");
-              //     let mut input = String::new();
-              //     let errfn = errchoice.read_line(&mut input).unwrap();
-
-              //     match errfn {
-              //         1 => {
-              //             //display first error
-              //             display_first_error(vec![t_er]);
-              //             return false;
-              //         }
-              //         2 => {
-              //             //display all errors
-              //             display_all_errors(vec![t_er]);
-              //             return false;
-              //         }
-              //         3 => {
-              //             //display errors in chunks
-              //             format_error_alt(vec![t_er]);
-              //             return false;
-              //         }
-              //         _ => {
-              //             //display first error
-
-              //             return false;
-              //         }
-              //     }
-              // }
-        }
-    }
-
-    pub fn remove_in(file_path: String) -> bool {
-        let res: bool = Path::new(&file_path).try_exists().unwrap_or_else(|_| false);
-        if res {
-            println!("File exists, removing...");
-
-            let fp1 = file_path.clone();
-            let dvcs_hidden = loop_find(fp1);
-            if dvcs_hidden.is_none() {
-                println!("not a git repository (or any of the parent directories)");
-                return false;
-            }
-
-            let fp = file_path.clone();
-
-            let stager_i = Stager::new(
-                loop_find(file_path.clone()).unwrap().0.as_str(),
-                loop_find(file_path.clone()).unwrap().1.as_str(),
-            );
+            return Ok(());
+        }
+        let start = if start.is_empty() {
+            None
+        } else {
+            Some(start.as_str())
+        };
+        let tip = repo.create_branch(&name, start)?;
+        println!("Created branch {} at {}", name, short_id(&tip));
+        Ok(())
+    }
+
+    /// Deletes a branch, `force` deletes it even if it isn't merged into HEAD
+    pub fn delete_branch_in(
+        repository: &Repository,
+        name: String,
+        force: bool,
+    ) -> Result<(), DvcsError> {
+        required(&name, "No branch specified")?;
+        repository.controller()?.delete_branch(&name, force)?;
+        println!("Deleted branch {}", name);
+        Ok(())
+    }
+
+    pub fn rename_branch_in(
+        repository: &Repository,
+        old_name: String,
+        new_name: String,
+    ) -> Result<(), DvcsError> {
+        if old_name.is_empty() || new_name.is_empty() {
+            return Err(DvcsError::InvalidArgument(
+                "Both the old and the new branch name are needed".to_string(),
+            ));
+        }
+        repository
+            .controller()?
+            .rename_branch(&old_name, &new_name)?;
+        println!("Renamed branch {} to {}", old_name, new_name);
+        Ok(())
+    }
+
+    /// Switches to a branch, `create` creates it at HEAD first and `detach` checks out `target` as a commit instead
+    pub fn switch_in(
+        repository: &Repository,
+        target: String,
+        create: bool,
+        detach: bool,
+        force: bool,
+    ) -> Result<(), DvcsError> {
+        required(&target, "No branch specified")?;
+        let (mut staging, mut repo) = (repository.staging()?, repository.controller()?);
+        if detach {
+            let commit_id = repo.detach_head(&mut staging, &target, force)?;
+            println!("HEAD is now at {}", short_id(&commit_id));
+        } else {
+            if create {
+                repo.create_branch(&target, None)?;
+            }
+            repo.switch_branch(&mut staging, &target, force)?;
+            println!("Switched to branch {}", target);
+        }
+        Ok(())
+    }
 
-            if stager_i.is_err() {
-                let t_er = stager_i.unwrap_err();
-                display_first_error(vec![t_er]);
-                return false;
+    /// Lists the tags with their commits when `name` is empty, otherwise creates a tag at `revision` (or HEAD when empty)
+    pub fn tag_in(
+        repository: &Repository,
+        name: String,
+        revision: String,
+    ) -> Result<(), DvcsError> {
+        let mut repo = repository.controller()?;
+        if name.is_empty() {
+            for (tag, commit_id) in repo.list_tags() {
+                println!("{} {}", tag, short_id(&commit_id));
             }
-            let remove_res = stager_i.unwrap().remove(file_path);
-            if remove_res.is_ok() {
-                return true;
-            } else {
-                let err = remove_res.unwrap_err();
-                display_first_error(vec![err]);
-                return false;
+            return Ok(());
+        }
+        let revision = if revision.is_empty() {
+            None
+        } else {
+            Some(revision.as_str())
+        };
+        let commit_id = repo.create_tag(&name, revision)?;
+        println!("Created tag {} at {}", name, short_id(&commit_id));
+        Ok(())
+    }
+
+    /// Lists the remote-tracking branches with their commits
+    pub fn remote_branches_in(repository: &Repository) -> Result<(), DvcsError> {
+        for (branch, tip) in repository.controller()?.list_remote_branches() {
+            println!("  {} {}", branch, short_id(&tip));
+        }
+        Ok(())
+    }
+
+    /// Lists the remotes with their urls when `name` is empty, otherwise adds a remote
+    pub fn remote_in(repository: &Repository, name: String, url: String) -> Result<(), DvcsError> {
+        let mut repo = repository.controller()?;
+        if name.is_empty() {
+            for (remote, url) in repo.list_remotes() {
+                println!("{}\t{}", remote, url);
             }
+            return Ok(());
         }
-        display_first_error(vec!["file path not found".to_string()]);
-        return false;
+        required(&url, "No url specified")?;
+        // * Paths are saved absolute, so the remote is found from any folder of the working directory
+        let url = match location(&url)? {
+            Location::Local(path) if !url.contains("://") => match fs::canonicalize(path) {
+                Ok(path) => path.display().to_string(),
+                Err(_) => url,
+            },
+            _ => url,
+        };
+        repo.add_remote(&name, &url)?;
+        println!("Added remote {} for {}", name, url);
+        Ok(())
     }
 
-    pub fn add_in(file_path: String) -> bool {
-        let res: bool = Path::new(&file_path).try_exists().unwrap_or_else(|_| false);
-        if res {
-            println!("File exists, adding...");
+    pub fn remove_remote_in(repository: &Repository, name: String) -> Result<(), DvcsError> {
+        required(&name, "No remote specified")?;
+        repository.controller()?.remove_remote(&name)?;
+        println!("Removed remote {}", name);
+        Ok(())
+    }
+
+    /// Fetches the branches and tags of a remote into its remote-tracking branches
+    pub fn fetch_in(repository: &Repository, remote: String) -> Result<(), DvcsError> {
+        let mut repo = repository.controller()?;
+        let updates = repo.fetch(&remote)?;
+        print_ref_updates(repo.remote_url(&remote)?, &updates);
+        Ok(())
+    }
 
-            let fp1 = file_path.clone();
-            let dvcs_hidden = loop_find(fp1);
+    /// Fetches a remote and merges its branch into the current branch, conflicts are reported as an error once the files are written
+    pub fn pull_in(
+        repository: &Repository,
+        remote: String,
+        branch: Option<String>,
+    ) -> Result<(), DvcsError> {
+        let (mut staging, mut repo) = (repository.staging()?, repository.controller()?);
+        let (updates, outcome) = repo.pull(&mut staging, &remote, branch.as_deref())?;
+        print_ref_updates(repo.remote_url(&remote)?, &updates);
+        match outcome {
+            MergeOutcome::UpToDate => println!("Already up to date."),
+            MergeOutcome::FastForward(commit_id) => {
+                println!("Fast-forward to {}", short_id(&commit_id))
+            }
+            MergeOutcome::Merged(commit_id) => {
+                println!("Merge made, created {}", short_id(&commit_id))
+            }
+            MergeOutcome::Conflicts(paths) => return Err(DvcsError::MergeConflict(paths)),
+        }
+        Ok(())
+    }
 
-            if dvcs_hidden.is_none() {
-                println!("not a git repository (or any of the parent directories)");
-                return false;
+    /// Pushes a branch, or the current branch when `branch` is empty, to the branch of the same name of a remote
+    pub fn push_in(
+        repository: &Repository,
+        remote: String,
+        branch: String,
+        mode: PushMode,
+    ) -> Result<(), DvcsError> {
+        let mut repo = repository.controller()?;
+        let branch = match (branch.is_empty(), repo.current_branch()) {
+            (false, _) => branch,
+            (true, Some(current)) => current.to_string(),
+            (true, None) => {
+                return Err(DvcsError::InvalidArgument(
+                    "HEAD is detached, name the branch to push".to_string(),
+                ))
             }
+        };
+        let update = repo.push(&remote, &branch, mode)?;
+        println!("To {}", repo.remote_url(&remote)?);
+        match (&update.old, &update.new) {
+            (Some(old), Some(new)) if old == new => println!("Everything up-to-date"),
+            (Some(old), Some(new)) if repo.is_ancestor(old, new)? => println!(
+                "   {}..{}  {} -> {}",
+                short_id(old),
+                short_id(new),
+                branch,
+                branch
+            ),
+            (Some(old), Some(new)) => println!(
+                " + {}...{} {} -> {} (forced update)",
+                short_id(old),
+                short_id(new),
+                branch,
+                branch
+            ),
+            _ => println!(" * [new branch]      {} -> {}", branch, branch),
+        }
+        Ok(())
+    }
 
-            let fp = file_path.clone();
+    /// Private helper function printing the remote-tracking branches a fetch changed
+    fn print_ref_updates(url: &str, updates: &[RefUpdate]) {
+        if updates.is_empty() {
+            return;
+        }
+        println!("From {}", url);
+        for update in updates {
+            match (&update.old, &update.new) {
+                (None, _) => println!(" * [new branch]      {}", update.name),
+                (Some(old), Some(new)) => {
+                    println!("   {}..{}  {}", short_id(old), short_id(new), update.name)
+                }
+                (Some(_), None) => println!(" - [deleted]         {}", update.name),
+            }
+        }
+    }
 
-            let stager_i = Stager::new(
-                loop_find(file_path.clone()).unwrap().0.as_str(),
-                loop_find(file_path.clone()).unwrap().1.as_str(),
-            );
+    pub fn delete_tag_in(repository: &Repository, name: String) -> Result<(), DvcsError> {
+        required(&name, "No tag specified")?;
+        repository.controller()?.delete_tag(&name)?;
+        println!("Deleted tag {}", name);
+        Ok(())
+    }
+
+    /// Private helper function to shorten a commit id for display
+    fn short_id(commit_id: &str) -> &str {
+        &commit_id[..commit_id.len().min(7)]
+    }
+
+    pub fn see_diff_in(
+        repository: &Repository,
+        snapshot: &HashMap<String, String>,
+    ) -> Result<HashMap<String, String>, DvcsError> {
+        Ok(repository.storage()?.see_diff(snapshot))
+    }
+}
+
+pub mod cli {
+    use crate::repository::Repository;
+    use crate::user_feedback::{display_error, exit_code};
+    use crate::user_interaction;
+    use clap::{Parser, Subcommand};
+    use dvcs_error::DvcsError;
+    use repo::log::LogOptions;
+    use repo::remote::{PushMode, DEFAULT_REMOTE};
+    use repo_directory_hiding::line_diff::DEFAULT_CONTEXT_LINES;
+    use std::collections::HashMap;
+    use std::env;
+    use std::io;
+    use std::path::PathBuf;
+    use storage_hiding::commit_objects::parse_date;
+
+    /// Distributed version control system
+    #[derive(Parser, Debug)]
+    #[command(name = "dvcs", version, about)]
+    pub struct Cli {
+        /// Run as if dvcs was started in this folder
+        #[arg(short = 'C', value_name = "DIR", global = true)]
+        pub directory: Option<String>,
+        #[command(subcommand)]
+        pub command: Command,
+    }
+
+    #[derive(Subcommand, Debug)]
+    pub enum Command {
+        /// Create an empty repository
+        Init {
+            /// Folder to create the repository in
+            #[arg(default_value = ".")]
+            path: String,
+            /// Create a repository without a working directory, to share and push to
+            #[arg(long)]
+            bare: bool,
+        },
+        /// Copy a repository into a new folder and check out its default branch
+        Clone {
+            /// Path, file:// url or dvcs://host:port/path url of the repository to clone
+            source: String,
+            /// Folder to clone into, it must not exist or be empty (defaults to the name of the source folder)
+            destination: Option<String>,
+        },
+        /// Serve the repositories in a folder to other machines, for clone, fetch and push with dvcs://host:port/path urls
+        Serve {
+            /// Folder of the served repositories, a url path is a path inside it
+            #[arg(default_value = ".")]
+            path: String,
+            /// Address to listen on (defaults to 127.0.0.1:9419, use 0.0.0.0:<port> to accept other machines)
+            #[arg(long, value_name = "ADDRESS")]
+            listen: Option<String>,
+        },
+        /// Show changes as a unified diff
+        Diff {
+            /// Compare the staged files against HEAD instead of the working directory against the staged files
+            #[arg(long, visible_alias = "staged", conflicts_with = "revision")]
+            cached: bool,
+            /// Number of unchanged lines shown around every change
+            #[arg(short = 'U', long = "unified", default_value_t = DEFAULT_CONTEXT_LINES)]
+            context: usize,
+            /// Compare a commit against the working directory, two commits written as <commit>..<commit>, or <commit>...<commit> from their merge base
+            ///
+            /// Commits are revisions like HEAD~2, master^2, a tag or an abbreviated id
+            revision: Option<String>,
+            /// File or folder to limit the diff to
+            #[arg(last = true, default_value = ".")]
+            path: String,
+        },
+        /// Show the state of the working directory and the staged files
+        Status {
+            /// File or folder to show
+            #[arg(default_value = ".")]
+            path: String,
+            /// Print `XY <path>` lines in a format that is stable for scripts
+            #[arg(long)]
+            porcelain: bool,
+        },
+        /// Show the commit that last changed every line of a file
+        Blame {
+            /// File to blame
+            path: String,
+            /// Revision to blame the file at, HEAD when left out
+            revision: Option<String>,
+        },
+        /// Show the commits reachable from HEAD or from other commits and branches
+        Log {
+            /// Revisions or ranges (A..B, A...B) to show, HEAD when left out
+            revisions: Vec<String>,
+            /// Show every commit on one line as its short id and the first line of its message
+            #[arg(long)]
+            oneline: bool,
+            /// Draw the commit graph on the left of the commits
+            #[arg(long)]
+            graph: bool,
+            /// Only show commits whose author name or email contains this text
+            #[arg(long)]
+            author: Option<String>,
+            /// Only show commits made at or after a date, YYYY-MM-DD, "YYYY-MM-DD HH:MM:SS" (UTC) or seconds since the unix epoch
+            #[arg(long, visible_alias = "after", value_name = "DATE")]
+            since: Option<String>,
+            /// Only show commits made at or before a date, written like --since
+            #[arg(long, visible_alias = "before", value_name = "DATE")]
+            until: Option<String>,
+            /// Stop after this many commits
+            #[arg(short = 'n', long = "max-count", value_name = "NUMBER")]
+            max_count: Option<usize>,
+            /// Print every commit with a template, %H id, %h short id, %an %ae %ad author name, email and date, %cn %ce %cd the same for the committer, %s subject, %b body, %n new line
+            #[arg(long, conflicts_with = "oneline")]
+            format: Option<String>,
+            /// Only show commits that changed these files or folders
+            #[arg(last = true)]
+            paths: Vec<String>,
+        },
+        /// Stage files to be committed
+        Add {
+            /// Files or folders to stage
+            #[arg(required = true)]
+            paths: Vec<String>,
+        },
+        /// Remove files from the staged files
+        Remove {
+            /// Files or folders to unstage
+            #[arg(required = true)]
+            paths: Vec<String>,
+        },
+        /// Record the staged files as a new commit on the current branch
+        Commit {
+            /// Commit message
+            #[arg(short, long)]
+            message: String,
+        },
+        /// Restore the working directory to a commit and detach HEAD
+        Checkout {
+            /// Revision to check out, like HEAD~2, master^2, a tag or an abbreviated id
+            commit: String,
+            /// Discard local changes to files the checkout writes over
+            #[arg(short, long)]
+            force: bool,
+        },
+        /// List, create, delete or rename branches
+        Branch {
+            /// Name of the branch to create, the branches are listed when left out
+            name: Option<String>,
+            /// Revision the new branch starts at, HEAD when left out
+            start: Option<String>,
+            /// Delete a branch that is merged into HEAD
+            #[arg(short, long, value_name = "BRANCH", conflicts_with_all = ["name", "force_delete", "rename"])]
+            delete: Option<String>,
+            /// Delete a branch even if it isn't merged
+            #[arg(short = 'D', value_name = "BRANCH", conflicts_with_all = ["name", "rename"])]
+            force_delete: Option<String>,
+            /// Rename a branch
+            #[arg(short = 'm', long = "move", num_args = 2, value_names = ["OLD", "NEW"], conflicts_with = "name")]
+            rename: Option<Vec<String>>,
+            /// List the remote-tracking branches
+            #[arg(short, long, conflicts_with_all = ["name", "delete", "force_delete", "rename"])]
+            remotes: bool,
+        },
+        /// List, add or remove the remote repositories
+        Remote {
+            /// Name of the remote to add, the remotes are listed when left out
+            name: Option<String>,
+            /// Path or file:// url of the remote to add
+            url: Option<String>,
+            /// Remove a remote and its remote-tracking branches
+            #[arg(short, long, value_name = "REMOTE", conflicts_with = "name")]
+            delete: Option<String>,
+        },
+        /// Copy the commits of a remote that are missing and update its remote-tracking branches
+        Fetch {
+            /// Name of the remote to fetch
+            #[arg(default_value = DEFAULT_REMOTE)]
+            remote: String,
+        },
+        /// Fetch a remote and merge its branch into the current branch
+        Pull {
+            /// Name of the remote to pull from
+            #[arg(default_value = DEFAULT_REMOTE)]
+            remote: String,
+            /// Branch of the remote to merge, the branch with the same name as the current branch when left out
+            branch: Option<String>,
+        },
+        /// Copy the commits of a branch to a remote and move the branch of the same name there
+        Push {
+            /// Name of the remote to push to
+            #[arg(default_value = DEFAULT_REMOTE)]
+            remote: String,
+            /// Branch to push, the current branch when left out
+            branch: Option<String>,
+            /// Move the remote branch even if commits only the remote has are dropped
+            #[arg(short, long)]
+            force: bool,
+            /// Force only while the remote branch is at the expected revision, where the remote-tracking branch is when none is given
+            #[arg(long, value_name = "EXPECTED", num_args = 0..=1, require_equals = true, conflicts_with = "force")]
+            force_with_lease: Option<Option<String>>,
+        },
+        /// List, create or delete tags
+        Tag {
+            /// Name of the tag to create, the tags are listed when left out
+            name: Option<String>,
+            /// Revision the tag points to, HEAD when left out
+            revision: Option<String>,
+            /// Delete a tag
+            #[arg(short, long, value_name = "TAG", conflicts_with = "name")]
+            delete: Option<String>,
+        },
+        /// Switch to a branch
+        Switch {
+            /// Branch to switch to, or revision with --detach
+            target: String,
+            /// Create the branch at HEAD before switching to it
+            #[arg(short, long, conflicts_with = "detach")]
+            create: bool,
+            /// Check out a commit without a branch
+            #[arg(long)]
+            detach: bool,
+            /// Discard local changes to files the switch writes over
+            #[arg(short, long)]
+            force: bool,
+        },
+        /// Explain which .dvcsignore (or exclude) rule decides if a path is ignored
+        #[command(name = "check-ignore")]
+        CheckIgnore {
+            /// Path to check, it doesn't have to exist
+            path: String,
+        },
+        /// Show the files that changed in the repository storage
+        #[command(name = "see-diff", alias = "see_diff")]
+        SeeDiff,
+    }
+
+    /// Runs a parsed command, errors are printed and turned into the exit code (see `user_feedback::exit_code`), 0 if it succeeded
+    ///
+    /// Every command but init, clone and serve works on the repository of the current directory (see `Repository::discover`)
+    pub fn run(cli: Cli) -> u8 {
+        // * -C changes the folder first so the repository and every path argument are found from it
+        if let Some(directory) = &cli.directory {
+            if let Err(e) = env::set_current_dir(directory) {
+                return report(Err(match e.kind() {
+                    io::ErrorKind::NotFound => DvcsError::PathNotFound(PathBuf::from(directory)),
+                    _ => DvcsError::io(directory, e),
+                }));
+            }
+        }
+        match cli.command {
+            Command::Init { path, bare } => report(user_interaction::init_in(path, bare)),
+            Command::Clone {
+                source,
+                destination,
+            } => report(user_interaction::clone_in(source, destination)),
+            Command::Serve { path, listen } => report(user_interaction::serve_in(path, listen)),
+            command => match Repository::discover(".") {
+                Ok(repository) => execute(&repository, command),
+                Err(error) => report(Err(error)),
+            },
+        }
+    }
+
+    /// Private helper function running a command on a repository, a command that writes keeps the repository locked for the whole command and not only while each file is written
+    fn execute(repository: &Repository, command: Command) -> u8 {
+        // * Commands that only read don't wait for (or block) a command that writes
+        let _lock = match command.writes() {
+            true => match repository.lock() {
+                Ok(lock) => Some(lock),
+                Err(error) => return report(Err(error)),
+            },
+            false => None,
+        };
+        let result = match command {
+            // * Handled by `run` without a repository
+            Command::Init { .. } | Command::Clone { .. } | Command::Serve { .. } => unreachable!(),
+            Command::Diff {
+                cached,
+                context,
+                revision,
+                path,
+            } => {
+                let head = match (cached, revision) {
+                    (true, _) => "--cached".to_string(),
+                    (false, revision) => revision.unwrap_or_default(),
+                };
+                user_interaction::diff_in(repository, path, head, context)
+            }
+            Command::Status { path, porcelain } => {
+                user_interaction::status_in(repository, path, porcelain)
+            }
+            Command::Blame { path, revision } => {
+                user_interaction::blame_in(repository, path, revision.unwrap_or_default())
+            }
+            Command::Log {
+                revisions,
+                oneline,
+                graph,
+                author,
+                since,
+                until,
+                max_count,
+                format,
+                paths,
+            } => {
+                log_options(revisions, author, since, until, max_count, paths).and_then(|options| {
+                    let style = match (oneline, format) {
+                        (_, Some(template)) => user_interaction::LogStyle::Format(template),
+                        (true, None) => user_interaction::LogStyle::Oneline,
+                        (false, None) => user_interaction::LogStyle::Full,
+                    };
+                    user_interaction::log_in(repository, options, style, graph)
+                })
+            }
+            // * Every path is tried even if an earlier one failed
+            Command::Add { paths } => return run_each(repository, paths, user_interaction::add_in),
+            Command::Remove { paths } => {
+                return run_each(repository, paths, user_interaction::remove_in)
+            }
+            Command::Commit { message } => user_interaction::commit_in(repository, message),
+            Command::Checkout { commit, force } => {
+                user_interaction::checkout_in(repository, commit, force)
+            }
+            Command::Branch {
+                name,
+                start,
+                delete,
+                force_delete,
+                rename,
+                remotes,
+            } => match (delete, force_delete, rename) {
+                _ if remotes => user_interaction::remote_branches_in(repository),
+                (Some(name), _, _) => user_interaction::delete_branch_in(repository, name, false),
+                (_, Some(name), _) => user_interaction::delete_branch_in(repository, name, true),
+                (_, _, Some(names)) => user_interaction::rename_branch_in(
+                    repository,
+                    names[0].clone(),
+                    names[1].clone(),
+                ),
+                _ => user_interaction::branch_in(
+                    repository,
+                    name.unwrap_or_default(),
+                    start.unwrap_or_default(),
+                ),
+            },
+            Command::Remote { name, url, delete } => match delete {
+                Some(name) => user_interaction::remove_remote_in(repository, name),
+                None => user_interaction::remote_in(
+                    repository,
+                    name.unwrap_or_default(),
+                    url.unwrap_or_default(),
+                ),
+            },
+            Command::Fetch { remote } => user_interaction::fetch_in(repository, remote),
+            Command::Push {
+                remote,
+                branch,
+                force,
+                force_with_lease,
+            } => {
+                let mode = match (force, force_with_lease) {
+                    (_, Some(expected)) => PushMode::ForceWithLease(expected),
+                    (true, None) => PushMode::Force,
+                    (false, None) => PushMode::FastForward,
+                };
+                user_interaction::push_in(repository, remote, branch.unwrap_or_default(), mode)
+            }
+            Command::Pull { remote, branch } => {
+                user_interaction::pull_in(repository, remote, branch)
+            }
+            Command::Tag {
+                name,
+                revision,
+                delete,
+            } => match delete {
+                Some(name) => user_interaction::delete_tag_in(repository, name),
+                None => user_interaction::tag_in(
+                    repository,
+                    name.unwrap_or_default(),
+                    revision.unwrap_or_default(),
+                ),
+            },
+            Command::Switch {
+                target,
+                create,
+                detach,
+                force,
+            } => user_interaction::switch_in(repository, target, create, detach, force),
+            Command::CheckIgnore { path } => user_interaction::check_ignore_in(repository, path),
+            Command::SeeDiff => {
+                user_interaction::see_diff_in(repository, &HashMap::new()).map(|diff| {
+                    let mut paths: Vec<&String> = diff.keys().collect();
+                    paths.sort();
+                    for path in paths {
+                        println!("{} {}", diff[path], path);
+                    }
+                })
+            }
+        };
+        report(result)
+    }
+
+    impl Command {
+        /// Private helper function telling if the command changes the repository, listing branches, tags or remotes only reads it
+        fn writes(&self) -> bool {
+            match self {
+                Command::Diff { .. }
+                | Command::Status { .. }
+                | Command::Blame { .. }
+                | Command::Log { .. }
+                | Command::CheckIgnore { .. }
+                | Command::SeeDiff => false,
+                Command::Branch {
+                    name,
+                    delete,
+                    force_delete,
+                    rename,
+                    remotes,
+                    ..
+                } => {
+                    !*remotes
+                        && (name.is_some()
+                            || delete.is_some()
+                            || force_delete.is_some()
+                            || rename.is_some())
+                }
+                Command::Tag { name, delete, .. } => name.is_some() || delete.is_some(),
+                Command::Remote { name, delete, .. } => name.is_some() || delete.is_some(),
+                _ => true,
+            }
+        }
+    }
 
-            if stager_i.is_err() {
-                let t_er = stager_i.unwrap_err();
-                display_first_error(vec![t_er]);
-                return false;
+    /// Private helper function collecting the filters of the log command, dates that can't be read are rejected
+    fn log_options(
+        revisions: Vec<String>,
+        author: Option<String>,
+        since: Option<String>,
+        until: Option<String>,
+        max_count: Option<usize>,
+        paths: Vec<String>,
+    ) -> Result<LogOptions, DvcsError> {
+        let date = |date: Option<String>| -> Result<Option<u64>, DvcsError> {
+            match date {
+                Some(date) => match parse_date(&date) {
+                    Some(timestamp) => Ok(Some(timestamp)),
+                    None => Err(DvcsError::InvalidArgument(format!(
+                        "'{}' is not a date, use YYYY-MM-DD or \"YYYY-MM-DD HH:MM:SS\"",
+                        date
+                    ))),
+                },
+                None => Ok(None),
             }
-            let add_res = stager_i.unwrap().add(file_path);
-            if add_res.is_ok() {
-                return true;
-            } else {
-                let err = add_res.unwrap_err();
-                display_first_error(vec![err]);
-                return false;
+        };
+        Ok(LogOptions {
+            revisions,
+            author,
+            since: date(since)?,
+            until: date(until)?,
+            paths,
+            max_count,
+            topological: false,
+        })
+    }
+
+    /// Private helper function printing the error of a command and turning it into the exit code
+    fn report(result: Result<(), DvcsError>) -> u8 {
+        match result {
+            Ok(()) => 0,
+            Err(error) => {
+                display_error(&error);
+                exit_code(&error)
             }
         }
-        let test_err = vec!["file path not found".to_string()];
-        display_first_error(test_err);
-        return false;
     }
 
-    pub fn see_diff_in(snapshot: &HashMap<String, String>) -> (HashMap<String, String>, bool) {
-        //new instance of RepositoryStorage
-        let repo = RepositoryStorage::new();
-        (repo.see_diff(snapshot), true)
+    /// Private helper function running a command on every path, every error is printed and the first one gives the exit code
+    fn run_each(
+        repository: &Repository,
+        paths: Vec<String>,
+        command: fn(&Repository, String) -> Result<(), DvcsError>,
+    ) -> u8 {
+        paths
+            .into_iter()
+            .fold(0, |code, path| match command(repository, path) {
+                Ok(()) => code,
+                Err(error) => {
+                    display_error(&error);
+                    if code == 0 {
+                        exit_code(&error)
+                    } else {
+                        code
+                    }
+                }
+            })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_parse_commands() {
+            let cli = Cli::try_parse_from(["dvcs", "commit", "-m", "message with spaces"]).unwrap();
+            assert!(
+                matches!(cli.command, Command::Commit { message } if message == "message with spaces")
+            );
+
+            let cli =
+                Cli::try_parse_from(["dvcs", "diff", "-U", "1", "a..b", "--", "my dir"]).unwrap();
+            assert!(matches!(
+                cli.command,
+                Command::Diff { cached: false, context: 1, revision: Some(revision), path }
+                    if revision == "a..b" && path == "my dir"
+            ));
+
+            let cli = Cli::try_parse_from(["dvcs", "branch", "-m", "old", "new"]).unwrap();
+            assert!(
+                matches!(cli.command, Command::Branch { rename: Some(names), .. } if names == ["old", "new"])
+            );
+
+            // missing or conflicting arguments are rejected before anything runs
+            assert!(Cli::try_parse_from(["dvcs", "commit"]).is_err());
+            assert!(Cli::try_parse_from(["dvcs", "add"]).is_err());
+            assert!(Cli::try_parse_from(["dvcs", "switch", "-c", "--detach", "x"]).is_err());
+            assert!(Cli::try_parse_from(["dvcs", "unknown"]).is_err());
+        }
+
+        #[test]
+        fn test_parse_check_ignore() {
+            let cli = Cli::try_parse_from(["dvcs", "check-ignore", "target/debug"]).unwrap();
+            assert!(matches!(cli.command, Command::CheckIgnore { path } if path == "target/debug"));
+            assert!(Cli::try_parse_from(["dvcs", "check-ignore"]).is_err());
+        }
+
+        #[test]
+        fn test_parse_status() {
+            let cli = Cli::try_parse_from(["dvcs", "status", "--porcelain"]).unwrap();
+            assert!(
+                matches!(cli.command, Command::Status { path, porcelain } if path == "." && porcelain)
+            );
+            let cli = Cli::try_parse_from(["dvcs", "status", "src"]).unwrap();
+            assert!(
+                matches!(cli.command, Command::Status { path, porcelain } if path == "src" && !porcelain)
+            );
+        }
+
+        #[test]
+        fn test_run_fails_outside_repository() {
+            let cli = Cli::try_parse_from(["dvcs", "commit", "-m", "message"]).unwrap();
+            assert_eq!(run(cli), 3);
+        }
+
+        #[test]
+        fn test_parse_blame() {
+            let cli = Cli::try_parse_from(["dvcs", "blame", "src/lib.rs", "master"]).unwrap();
+            assert!(matches!(
+                cli.command,
+                Command::Blame { path, revision: Some(revision) } if path == "src/lib.rs" && revision == "master"
+            ));
+            let cli = Cli::try_parse_from(["dvcs", "blame", "a.txt"]).unwrap();
+            assert!(matches!(cli.command, Command::Blame { revision: None, .. }));
+            assert!(Cli::try_parse_from(["dvcs", "blame"]).is_err());
+        }
+
+        #[test]
+        fn test_parse_log() {
+            let cli = Cli::try_parse_from([
+                "dvcs",
+                "log",
+                "--oneline",
+                "-n",
+                "3",
+                "master",
+                "topic",
+                "--",
+                "src",
+            ])
+            .unwrap();
+            assert!(matches!(
+                cli.command,
+                Command::Log { oneline: true, max_count: Some(3), revisions, paths, .. }
+                    if revisions == ["master", "topic"] && paths == ["src"]
+            ));
+            assert!(Cli::try_parse_from(["dvcs", "log", "--oneline", "--format", "%h"]).is_err());
+            assert!(log_options(
+                vec![],
+                None,
+                Some("2024-01-31".to_string()),
+                None,
+                None,
+                vec![]
+            )
+            .is_ok_and(|options| options.since == Some(1_706_659_200)));
+            assert!(matches!(
+                log_options(
+                    vec![],
+                    None,
+                    None,
+                    Some("tomorrow".to_string()),
+                    None,
+                    vec![]
+                ),
+                Err(DvcsError::InvalidArgument(_))
+            ));
+        }
+
+        #[test]
+        fn test_parse_tag() {
+            let cli = Cli::try_parse_from(["dvcs", "tag", "v1", "HEAD~2"]).unwrap();
+            assert!(matches!(
+                cli.command,
+                Command::Tag { name: Some(name), revision: Some(revision), delete: None }
+                    if name == "v1" && revision == "HEAD~2"
+            ));
+            assert!(Cli::try_parse_from(["dvcs", "tag", "-d", "v1", "v2"]).is_err());
+        }
+
+        #[test]
+        fn test_parse_init_bare() {
+            let cli = Cli::try_parse_from(["dvcs", "init", "--bare", "hub"]).unwrap();
+            assert!(matches!(cli.command, Command::Init { path, bare: true } if path == "hub"));
+            let cli = Cli::try_parse_from(["dvcs", "init"]).unwrap();
+            assert!(matches!(cli.command, Command::Init { bare: false, .. }));
+        }
+
+        #[test]
+        fn test_parse_clone() {
+            let cli = Cli::try_parse_from(["dvcs", "clone", "file:///tmp/a", "b"]).unwrap();
+            assert!(matches!(
+                cli.command,
+                Command::Clone { source, destination: Some(destination) }
+                    if source == "file:///tmp/a" && destination == "b"
+            ));
+            assert!(Cli::try_parse_from(["dvcs", "clone"]).is_err());
+        }
+
+        #[test]
+        fn test_parse_serve() {
+            let cli = Cli::try_parse_from(["dvcs", "serve", "repos", "--listen", "0.0.0.0:7000"])
+                .unwrap();
+            assert!(matches!(
+                cli.command,
+                Command::Serve { path, listen: Some(listen) } if path == "repos" && listen == "0.0.0.0:7000"
+            ));
+            let cli = Cli::try_parse_from(["dvcs", "serve"]).unwrap();
+            assert!(matches!(cli.command, Command::Serve { path, listen: None } if path == "."));
+        }
+
+        #[test]
+        fn test_parse_remotes() {
+            let cli = Cli::try_parse_from(["dvcs", "remote", "upstream", "../up"]).unwrap();
+            assert!(matches!(
+                cli.command,
+                Command::Remote { name: Some(name), url: Some(url), delete: None }
+                    if name == "upstream" && url == "../up"
+            ));
+            assert!(Cli::try_parse_from(["dvcs", "remote", "-d", "a", "b"]).is_err());
+            let cli = Cli::try_parse_from(["dvcs", "fetch"]).unwrap();
+            assert!(matches!(cli.command, Command::Fetch { remote } if remote == "origin"));
+            let cli = Cli::try_parse_from(["dvcs", "pull", "upstream", "topic"]).unwrap();
+            assert!(matches!(
+                cli.command,
+                Command::Pull { remote, branch: Some(branch) } if remote == "upstream" && branch == "topic"
+            ));
+        }
+
+        #[test]
+        fn test_parse_push() {
+            let cli = Cli::try_parse_from(["dvcs", "push", "origin", "topic", "-f"]).unwrap();
+            assert!(matches!(
+                cli.command,
+                Command::Push { branch: Some(branch), force: true, force_with_lease: None, .. }
+                    if branch == "topic"
+            ));
+            let cli = Cli::try_parse_from(["dvcs", "push", "--force-with-lease"]).unwrap();
+            assert!(matches!(
+                cli.command,
+                Command::Push {
+                    force_with_lease: Some(None),
+                    ..
+                }
+            ));
+            let cli =
+                Cli::try_parse_from(["dvcs", "push", "--force-with-lease=abc1234", "up"]).unwrap();
+            assert!(matches!(
+                cli.command,
+                Command::Push { remote, force_with_lease: Some(Some(expected)), .. }
+                    if remote == "up" && expected == "abc1234"
+            ));
+            assert!(Cli::try_parse_from(["dvcs", "push", "-f", "--force-with-lease"]).is_err());
+        }
+
+        #[test]
+        fn test_parse_directory() {
+            let cli = Cli::try_parse_from(["dvcs", "-C", "my dir", "status"]).unwrap();
+            assert_eq!(cli.directory.as_deref(), Some("my dir"));
+            // * -C can also be given after the command
+            let cli = Cli::try_parse_from(["dvcs", "add", "a.txt", "-C", "sub"]).unwrap();
+            assert_eq!(cli.directory.as_deref(), Some("sub"));
+            assert!(Cli::try_parse_from(["dvcs", "status"])
+                .unwrap()
+                .directory
+                .is_none());
+        }
+
+        #[test]
+        fn test_execute_each_path() {
+            let root = std::env::temp_dir().join("dvcs_usemods_execute_each_path");
+            let _ = std::fs::remove_dir_all(&root);
+            std::fs::create_dir_all(&root).unwrap();
+            let root = root.display().to_string();
+            user_interaction::init_in(root.clone(), false).unwrap();
+            std::fs::write(format!("{}/a.txt", root), "a").unwrap();
+            let repository = Repository::discover(&root).unwrap();
+
+            // * Every path is tried, the first error gives the exit code
+            let command = Command::Add {
+                paths: vec![format!("{}/missing", root), format!("{}/a.txt", root)],
+            };
+            assert_eq!(execute(&repository, command), 4);
+            let report = repository.stager().unwrap().status(root.clone()).unwrap();
+            assert!(report.to_porcelain().contains("A  a.txt"));
+        }
+
+        #[test]
+        // * Staging in a bare repository fails the same way whether the path exists or not
+        fn test_add_in_bare_repository() {
+            let root = std::env::temp_dir().join("dvcs_usemods_add_in_bare_repository");
+            let _ = std::fs::remove_dir_all(&root);
+            std::fs::create_dir_all(&root).unwrap();
+            let root = root.display().to_string();
+            user_interaction::init_in(root.clone(), true).unwrap();
+            let repository = Repository::discover(&root).unwrap();
+            for path in [root.clone(), format!("{}/missing", root)] {
+                assert!(matches!(
+                    user_interaction::add_in(&repository, path.clone()),
+                    Err(DvcsError::BareRepository(_))
+                ));
+                assert!(matches!(
+                    user_interaction::remove_in(&repository, path),
+                    Err(DvcsError::BareRepository(_))
+                ));
+            }
+        }
+
+        #[test]
+        // * Only commands that write wait for the lock, listing branches or tags only reads
+        fn test_commands_that_write() {
+            let parse = |args: &[&str]| {
+                Cli::try_parse_from([&["dvcs"], args].concat())
+                    .unwrap()
+                    .command
+            };
+            for args in [
+                &["status"][..],
+                &["log"],
+                &["diff"],
+                &["branch"],
+                &["branch", "-r"],
+                &["tag"],
+                &["remote"],
+            ] {
+                assert!(!parse(args).writes(), "{:?}", args);
+            }
+            for args in [
+                &["add", "a.txt"][..],
+                &["commit", "-m", "message"],
+                &["branch", "topic"],
+                &["branch", "-d", "topic"],
+                &["tag", "v1"],
+                &["remote", "origin", "url"],
+                &["fetch"],
+            ] {
+                assert!(parse(args).writes(), "{:?}", args);
+            }
+        }
     }
 }
 
+#[cfg(test)]
 mod err_handling_tests {
     use crate::user_feedback::*;
 
@@ -438,96 +1431,138 @@ mod err_handling_tests {
     fn test_display_first_error() {
         let test_err = vec!["test error on first err fn".to_string()];
         let res = display_first_error(test_err);
-        assert_eq!(res, true);
+        assert!(res);
     }
     #[test]
     fn test_display_all_errors() {
         let test_err = vec!["test error on display all err fn".to_string()];
         let res = display_all_errors(test_err);
-        assert_eq!(res, true);
+        assert!(res);
     }
     #[test]
     fn test_format_error_alt() {
-        print!("Press any key to continue...\n");
+        println!("Press any key to continue...");
         let test_err = vec!["test error on format err fn".to_string()];
         let res = format_error_alt(test_err);
-        assert_eq!(res, true);
+        assert!(res);
+    }
+    #[test]
+    fn test_exit_code() {
+        use dvcs_error::DvcsError;
+        use std::path::PathBuf;
+
+        let errors = [
+            DvcsError::NothingToCommit,
+            DvcsError::InvalidArgument("bad".to_string()),
+            DvcsError::NotARepository(PathBuf::from(".")),
+            DvcsError::PathNotFound(PathBuf::from("missing")),
+            DvcsError::Conflict {
+                operation: "checkout".to_string(),
+                paths: vec!["a.txt".to_string()],
+            },
+            DvcsError::RevisionNotFound("abc".to_string()),
+            DvcsError::IndexCorrupt("eof".to_string()),
+            DvcsError::io("a.txt", std::io::ErrorKind::PermissionDenied.into()),
+            DvcsError::RepositoryLocked {
+                pid: Some(42),
+                path: PathBuf::from(".dvcs_hidden/dvcs.lock"),
+            },
+        ];
+        // every kind of error has its own code, and none of them is success
+        let codes: Vec<u8> = errors.iter().map(exit_code).collect();
+        assert_eq!(codes, vec![1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        errors.iter().for_each(display_error);
     }
 }
 
+#[cfg(test)]
 mod user_interaction_tests {
+    use dvcs_error::DvcsError;
     use std::fs::File;
 
+    use crate::repository::Repository;
     use crate::user_interaction::add_in;
+    use crate::user_interaction::commit_in;
     use crate::user_interaction::diff_in;
     use crate::user_interaction::init_in;
-    use crate::user_interaction::loop_find;
     use crate::user_interaction::remove_in;
     use crate::user_interaction::status_in;
+    use crate::user_interaction::switch_in;
+
+    /// Creates the ./repo repository the tests share and opens it
+    fn test_repository() -> Repository {
+        std::fs::create_dir_all("./repo").unwrap();
+        std::fs::create_dir_all("./repo/.dvcs_hidden").unwrap();
+        File::create("./repo/text.txt").unwrap();
+        Repository::discover("./repo").unwrap()
+    }
 
     #[test]
-    fn find_hidden_dvcs_folder_test() {
-        println!("TEST: {:?}", loop_find("./".to_string()));
+    fn discover_test() {
+        let repository = test_repository();
+        assert!(repository.working_directory().unwrap().ends_with("repo"));
+        assert_eq!(Repository::discover("./repo/text.txt").unwrap(), repository);
     }
 
-    static mut TEST_PATH: &str = "/tmp/dvcs_test/";
     #[test]
     fn init_test_succ() {
         //create a valid path
-        std::fs::create_dir_all("./repo");
-        std::fs::create_dir_all("./repo/.dvcs_hidden");
-        File::create("./repo/text.txt".to_string());
+        test_repository();
         let file_path = String::from("./repo");
-        let res = init_in(file_path.to_string());
-        assert_eq!(res, true);
+        let res = init_in(file_path.to_string(), false);
+        assert!(res.is_ok());
     }
     #[test]
     fn init_test_fail() {
         //create an invalid path
-        std::fs::create_dir_all("./repo");
-        std::fs::create_dir_all("./repo/.dvcs_hidden");
-        File::create("./repo/text.txt".to_string());
+        test_repository();
         let file_path = String::from("./dewdedewedwdeweddwe");
-        let res = init_in(file_path);
-        assert_eq!(res, false);
+        let res = init_in(file_path, false);
+        assert!(res.is_err());
     }
     #[test]
 
     fn diff_in_test() {
-        // std::fs::create_dir_all("./repo");
-        // std::fs::create_dir_all("./repo/.dvcs_hidden");
-        // File::create("./repo/text.txt".to_string());
-        // let file_path = String::from("./dewdedewedwdeweddwe");
-        // let res = diff_in(file_path.to_string(), "HEAD".to_string());
-        // assert_eq!(res, true);
+        let repository = test_repository();
+        let file_path = String::from("./dewdedewedwdeweddwe");
+        let res = diff_in(&repository, file_path, String::new(), 3);
+        assert!(matches!(res, Err(DvcsError::PathNotFound(_))));
     }
     #[test]
     fn status_in_test() {
-        std::fs::create_dir_all("./repo");
-        std::fs::create_dir_all("./repo/.dvcs_hidden");
-        File::create("./repo/text.txt".to_string());
+        let repository = test_repository();
         let file_path = String::from("./repo");
-        let res = status_in(file_path.to_string());
-        assert_eq!(res, true);
+        let res = status_in(&repository, file_path.to_string(), false);
+        assert!(res.is_ok());
     }
 
     #[test]
     fn remove_in_test() {
-        std::fs::create_dir_all("./repo");
-        std::fs::create_dir_all("./repo/.dvcs_hidden");
-        File::create("./repo/text.txt".to_string());
+        let repository = test_repository();
         let file_path = String::from("./repo");
-        let res = add_in(file_path.clone().to_string());
-        let res = remove_in(file_path.clone().to_string());
-        assert_eq!(res, true);
+        add_in(&repository, file_path.clone().to_string()).unwrap();
+        let res = remove_in(&repository, file_path.clone().to_string());
+        assert!(res.is_ok());
+    }
+    #[test]
+    fn commit_in_test() {
+        // a message is needed
+        let repository = test_repository();
+        let res = commit_in(&repository, String::from(" "));
+        assert!(res.is_err());
+    }
+    #[test]
+    fn switch_in_test() {
+        // nothing to switch to
+        let repository = test_repository();
+        let res = switch_in(&repository, String::new(), false, false, false);
+        assert!(res.is_err());
     }
     #[test]
     fn add_in_test() {
-        std::fs::create_dir_all("./repo");
-        std::fs::create_dir_all("./repo/.dvcs_hidden");
-        File::create("./repo/text.txt".to_string());
+        let repository = test_repository();
         let file_path = String::from("./repo");
-        let res = add_in(file_path.clone().to_string());
-        assert_eq!(res, true);
+        let res = add_in(&repository, file_path.clone().to_string());
+        assert!(res.is_ok());
     }
 }
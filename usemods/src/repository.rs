@@ -0,0 +1,332 @@
+use dvcs_error::DvcsError;
+use repo::{is_bare_repository, RepositoryController};
+use stager::stager::Stager;
+use staging::staging_storage::Staging;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use storage_hiding::repository_lock::RepositoryLock;
+use storage_hiding::repository_storage::RepositoryStorage;
+
+/// Name of the hidden folder that holds the repository information
+pub const DVCS_HIDDEN: &str = ".dvcs_hidden";
+
+/// Environment variable naming the DVCS hidden folder to use instead of looking for one
+pub const DVCS_DIR: &str = "DVCS_DIR";
+
+/// Environment variable with folders (separated like `PATH`) the lookup doesn't go up into
+pub const DVCS_CEILING_DIRECTORIES: &str = "DVCS_CEILING_DIRECTORIES";
+
+/// Environment variable that lets the lookup cross into another file system when set to `1` or `true`
+pub const DVCS_DISCOVERY_ACROSS_FILESYSTEM: &str = "DVCS_DISCOVERY_ACROSS_FILESYSTEM";
+
+/// Repository found for a command, shared by every part of the command that needs it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repository {
+    dvcs_hidden: String,
+    /// None for a bare repository
+    working_directory: Option<String>,
+}
+
+impl Repository {
+    /// Finds the repository of a path with the settings from the environment, see `Discovery`
+    pub fn discover(start: &str) -> Result<Repository, DvcsError> {
+        Discovery::from_env().find(start)
+    }
+
+    /// Gets the path to the DVCS hidden folder
+    pub fn dvcs_hidden(&self) -> &str {
+        &self.dvcs_hidden
+    }
+
+    /// Gets the path to the working directory, None for a bare repository
+    pub fn working_directory(&self) -> Option<&str> {
+        self.working_directory.as_deref()
+    }
+
+    /// True for a repository without a working directory
+    pub fn is_bare(&self) -> bool {
+        self.working_directory.is_none()
+    }
+
+    /// Opens the stager of the repository, bare repositories don't have one
+    pub fn stager(&self) -> Result<Stager, DvcsError> {
+        Stager::new(&self.dvcs_hidden, self.required_working_directory()?)
+    }
+
+    /// Opens the staging index of the repository, bare repositories don't have one
+    pub fn staging(&self) -> Result<Staging, DvcsError> {
+        Staging::new(&self.dvcs_hidden, self.required_working_directory()?)
+    }
+
+    /// Opens the branches and history of the repository from its DVCS hidden folder, whatever the folder is named
+    pub fn controller(&self) -> Result<RepositoryController, DvcsError> {
+        RepositoryController::open_hidden(&self.dvcs_hidden, self.working_directory())
+    }
+
+    /// Turns a path given to a command into the `/` separated path of the file in the commits (index key)
+    ///
+    /// Paths are relative to the current folder in a working directory, a bare repository has no files on disk so they are taken as they are
+    pub fn repository_path(&self, file_path: &str) -> Result<String, DvcsError> {
+        if !self.is_bare() {
+            return self.staging()?.index_key(file_path);
+        }
+        let path = file_path
+            .split(['/', '\\'])
+            .filter(|part| !part.is_empty() && *part != ".")
+            .collect::<Vec<&str>>()
+            .join("/");
+        Ok(if path.is_empty() {
+            ".".to_string()
+        } else {
+            path
+        })
+    }
+
+    /// Opens the object database of the repository
+    pub fn storage(&self) -> Result<RepositoryStorage, DvcsError> {
+        RepositoryStorage::new(&self.dvcs_hidden)
+    }
+
+    /// Locks the repository until the lock is dropped
+    pub fn lock(&self) -> Result<RepositoryLock, DvcsError> {
+        RepositoryLock::acquire(&self.dvcs_hidden)
+    }
+
+    /// Private helper function failing with `BareRepository` when there is no working directory
+    fn required_working_directory(&self) -> Result<&str, DvcsError> {
+        self.working_directory()
+            .ok_or_else(|| DvcsError::BareRepository(PathBuf::from(&self.dvcs_hidden)))
+    }
+
+    /// Private helper function for a DVCS hidden folder, its parent is the working directory, or for the folder of a bare repository
+    fn from_dvcs_hidden(dvcs_hidden: &Path) -> Repository {
+        let working_directory = match is_bare_repository(dvcs_hidden) {
+            true => None,
+            false => Some(dvcs_hidden.parent().unwrap_or(dvcs_hidden)),
+        };
+        Repository {
+            dvcs_hidden: dvcs_hidden.to_string_lossy().to_string(),
+            working_directory: working_directory.map(|path| path.to_string_lossy().to_string()),
+        }
+    }
+}
+
+/// Settings for finding the repository of a path
+#[derive(Debug, Clone, Default)]
+pub struct Discovery {
+    /// DVCS hidden folder to use instead of looking for one (`DVCS_DIR`)
+    pub dvcs_dir: Option<PathBuf>,
+    /// Folders the lookup doesn't go up into, the start folder itself can still be one (`DVCS_CEILING_DIRECTORIES`)
+    pub ceiling_directories: Vec<PathBuf>,
+    /// Keeps looking in parent folders on another file system (`DVCS_DISCOVERY_ACROSS_FILESYSTEM`)
+    pub across_filesystems: bool,
+}
+
+impl Discovery {
+    /// Reads the settings from the environment, empty variables are ignored
+    pub fn from_env() -> Discovery {
+        Discovery {
+            dvcs_dir: env::var_os(DVCS_DIR)
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from),
+            ceiling_directories: env::var_os(DVCS_CEILING_DIRECTORIES)
+                .map(|paths| env::split_paths(&paths).collect())
+                .unwrap_or_default(),
+            across_filesystems: env::var(DVCS_DISCOVERY_ACROSS_FILESYSTEM)
+                .is_ok_and(|value| value == "1" || value.eq_ignore_ascii_case("true")),
+        }
+    }
+
+    /// Finds the closest DVCS hidden folder (or bare repository) from a path up, the path doesn't have to exist
+    ///
+    /// The lookup stops before a ceiling directory and, unless `across_filesystems` is set, before a folder on another file system
+    pub fn find(&self, start: &str) -> Result<Repository, DvcsError> {
+        if let Some(dvcs_dir) = &self.dvcs_dir {
+            return match fs::canonicalize(dvcs_dir) {
+                Ok(dvcs_hidden) if dvcs_hidden.is_dir() => {
+                    Ok(Repository::from_dvcs_hidden(&dvcs_hidden))
+                }
+                _ => Err(DvcsError::NotARepository(dvcs_dir.clone())),
+            };
+        }
+
+        // * Paths that don't exist (anymore) are looked up from their closest existing folder
+        let mut existing = Path::new(start);
+        while !existing.exists() && existing != Path::new(".") {
+            existing = match existing.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+        }
+        let mut folder = fs::canonicalize(existing).map_err(|e| DvcsError::io(existing, e))?;
+        if folder.is_file() {
+            folder.pop();
+        }
+
+        // * Ceilings that don't exist can't be walked into anyway
+        let ceilings: Vec<PathBuf> = self
+            .ceiling_directories
+            .iter()
+            .filter_map(|ceiling| fs::canonicalize(ceiling).ok())
+            .collect();
+        let start_device = device(&folder);
+        for current in folder.ancestors() {
+            if current != folder && ceilings.iter().any(|ceiling| ceiling == current) {
+                break;
+            }
+            if !self.across_filesystems && device(current) != start_device {
+                break;
+            }
+            let dvcs_hidden = current.join(DVCS_HIDDEN);
+            if dvcs_hidden.is_dir() {
+                return Ok(Repository::from_dvcs_hidden(&dvcs_hidden));
+            }
+            if is_bare_repository(current) {
+                return Ok(Repository::from_dvcs_hidden(current));
+            }
+        }
+        Err(DvcsError::NotARepository(PathBuf::from(start)))
+    }
+}
+
+/// Private helper function getting the file system a folder is on
+#[cfg(unix)]
+fn device(folder: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(folder).ok().map(|metadata| metadata.dev())
+}
+
+/// Private helper function getting the file system a folder is on, without device ids every folder counts as the same one
+#[cfg(not(unix))]
+fn device(_folder: &Path) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_folders(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("dvcs_usemods_{}", name));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("project").join(DVCS_HIDDEN)).unwrap();
+        fs::create_dir_all(root.join("project").join("src").join("deep")).unwrap();
+        fs::write(root.join("project").join("src").join("lib.rs"), "").unwrap();
+        fs::canonicalize(root).unwrap()
+    }
+
+    #[test]
+    // * The closest hidden folder is found from folders, files and paths that don't exist
+    fn test_find_from_any_path() {
+        let root = temp_folders("find_from_any_path");
+        let project = root.join("project");
+        let expected = Repository {
+            dvcs_hidden: project.join(DVCS_HIDDEN).display().to_string(),
+            working_directory: Some(project.display().to_string()),
+        };
+        let discovery = Discovery::default();
+        for start in [
+            project.clone(),
+            project.join("src").join("deep"),
+            project.join("src").join("lib.rs"),
+            project.join("src").join("deleted").join("file.txt"),
+        ] {
+            assert_eq!(discovery.find(start.to_str().unwrap()).unwrap(), expected);
+        }
+        assert!(matches!(
+            discovery.find(root.to_str().unwrap()),
+            Err(DvcsError::NotARepository(_))
+        ));
+    }
+
+    #[test]
+    // * A ceiling stops the lookup above it and DVCS_DIR skips the lookup
+    fn test_ceiling_and_dvcs_dir() {
+        let root = temp_folders("ceiling_and_dvcs_dir");
+        let project = root.join("project");
+        let deep = project.join("src").join("deep");
+        let ceiling = Discovery {
+            ceiling_directories: vec![project.join("src")],
+            ..Default::default()
+        };
+        assert!(ceiling.find(deep.to_str().unwrap()).is_err());
+        assert!(ceiling.find(project.to_str().unwrap()).is_ok());
+
+        let dvcs_dir = Discovery {
+            dvcs_dir: Some(project.join(DVCS_HIDDEN)),
+            ..Default::default()
+        };
+        let repository = dvcs_dir.find(root.to_str().unwrap()).unwrap();
+        assert_eq!(repository.working_directory(), project.to_str());
+        let missing = Discovery {
+            dvcs_dir: Some(root.join("missing")),
+            ..Default::default()
+        };
+        assert!(matches!(
+            missing.find(project.to_str().unwrap()),
+            Err(DvcsError::NotARepository(_))
+        ));
+    }
+
+    #[test]
+    // * DVCS_DIR can name a hidden folder that isn't called .dvcs_hidden, it is opened without creating the default one
+    fn test_dvcs_dir_with_another_name() {
+        let root = temp_folders("dvcs_dir_with_another_name");
+        let project = root.join("project");
+        let mut controller = RepositoryController::new(project.to_str().unwrap()).unwrap();
+        let hidden = project.join(DVCS_HIDDEN).display().to_string();
+        let mut staging = Staging::new(&hidden, project.to_str().unwrap()).unwrap();
+        fs::write(project.join("a.txt"), "a").unwrap();
+        staging.stage_path(&staging.working_path("a.txt")).unwrap();
+        let commit = controller.commit_staged(&mut staging, "First").unwrap();
+        fs::rename(project.join(DVCS_HIDDEN), project.join("custom")).unwrap();
+
+        let discovery = Discovery {
+            dvcs_dir: Some(project.join("custom")),
+            ..Default::default()
+        };
+        let repository = discovery.find(root.to_str().unwrap()).unwrap();
+        assert_eq!(repository.working_directory(), project.to_str());
+        let controller = repository.controller().unwrap();
+        assert_eq!(controller.head_commit(), Some(commit.clone()));
+        assert_eq!(controller.resolve_revision("HEAD").unwrap(), commit);
+        assert!(!project.join(DVCS_HIDDEN).exists());
+
+        // * A hidden folder without a repo file is an empty repository, still nothing is created
+        fs::create_dir_all(root.join("empty")).unwrap();
+        let empty = RepositoryController::open_hidden(
+            root.join("empty").to_str().unwrap(),
+            Some(root.to_str().unwrap()),
+        )
+        .unwrap();
+        assert!(empty.list_branches().is_empty());
+        assert!(!root.join("empty/repo.json").exists());
+    }
+
+    #[test]
+    // * A bare repository is found from its folder and anything inside it, and has no staging index
+    fn test_find_bare() {
+        let root = temp_folders("find_bare");
+        let bare = root.join("hub");
+        fs::create_dir_all(&bare).unwrap();
+        RepositoryController::init_bare(bare.to_str().unwrap()).unwrap();
+        let discovery = Discovery::default();
+        for start in [bare.clone(), bare.join("objects")] {
+            let repository = discovery.find(start.to_str().unwrap()).unwrap();
+            assert!(repository.is_bare());
+            assert_eq!(repository.dvcs_hidden(), bare.to_str().unwrap());
+        }
+        let repository = discovery.find(bare.to_str().unwrap()).unwrap();
+        assert!(matches!(
+            repository.staging(),
+            Err(DvcsError::BareRepository(_))
+        ));
+        assert!(!bare.join(staging::index_file::INDEX_FILE_NAME).exists());
+        assert!(repository.controller().unwrap().is_bare());
+        assert_eq!(
+            repository.repository_path("./src//a.txt").unwrap(),
+            "src/a.txt"
+        );
+    }
+}